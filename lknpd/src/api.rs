@@ -1,6 +1,12 @@
 mod authenticator;
 mod client;
 mod models;
+mod redact;
+mod settings;
+mod trace;
 
 pub use authenticator::PhoneAuthenticator;
-pub use client::AuthorizedClient;
+pub use client::{AuthorizedClient, CancelReason, Receipt, RequestError};
+pub use models::Income;
+pub use settings::HttpSettings;
+pub use trace::enable_trace;
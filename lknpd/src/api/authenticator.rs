@@ -5,6 +5,7 @@ use super::{
     models::{
         DeviceInfo, SMSChallengeRequest, SMSChallengeResponse, SMSVerifyRequest, TokenResponse,
     },
+    settings::HttpSettings,
 };
 
 /// Аутентификатор по номеру телефона.
@@ -14,13 +15,15 @@ use super::{
 //  2. Код верификации передать на https://lknpd.nalog.ru/api/v1/auth/challenge/sms/verify
 pub struct PhoneAuthenticator {
     client: InnerClient,
+    settings: HttpSettings,
     phone: String,
 }
 
 impl PhoneAuthenticator {
-    pub fn new(phone: String) -> anyhow::Result<Self> {
+    pub fn new(phone: String, settings: &HttpSettings) -> anyhow::Result<Self> {
         Ok(Self {
-            client: InnerClient::new()?,
+            client: InnerClient::new(settings)?,
+            settings: settings.clone(),
             phone,
         })
     }
@@ -50,7 +53,7 @@ impl PhoneAuthenticator {
 
         let payload = SMSVerifyRequest {
             challenge_token,
-            device_info: DeviceInfo::new(&device_id),
+            device_info: DeviceInfo::new(&device_id, &self.settings),
             code,
             phone: self.phone.clone(),
         };
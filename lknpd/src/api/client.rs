@@ -1,34 +1,35 @@
-use std::time::Duration;
+use std::time::Instant;
 
 use crate::{
-    api::models::{IncomeRequest, IncomeResponse},
+    api::models::{CancelRequest, Income, IncomeRequest, IncomeResponse, IncomesResponse},
     model::{AccessToken, Check, RefreshToken, TokenNewError},
 };
-use log::debug;
-use reqwest::{header::AUTHORIZATION, Method, StatusCode};
+use chrono::{DateTime, FixedOffset, Local};
+use log::{debug, trace};
+use reqwest::{
+    header::{HeaderMap, AUTHORIZATION, CONTENT_TYPE},
+    Method, StatusCode,
+};
 use serde::{de::DeserializeOwned, Serialize};
 
-use super::models::{DeviceInfo, TaxPayer, TokenRefreshRequest, TokenResponse};
+use super::{
+    models::{DeviceInfo, TaxPayer, TokenRefreshRequest, TokenResponse},
+    redact, trace, HttpSettings,
+};
 
 /// Внутренний клиент.
 pub(super) struct InnerClient {
     client: reqwest::blocking::Client,
+    base_url: String,
 }
 
-const BASE_URL: &str = "https://lknpd.nalog.ru";
-pub(super) const USER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/122.0.0.0 Safari/537.36";
-
 impl InnerClient {
     /// Создаёт новый инстанс внутреннего клиента.
-    pub(super) fn new() -> anyhow::Result<Self> {
-        let client = reqwest::blocking::Client::builder()
-            .user_agent(USER_AGENT)
-            .timeout(Duration::from_secs(5))
-            .connect_timeout(Duration::from_secs(5))
-            .pool_max_idle_per_host(10)
-            .build()?;
-
-        Ok(Self { client })
+    pub(super) fn new(settings: &HttpSettings) -> anyhow::Result<Self> {
+        Ok(Self {
+            client: settings.client()?,
+            base_url: settings.base_url.trim_end_matches('/').to_owned(),
+        })
     }
 
     pub(super) fn get<R: DeserializeOwned>(
@@ -55,49 +56,120 @@ impl InnerClient {
         payload: Option<&B>,
         access_token: Option<&AccessToken>,
     ) -> RequestResult<R> {
-        let url = Self::build_url(api_method);
+        let url = self.build_url(api_method);
         let mut req_builder = self.client.request(http_method.clone(), &url);
 
-        if let Some(b) = payload {
-            req_builder = req_builder.json(b)
+        let body = payload
+            .map(serde_json::to_string)
+            .transpose()
+            .map_err(RequestError::EncodeRequestBody)?;
+
+        if let Some(b) = &body {
+            req_builder = req_builder
+                .header(CONTENT_TYPE, "application/json")
+                .body(b.clone())
         }
 
         if let Some(t) = access_token {
             req_builder = req_builder.header(AUTHORIZATION, format!("Bearer {}", t.value.clone()))
         }
 
-        debug!(
-            "Запрос в АПИ: {} {} с токеном {:?}",
-            http_method, url, access_token
-        );
-        let resp = req_builder.send()?;
+        let req = req_builder.build()?;
+
+        // В логи и файл трассировки попадают только данные без секретов.
+        let mut entry = trace::Entry {
+            started_date_time: Local::now(),
+            time: 0,
+            request: trace::Request {
+                method: http_method.to_string(),
+                url: url.clone(),
+                headers: trace_headers(req.headers()),
+                post_data: body.map(|b| trace::Content {
+                    mime_type: "application/json".to_owned(),
+                    text: redact::body(&b),
+                }),
+            },
+            response: trace::Response::default(),
+            error: None,
+        };
+
+        debug!("Запрос в АПИ: {} {}", http_method, url);
+        if let Some(data) = &entry.request.post_data {
+            trace!("Тело запроса на {}: {}", url, data.text);
+        }
+
+        let started = Instant::now();
+        let result = self.client.execute(req).and_then(|resp| {
+            let status = resp.status();
+            let headers = resp.headers().clone();
+            Ok((status, headers, resp.text()?))
+        });
+        entry.time = started.elapsed().as_millis() as u64;
+
+        match &result {
+            Ok((status, headers, text)) => {
+                entry.response = trace::Response {
+                    status: status.as_u16(),
+                    headers: trace_headers(headers),
+                    content: trace::Content {
+                        mime_type: headers
+                            .get(CONTENT_TYPE)
+                            .and_then(|v| v.to_str().ok())
+                            .unwrap_or_default()
+                            .to_owned(),
+                        text: redact::body(text),
+                    },
+                };
+
+                debug!(
+                    "Ответ АПИ на {} {}: {} за {} мс",
+                    http_method, url, status, entry.time
+                );
+                trace!("Тело ответа на {}: {}", url, entry.response.content.text);
+            }
+            Err(e) => {
+                entry.error = Some(e.to_string());
+
+                debug!(
+                    "Запрос в АПИ {} {} завершился ошибкой за {} мс: {}",
+                    http_method, url, entry.time, e
+                );
+            }
+        }
+
+        trace::write(&entry);
 
-        let status_code = resp.status();
+        let (status_code, _, text) = result?;
 
         if status_code == StatusCode::UNAUTHORIZED {
-            let text = resp.text()?;
-            return Err(RequestError::Unauthorized(text));
+            return Err(RequestError::Unauthorized(redact::body(&text)));
         }
 
         if status_code != StatusCode::OK {
-            let text = resp.text()?;
-            return Err(RequestError::Not200(status_code, text));
+            return Err(RequestError::Not200(status_code, redact::body(&text)));
         }
 
-        let body = resp.text()?;
-
-        debug!("Тело ответа на {}: {}", url, body);
-
-        let data = serde_json::from_str(&body)?;
+        let data = serde_json::from_str(&text)?;
 
         Ok(data)
     }
 
-    fn build_url(method: &str) -> String {
-        format!("{}/api/{}", BASE_URL, method.trim_start_matches('/'))
+    fn build_url(&self, method: &str) -> String {
+        format!("{}/api/{}", self.base_url, method.trim_start_matches('/'))
     }
 }
 
+/// Превращает заголовки в записи для трассировки, скрывая секреты.
+fn trace_headers(headers: &HeaderMap) -> Vec<trace::Header> {
+    headers
+        .iter()
+        .map(|(name, value)| trace::Header {
+            name: name.to_string(),
+            value: redact::header(name.as_str(), value.to_str().unwrap_or_default()),
+        })
+        .collect()
+}
+
 pub type RequestResult<T> = Result<T, RequestError>;
 
 #[derive(thiserror::Error, Debug)]
@@ -111,6 +183,9 @@ pub enum RequestError {
     #[error("got {0} status code: {1}")]
     Not200(StatusCode, String),
 
+    #[error("encode request body")]
+    EncodeRequestBody(serde_json::Error),
+
     #[error("decode response body")]
     DecodeResponseBody(#[from] serde_json::Error),
 
@@ -118,10 +193,32 @@ pub enum RequestError {
     Token(#[from] TokenNewError),
 }
 
+/// Зарегистрированный чек.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Receipt {
+    /// Идентификатор чека.
+    pub uuid: String,
+
+    /// Ссылка на печатную форму чека.
+    pub url: String,
+}
+
+/// Причина аннулирования чека.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, derive_more::Display, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CancelReason {
+    #[display(fmt = "Чек сформирован ошибочно")]
+    Mistake,
+    #[display(fmt = "Возврат средств")]
+    Refund,
+}
+
 pub struct AuthorizedClient {
     client: InnerClient,
+    settings: HttpSettings,
     device_id: String,
     inn: String,
+    name: String,
     access_token: AccessToken,
     refresh_token: RefreshToken,
 }
@@ -131,11 +228,14 @@ impl AuthorizedClient {
         device_id: String,
         access_token: AccessToken,
         refresh_token: RefreshToken,
+        settings: &HttpSettings,
     ) -> anyhow::Result<Self> {
         let mut client = Self {
-            client: InnerClient::new()?,
+            client: InnerClient::new(settings)?,
+            settings: settings.clone(),
             device_id,
             inn: String::new(),
+            name: String::new(),
             access_token,
             refresh_token,
         };
@@ -143,6 +243,7 @@ impl AuthorizedClient {
         let taxpayer = client.taxpayer()?;
 
         client.inn = taxpayer.inn;
+        client.name = taxpayer.display_name;
 
         Ok(client)
     }
@@ -151,18 +252,74 @@ impl AuthorizedClient {
         self.get("/v1/taxpayer")
     }
 
-    pub fn register_income(&mut self, check: Check) -> anyhow::Result<String> {
+    pub fn register_income(&mut self, check: Check) -> anyhow::Result<Receipt> {
         let req = IncomeRequest::from(check);
 
-        debug!("Request {:?}", req);
         let resp: IncomeResponse = self.post("/v1/income", Some(&req))?;
 
-        let url = format!(
-            "{}/{}/{}/print",
-            BASE_URL, self.inn, resp.approved_receipt_uuid
-        );
+        let url = self
+            .settings
+            .receipt_url(&self.inn, &resp.approved_receipt_uuid);
+
+        Ok(Receipt {
+            uuid: resp.approved_receipt_uuid,
+            url,
+        })
+    }
+
+    /// Аннулирует чек с указанным идентификатором.
+    pub fn cancel_income(&mut self, uuid: &str, reason: CancelReason) -> anyhow::Result<()> {
+        let time = Local::now().format("%FT%X%:z").to_string();
+
+        let req = CancelRequest {
+            comment: reason.to_string(),
+            operation_time: time.clone(),
+            partner_code: None,
+            receipt_uuid: uuid.to_owned(),
+            request_time: time,
+        };
+
+        let _: serde_json::Value = self.post("/v1/cancel", Some(&req))?;
 
-        Ok(url)
+        Ok(())
+    }
+
+    /// Возвращает чеки, выписанные в указанный промежуток, начиная с самых
+    /// новых.
+    pub fn incomes(
+        &mut self,
+        from: DateTime<FixedOffset>,
+        to: DateTime<FixedOffset>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Income>> {
+        // Плюс в смещении часового пояса нужно экранировать, иначе он
+        // превратится в пробел.
+        let time =
+            |t: DateTime<FixedOffset>| t.format("%FT%X%.3f%:z").to_string().replace('+', "%2B");
+
+        let mut incomes = Vec::new();
+
+        loop {
+            let method = format!(
+                "/v1/incomes?from={}&to={}&offset={}&sortBy=operation_time:desc&limit={}",
+                time(from),
+                time(to),
+                incomes.len(),
+                limit.min(50),
+            );
+            let resp: IncomesResponse = self.get(&method)?;
+
+            let has_more = resp.has_more && !resp.content.is_empty();
+            incomes.extend(resp.content);
+
+            if !has_more || incomes.len() >= limit {
+                break;
+            }
+        }
+
+        incomes.truncate(limit);
+
+        Ok(incomes)
     }
 
     fn get<R: DeserializeOwned>(&mut self, api_method: &str) -> RequestResult<R> {
@@ -197,7 +354,7 @@ impl AuthorizedClient {
 
     fn refresh_token(&mut self) -> RequestResult<()> {
         let req = TokenRefreshRequest {
-            device_info: DeviceInfo::new(&self.device_id),
+            device_info: DeviceInfo::new(&self.device_id, &self.settings),
             refresh_token: self.refresh_token.value.clone(),
         };
 
@@ -213,6 +370,10 @@ impl AuthorizedClient {
         self.inn.clone()
     }
 
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
     pub fn get_access_token(&self) -> AccessToken {
         self.access_token.clone()
     }
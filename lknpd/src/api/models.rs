@@ -1,8 +1,9 @@
 use crate::{
-    api::client::USER_AGENT,
+    api::HttpSettings,
     model::{Check, Counterparty},
 };
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, FixedOffset, Utc};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
 #[derive(Default, Debug, Clone, PartialEq, Deserialize)]
@@ -99,14 +100,14 @@ pub struct DeviceInfo {
 }
 
 impl DeviceInfo {
-    pub fn new(device_id: &str) -> Self {
+    pub fn new(device_id: &str, settings: &HttpSettings) -> Self {
         Self {
-            app_version: "1.0.0".to_owned(),
+            app_version: settings.app_version.clone(),
             meta_details: DeviceInfoMetaDetails {
-                user_agent: USER_AGENT.to_owned(),
+                user_agent: settings.user_agent.clone(),
             },
             source_device_id: device_id.to_owned(),
-            source_type: "WEB".to_owned(),
+            source_type: settings.source_type.clone(),
         }
     }
 }
@@ -202,3 +203,38 @@ impl From<Check> for IncomeRequest {
 pub struct IncomeResponse {
     pub approved_receipt_uuid: String,
 }
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelRequest {
+    pub comment: String,
+    pub operation_time: String,
+    pub partner_code: Option<String>,
+    pub receipt_uuid: String,
+    pub request_time: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IncomesResponse {
+    pub content: Vec<Income>,
+    pub has_more: bool,
+}
+
+/// Чек, зарегистрированный в АПИ.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Income {
+    pub approved_receipt_uuid: String,
+    pub name: String,
+    pub operation_time: DateTime<FixedOffset>,
+    pub total_amount: Decimal,
+    #[serde(default)]
+    pub income_type: Option<String>,
+    #[serde(default)]
+    pub client_inn: Option<String>,
+    #[serde(default)]
+    pub client_display_name: Option<String>,
+    #[serde(default)]
+    pub cancellation_info: Option<serde_json::Value>,
+}
@@ -0,0 +1,134 @@
+use serde_json::Value;
+
+/// Чем заменяются скрытые значения.
+const MASK: &str = "***";
+
+/// Части названий полей, значения которых нельзя писать в логи: токены,
+/// идентификатор устройства и персональные данные самозанятого.
+const SENSITIVE: &[&str] = &[
+    "token", "password", "secret", "deviceid", "phone", "email", "passport", "snils", "birthday",
+    "address",
+];
+
+/// Скрывает чувствительные данные в теле запроса или ответа. Если тело не
+/// JSON, то оно возвращается как есть: АПИ отвечает не JSON только при ошибках
+/// прокси или балансировщика.
+pub(super) fn body(text: &str) -> String {
+    match serde_json::from_str::<Value>(text) {
+        Ok(mut value) => {
+            json(&mut value);
+            value.to_string()
+        }
+        Err(_) => text.to_owned(),
+    }
+}
+
+/// Скрывает чувствительные данные в JSON.
+pub(super) fn json(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            // Код из СМС лежит в поле с общим названием `code`, которое в
+            // ответах с ошибками содержит код ошибки, поэтому скрываем его
+            // только рядом с токеном проверки.
+            let has_challenge = map.contains_key("challengeToken");
+
+            for (key, value) in map.iter_mut() {
+                if is_sensitive(key) || (has_challenge && key == "code") {
+                    mask(value);
+                } else {
+                    json(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(json),
+        _ => {}
+    }
+}
+
+/// Скрывает значение заголовка, если в нём передаются секреты.
+pub(super) fn header(name: &str, value: &str) -> String {
+    match name.to_ascii_lowercase().as_str() {
+        "authorization" => match value.split_once(' ') {
+            Some((scheme, _)) => format!("{} {}", scheme, MASK),
+            None => MASK.to_owned(),
+        },
+        "cookie" | "set-cookie" => MASK.to_owned(),
+        _ => value.to_owned(),
+    }
+}
+
+fn is_sensitive(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    SENSITIVE.iter().any(|s| key.contains(s))
+}
+
+fn mask(value: &mut Value) {
+    // Пустые значения оставляем, чтобы было видно, что поле не заполнено.
+    match value {
+        Value::Null => {}
+        Value::String(s) if s.is_empty() => {}
+        _ => *value = Value::String(MASK.to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn redact_json() {
+        let mut taxpayer = json!({
+            "displayName": "Иванов Иван Иванович",
+            "inn": "123456789012",
+            "phone": "79001234567",
+            "email": "",
+            "passportSeries": "1234",
+            "passportNumber": "567890",
+            "snils": "123-456-789 00",
+            "oktmo": { "code": "45000000", "name": "Москва" },
+            "pfrInfo": { "regnum": null },
+        });
+        json(&mut taxpayer);
+
+        assert_eq!(
+            taxpayer,
+            json!({
+                "displayName": "Иванов Иван Иванович",
+                "inn": "123456789012",
+                "phone": "***",
+                "email": "",
+                "passportSeries": "***",
+                "passportNumber": "***",
+                "snils": "***",
+                "oktmo": { "code": "45000000", "name": "Москва" },
+                "pfrInfo": { "regnum": null },
+            })
+        );
+
+        let verify = body(
+            r#"{"challengeToken":"abc","code":"1234","deviceInfo":{"sourceDeviceId":"xyz","sourceType":"WEB"},"phone":"79001234567"}"#,
+        );
+        assert_eq!(
+            verify,
+            r#"{"challengeToken":"***","code":"***","deviceInfo":{"sourceDeviceId":"***","sourceType":"WEB"},"phone":"***"}"#
+        );
+
+        assert_eq!(
+            body(r#"{"code":"authentication.failed","message":"Неверный код"}"#),
+            r#"{"code":"authentication.failed","message":"Неверный код"}"#
+        );
+        assert_eq!(body("<html>Bad Gateway</html>"), "<html>Bad Gateway</html>");
+    }
+
+    #[test]
+    fn redact_header() {
+        assert_eq!(header("Authorization", "Bearer abc.def"), "Bearer ***");
+        assert_eq!(header("set-cookie", "session=abc"), "***");
+        assert_eq!(
+            header("content-type", "application/json"),
+            "application/json"
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::{Mutex, OnceLock},
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Local};
+use log::error;
+use serde::Serialize;
+
+/// Файл, в который пишутся запросы к АПИ. Как и логгер, задаётся один раз на
+/// всё приложение при запуске.
+static TRACE: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Включает запись всех запросов к АПИ и ответов на них в файл. Каждая строка
+/// файла - JSON с записью в формате, похожем на `entries` из HAR. Секреты и
+/// персональные данные в записях скрыты.
+pub fn enable_trace(path: &Path) -> anyhow::Result<()> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open http trace file {:?}", path))?;
+
+    TRACE
+        .set(Mutex::new(file))
+        .map_err(|_| anyhow::anyhow!("http trace is already enabled"))
+}
+
+/// Запись о запросе к АПИ.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct Entry {
+    pub started_date_time: DateTime<Local>,
+
+    /// Сколько миллисекунд занял запрос.
+    pub time: u64,
+
+    pub request: Request,
+
+    pub response: Response,
+
+    /// Ошибка, из-за которой ответ не был получен.
+    #[serde(rename = "_error", skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct Request {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<Header>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_data: Option<Content>,
+}
+
+/// Ответ на запрос. Если ответ не получен, то статус 0.
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct Response {
+    pub status: u16,
+    pub headers: Vec<Header>,
+    pub content: Content,
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct Header {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct Content {
+    pub mime_type: String,
+    pub text: String,
+}
+
+/// Дописывает запись в файл, если запись запросов включена. Ошибки записи
+/// только логируются, чтобы не мешать работе с АПИ.
+pub(super) fn write(entry: &Entry) {
+    let Some(file) = TRACE.get() else {
+        return;
+    };
+
+    let result = serde_json::to_string(entry)
+        .map_err(anyhow::Error::from)
+        .and_then(|line| {
+            let mut file = file.lock().map_err(|_| anyhow::anyhow!("poisoned lock"))?;
+            writeln!(file, "{}", line)?;
+            Ok(())
+        });
+
+    if let Err(e) = result {
+        error!("Не удалось записать запрос в файл: {:#}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn har_like_entry() {
+        let entry = Entry {
+            started_date_time: Local::now(),
+            time: 42,
+            request: Request {
+                method: "POST".to_owned(),
+                url: "https://lknpd.nalog.ru/api/v1/income".to_owned(),
+                headers: vec![Header {
+                    name: "authorization".to_owned(),
+                    value: "Bearer ***".to_owned(),
+                }],
+                post_data: Some(Content {
+                    mime_type: "application/json".to_owned(),
+                    text: "{}".to_owned(),
+                }),
+            },
+            response: Response::default(),
+            error: Some("timeout".to_owned()),
+        };
+
+        let json = serde_json::to_value(&entry).unwrap();
+        assert_eq!(json["time"], 42);
+        assert_eq!(json["request"]["postData"]["mimeType"], "application/json");
+        assert_eq!(json["request"]["headers"][0]["value"], "Bearer ***");
+        assert_eq!(json["response"]["status"], 0);
+        assert_eq!(json["_error"], "timeout");
+        assert!(json["startedDateTime"].is_string());
+    }
+}
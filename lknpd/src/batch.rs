@@ -0,0 +1,100 @@
+use std::{fs, path::Path};
+
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+
+use crate::template::compiled::FieldValues;
+
+/// Файл с чеками, которые выписываются командой `batch` без интерактивного
+/// ввода.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Batch {
+    pub checks: Vec<Entry>,
+}
+
+/// Чек в файле.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// Название шаблона.
+    pub template: String,
+
+    /// Время, относительно которого вычисляются функции в шаблоне.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub now: Option<DateTime<FixedOffset>>,
+
+    /// Значения переменных шаблона. Для переменных типа `client` указывается
+    /// ИНН заказчика из адресной книги.
+    #[serde(default)]
+    pub values: FieldValues,
+
+    /// Произвольный комментарий, например платёж, за который выписывается чек.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+
+    /// Идентификатор выписанного чека. Записывается командой `batch`, чтобы
+    /// при повторном запуске чек не выписывался снова.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receipt: Option<String>,
+
+    /// Номер чека в очереди, если сервис был недоступен.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queued: Option<String>,
+}
+
+impl Entry {
+    /// Проверяет, что чек уже выписан или поставлен в очередь.
+    pub fn is_done(&self) -> bool {
+        self.receipt.is_some() || self.queued.is_some()
+    }
+}
+
+/// Загружает чеки из файла.
+pub fn load(path: &Path) -> anyhow::Result<Batch> {
+    let content = fs::read_to_string(path)?;
+
+    Ok(toml::from_str(&content)?)
+}
+
+/// Сохраняет чеки в файл.
+pub fn save(batch: &Batch, path: &Path) -> anyhow::Result<()> {
+    let content = toml::to_string(batch)?;
+
+    fs::write(path, content)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let batch = Batch {
+            checks: vec![Entry {
+                template: "support".to_owned(),
+                now: Some(DateTime::parse_from_rfc3339("2024-03-05T12:00:00+03:00").unwrap()),
+                values: FieldValues::from([
+                    ("buyer".to_owned(), "1234567890".to_owned()),
+                    ("amount".to_owned(), "25000".to_owned()),
+                ]),
+                comment: Some("платёж 15 от 05.03.2024".to_owned()),
+                receipt: Some("abc".to_owned()),
+                queued: None,
+            }],
+        };
+
+        let content = toml::to_string(&batch).unwrap();
+        let loaded: Batch = toml::from_str(&content).unwrap();
+        assert_eq!(loaded.checks, batch.checks);
+        assert!(loaded.checks[0].is_done());
+
+        let loaded: Batch = toml::from_str(
+            "[[checks]]\ntemplate = \"support\"\n\n[checks.values]\nhours = \"10\"\n",
+        )
+        .unwrap();
+        assert_eq!(loaded.checks[0].now, None);
+        assert_eq!(loaded.checks[0].values["hours"], "10");
+        assert!(!loaded.checks[0].is_done());
+    }
+}
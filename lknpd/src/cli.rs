@@ -1,20 +1,36 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
 use enum_iterator::{all, cardinality};
 use inquire::validator::Validation;
-use inquire::Text;
+use inquire::{Select, Text};
 
 use crate::{
+    clients::Client,
+    clock::Clock,
     functions,
     template::compiled::{
-        Field, FieldName, FieldValues, Fields, Placeholder, PlaceholderDefault, Values,
+        ClientProperty, Field, FieldName, FieldValues, Fields, PlaceholderDefault, Values,
+        Variable, VariableKind,
     },
 };
 
 /// Запрашивает пользовательский ввод для всех переменных, если они есть.
-pub fn ask(fields: &Fields) -> anyhow::Result<Values> {
+/// Значение для каждой переменной запрашивается один раз, даже если она
+/// встречается в нескольких плейсхолдерах или полях.
+/// Значения из `last` предлагаются по-умолчанию вместо значений из шаблона.
+/// Заказчики для переменных типа `client` выбираются из `clients`.
+pub fn ask(
+    fields: &Fields,
+    clock: &Clock,
+    last: &FieldValues,
+    clients: &[Client],
+) -> anyhow::Result<Values> {
     let mut values = Values::with_capacity(cardinality::<FieldName>());
+    let mut answers = HashMap::new();
 
     for name in all::<FieldName>() {
-        let res = ask_field(fields.get(&name))?;
+        let res = ask_field(fields.get(&name), &mut answers, last, clients, clock)?;
 
         if let Some(fvs) = res {
             values.insert(name, fvs);
@@ -24,63 +40,261 @@ pub fn ask(fields: &Fields) -> anyhow::Result<Values> {
     Ok(values)
 }
 
-fn ask_field(fields: Option<&Field>) -> anyhow::Result<Option<FieldValues>> {
+fn ask_field(
+    fields: Option<&Field>,
+    answers: &mut HashMap<String, FieldValues>,
+    last: &FieldValues,
+    clients: &[Client],
+    clock: &Clock,
+) -> anyhow::Result<Option<FieldValues>> {
     fields.map_or(Ok(None), |f| {
-        if f.placeholders.is_empty() {
-            return Ok(None);
-        }
+        let vars = f.variables();
 
-        let mut values = FieldValues::with_capacity(f.placeholders.len());
+        let mut values = FieldValues::with_capacity(vars.len());
 
-        let vars = f.placeholders.iter().filter(|ph| {
-            matches!(
-                ph,
-                Placeholder::Variable {
-                    name: _,
-                    default: _
-                }
-            )
-        });
-
-        for ph in vars {
-            let value = prompt(ph)?;
-            values.insert(ph.name(), value);
+        for var in vars {
+            if !answers.contains_key(&var.name) {
+                let answer = match var.kind {
+                    VariableKind::Text => FieldValues::from([(
+                        var.name.clone(),
+                        prompt(&var, last.get(&var.name), clock)?,
+                    )]),
+                    VariableKind::Client => select_client(&var, last, clients)?,
+                };
+                answers.insert(var.name.clone(), answer);
+            }
+            values.extend(answers[&var.name].clone());
         }
         Ok(Some(values))
     })
 }
 
-fn prompt(ph: &Placeholder) -> anyhow::Result<String> {
-    let title = format!("Значение для переменной \"{}\"", ph.name());
+/// Предлагает выбрать заказчика из адресной книги.
+fn select_client(
+    var: &Variable,
+    last: &FieldValues,
+    clients: &[Client],
+) -> anyhow::Result<FieldValues> {
+    if clients.is_empty() {
+        return Err(anyhow!(
+            "address book is empty, add clients with `clients add` first"
+        ));
+    }
+
+    let title = format!("Заказчик для переменной \"{}\"", var.name);
+
+    // По-умолчанию выбран заказчик, который был выбран в прошлый раз.
+    let last_inn = last.get(&ClientProperty::Inn.key(&var.name));
+    let cursor = clients
+        .iter()
+        .position(|c| Some(&c.inn) == last_inn)
+        .unwrap_or_default();
+
+    let client = Select::new(&title, clients.to_vec())
+        .with_starting_cursor(cursor)
+        .with_help_message("начните вводить название или ИНН для поиска")
+        .prompt()?;
+
+    Ok(client.values(&var.name))
+}
+
+fn prompt(var: &Variable, last: Option<&String>, clock: &Clock) -> anyhow::Result<String> {
+    let title = format!("Значение для переменной \"{}\"", var.name);
 
     let mut prompt = Text::new(&title);
 
-    let default_value = compute_default_value(ph)?;
+    let default_value = default_value(var, last, clock)?;
 
     if !default_value.is_empty() {
         prompt = prompt.with_default(&default_value);
     }
 
-    // На данный момент считаем что все плейсхолдеры обязательны для заполнения.
-    prompt = prompt.with_validator(|s: &str| {
-        if s.is_empty() {
-            return Ok(Validation::Invalid("required".into()));
-        };
-        Ok(Validation::Valid)
-    });
+    if var.required {
+        prompt = prompt.with_validator(|s: &str| {
+            if s.is_empty() {
+                return Ok(Validation::Invalid("required".into()));
+            };
+            Ok(Validation::Valid)
+        });
+    } else {
+        prompt = prompt.with_help_message("можно оставить пустым");
+    }
 
     let val = prompt.prompt()?;
 
     Ok(val)
 }
 
-fn compute_default_value(ph: &Placeholder) -> anyhow::Result<String> {
-    let val = match ph.default() {
+/// Возвращает значение, которое предлагается для переменной. Последнее
+/// введённое значение удобнее значения из шаблона, так как для регулярных
+/// чеков оно обычно не меняется. Но значение из функции, например `now()`,
+/// вычисляется заново, иначе по Enter выписался бы чек прошлой датой.
+fn default_value(var: &Variable, last: Option<&String>, clock: &Clock) -> anyhow::Result<String> {
+    match (&var.default, last) {
+        (Some(PlaceholderDefault::Function(_)), _) => {
+            compute_default_value(var.default.clone(), clock)
+        }
+        (_, Some(v)) if !v.is_empty() => Ok(v.clone()),
+        _ => compute_default_value(var.default.clone(), clock),
+    }
+}
+
+/// Заполняет переменные шаблона заранее известными значениями, без
+/// пользовательского ввода. Если значение не указано, то используется значение
+/// по-умолчанию из шаблона. Для переменных типа `client` указывается ИНН
+/// заказчика из адресной книги.
+pub fn fill(
+    fields: &Fields,
+    given: &FieldValues,
+    clients: &[Client],
+    clock: &Clock,
+) -> anyhow::Result<Values> {
+    let mut values = Values::with_capacity(fields.len());
+
+    for (name, field) in fields {
+        let mut field_values = FieldValues::new();
+
+        for var in field.variables() {
+            let value = given.get(&var.name);
+
+            match (var.kind, value) {
+                (VariableKind::Text, Some(v)) => {
+                    field_values.insert(var.name, v.clone());
+                }
+                (VariableKind::Text, None) if var.default.is_some() || !var.required => {
+                    let value = compute_default_value(var.default, clock)?;
+                    field_values.insert(var.name, value);
+                }
+                (VariableKind::Client, Some(inn)) => {
+                    let client = clients
+                        .iter()
+                        .find(|c| &c.inn == inn)
+                        .ok_or(anyhow!("client with inn {} not found", inn))?;
+                    field_values.extend(client.values(&var.name));
+                }
+                (_, None) => return Err(anyhow!("no value for variable {}", var.name)),
+            }
+        }
+
+        values.insert(name.clone(), field_values);
+    }
+
+    Ok(values)
+}
+
+/// Вычисляет значение по-умолчанию для переменной.
+pub fn compute_default_value(
+    default: Option<PlaceholderDefault>,
+    clock: &Clock,
+) -> anyhow::Result<String> {
+    let val = match default {
         Some(d) => match d {
             PlaceholderDefault::String(s) => s,
-            PlaceholderDefault::Function(n) => functions::execute(&n)?,
+            PlaceholderDefault::Function(n) => functions::execute(&n, clock)?,
         },
         None => String::new(),
     };
     Ok(val)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clients::Kind,
+        template::{compiled, raw},
+    };
+    use chrono::DateTime;
+
+    #[test]
+    fn fill_given_values() {
+        let clock =
+            Clock::Fixed(DateTime::parse_from_rfc3339("2024-03-31T18:00:00+03:00").unwrap());
+        let tmpl = compiled::Template::new(
+            raw::Template {
+                title: "Поддержка{{#if urgent}} срочная{{/if}}, {{ hours }} ч.".to_owned(),
+                price: "{{ hours * 2500 }}".to_owned(),
+                date: "{{ date:now() }}".to_owned(),
+                counterparty: raw::Counterparty::Organization {
+                    name: "{{ buyer:client(name) }}".to_owned(),
+                    inn: "{{ buyer:client(inn) }}".to_owned(),
+                },
+                no_history: Vec::new(),
+                email: None,
+            },
+            clock,
+        )
+        .unwrap();
+
+        let clients = [Client {
+            name: "ООО Ромашка".to_owned(),
+            inn: "1234567890".to_owned(),
+            kind: Kind::Organization,
+            phone: None,
+            email: None,
+        }];
+
+        let fill = |given: &[(&str, &str)]| {
+            let given = given
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            fill(tmpl.get_fields(), &given, &clients, &clock)
+        };
+
+        let values = fill(&[("hours", "10"), ("buyer", "1234567890")]).unwrap();
+
+        assert_eq!(values[&FieldName::Title]["urgent"], "");
+        assert_eq!(
+            values[&FieldName::Date]["date"],
+            "2024-03-31T18:00:00+03:00"
+        );
+
+        let check = tmpl.build_check(&values).unwrap();
+        assert_eq!(
+            check.title,
+            crate::model::Title::new("Поддержка, 10 ч.").unwrap()
+        );
+        assert_eq!(check.price, crate::model::Price::new(25000));
+
+        let err = fill(&[("buyer", "1234567890")]).unwrap_err();
+        assert_eq!(err.to_string(), "no value for variable hours");
+
+        let err = fill(&[("hours", "10"), ("buyer", "0000000000")]).unwrap_err();
+        assert_eq!(err.to_string(), "client with inn 0000000000 not found");
+    }
+
+    #[test]
+    fn function_default_beats_history() {
+        let clock =
+            Clock::Fixed(DateTime::parse_from_rfc3339("2024-03-31T18:00:00+03:00").unwrap());
+        let tmpl = compiled::Template::new(
+            raw::Template {
+                title: "Поддержка, {{ hours:\"10\" }} ч.".to_owned(),
+                price: "{{ hours * 2500 }}".to_owned(),
+                date: "{{ date:now() }}".to_owned(),
+                counterparty: raw::Counterparty::Person,
+                no_history: vec!["hours".to_owned()],
+                email: None,
+            },
+            clock,
+        )
+        .unwrap();
+
+        let vars = tmpl.variables();
+        let var = |name: &str| vars.iter().find(|v| v.name == name).unwrap();
+        let stale = "2024-02-29T18:00:00+03:00".to_owned();
+
+        assert_eq!(
+            default_value(var("date"), Some(&stale), &clock).unwrap(),
+            "2024-03-31T18:00:00+03:00"
+        );
+        assert_eq!(
+            default_value(var("hours"), Some(&"8".to_owned()), &clock).unwrap(),
+            "8"
+        );
+        assert_eq!(default_value(var("hours"), None, &clock).unwrap(), "10");
+
+        assert_eq!(tmpl.no_history(), ["hours", "date"]);
+    }
+}
@@ -0,0 +1,224 @@
+use std::{fmt::Display, fs, io, path::Path};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use crate::template::compiled::{ClientProperty, FieldValues};
+
+/// Адресная книга заказчиков.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Book {
+    pub clients: Vec<Client>,
+}
+
+/// Заказчик из адресной книги.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Client {
+    /// Название организации или ФИО.
+    pub name: String,
+
+    /// ИНН.
+    pub inn: String,
+
+    /// Тип заказчика.
+    pub kind: Kind,
+
+    /// Контактный телефон.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phone: Option<String>,
+
+    /// Email, на который отправляется ссылка на чек.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+}
+
+impl Display for Client {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (ИНН {}, {})", self.name, self.inn, self.kind)
+    }
+}
+
+/// Тип заказчика.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, derive_more::Display, clap::ValueEnum,
+)]
+pub enum Kind {
+    #[display(fmt = "юр. лицо")]
+    Organization,
+    #[display(fmt = "физ. лицо")]
+    Person,
+}
+
+impl Client {
+    /// Возвращает значения для переменной типа `client`: все свойства заказчика,
+    /// а также его название под именем самой переменной.
+    pub fn values(&self, variable: &str) -> FieldValues {
+        FieldValues::from([
+            (variable.to_owned(), self.name.clone()),
+            (ClientProperty::Name.key(variable), self.name.clone()),
+            (ClientProperty::Inn.key(variable), self.inn.clone()),
+            (
+                ClientProperty::Phone.key(variable),
+                self.phone.clone().unwrap_or_default(),
+            ),
+        ])
+    }
+
+    /// Проверяет, что данные заказчика корректны.
+    /// У организации ИНН состоит из 10 цифр, у физ. лица из 12.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.name.trim().is_empty() {
+            return Err(anyhow!("name shouldn't be empty"));
+        }
+
+        let (kind, len) = match self.kind {
+            Kind::Organization => ("organization", 10),
+            Kind::Person => ("person", 12),
+        };
+
+        if self.inn.len() != len || !self.inn.chars().all(|c| c.is_ascii_digit()) {
+            return Err(anyhow!("{} inn should be exactly {} digits", kind, len));
+        }
+
+        Ok(())
+    }
+}
+
+impl Book {
+    /// Добавляет заказчика в адресную книгу.
+    /// Заказчики с одинаковым ИНН не допускаются.
+    pub fn add(&mut self, client: Client) -> anyhow::Result<()> {
+        client.validate()?;
+
+        if let Some(c) = self.clients.iter().find(|c| c.inn == client.inn) {
+            return Err(anyhow!(
+                "client with inn {} already exists: {}",
+                c.inn,
+                c.name
+            ));
+        }
+
+        self.clients.push(client);
+        self.clients.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(())
+    }
+
+    /// Удаляет заказчика с указанным ИНН или названием и возвращает его.
+    pub fn remove(&mut self, inn_or_name: &str) -> anyhow::Result<Client> {
+        let found: Vec<usize> = self
+            .clients
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.inn == inn_or_name || c.name == inn_or_name)
+            .map(|(i, _)| i)
+            .collect();
+
+        match found.as_slice() {
+            [i] => Ok(self.clients.remove(*i)),
+            [] => Err(anyhow!("client {} not found", inn_or_name)),
+            _ => Err(anyhow!(
+                "there are several clients named {}, use inn instead",
+                inn_or_name
+            )),
+        }
+    }
+}
+
+/// Загружает адресную книгу из указанного файла.
+/// Если файла нет, то возвращается пустая адресная книга.
+pub fn load(path: &Path) -> LoadResult {
+    if !path.exists() {
+        return Ok(Book::default());
+    }
+
+    let content = fs::read_to_string(path)?;
+
+    let book: Book = serde_json::from_str(&content)?;
+
+    Ok(book)
+}
+
+pub type LoadResult = std::result::Result<Book, LoadError>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum LoadError {
+    #[error("read clients file")]
+    ReadFile(#[from] io::Error),
+
+    #[error("deserialize")]
+    Deserialize(#[from] serde_json::Error),
+}
+
+/// Сохраняет адресную книгу в указанный файл.
+pub fn save(book: &Book, path: &Path) -> SaveResult {
+    let content = serde_json::to_string_pretty(book)?;
+
+    fs::create_dir_all(path.parent().unwrap_or(Path::new("")))?;
+
+    fs::write(path, content)?;
+
+    Ok(())
+}
+
+pub type SaveResult = std::result::Result<(), SaveError>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SaveError {
+    #[error("write clients file")]
+    WriteFile(#[from] io::Error),
+
+    #[error("serialize")]
+    Serialize(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(name: &str, inn: &str, kind: Kind) -> Client {
+        Client {
+            name: name.to_owned(),
+            inn: inn.to_owned(),
+            kind,
+            phone: None,
+            email: None,
+        }
+    }
+
+    #[test]
+    fn add_and_remove() {
+        let mut book = Book::default();
+
+        book.add(client("Ромашка", "1234567890", Kind::Organization))
+            .unwrap();
+        book.add(client("Иванов И.И.", "123456789012", Kind::Person))
+            .unwrap();
+
+        assert_eq!(
+            book.add(client("Лютик", "1234567890", Kind::Organization))
+                .unwrap_err()
+                .to_string(),
+            "client with inn 1234567890 already exists: Ромашка"
+        );
+        assert_eq!(
+            book.add(client("Лютик", "123", Kind::Organization))
+                .unwrap_err()
+                .to_string(),
+            "organization inn should be exactly 10 digits"
+        );
+
+        assert_eq!(
+            book.clients
+                .iter()
+                .map(|c| c.name.as_str())
+                .collect::<Vec<_>>(),
+            ["Иванов И.И.", "Ромашка"]
+        );
+
+        assert_eq!(book.remove("Ромашка").unwrap().inn, "1234567890");
+        assert_eq!(book.remove("123456789012").unwrap().name, "Иванов И.И.");
+        assert!(book.remove("Ромашка").is_err());
+        assert!(book.clients.is_empty());
+    }
+}
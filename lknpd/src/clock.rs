@@ -0,0 +1,31 @@
+use chrono::{DateTime, FixedOffset, Local};
+
+/// Источник текущего времени.
+///
+/// Позволяет подменить "сейчас", например, чтобы выписать чеки за прошлый месяц
+/// первого числа, а так же делает функции шаблонов детерминированными в тестах.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Clock {
+    /// Системные часы в локальном часовом поясе.
+    #[default]
+    System,
+
+    /// Зафиксированный момент времени.
+    Fixed(DateTime<FixedOffset>),
+}
+
+impl Clock {
+    /// Возвращает текущий момент времени.
+    pub fn now(&self) -> DateTime<FixedOffset> {
+        match self {
+            Self::System => Local::now().fixed_offset(),
+            Self::Fixed(now) => *now,
+        }
+    }
+}
+
+impl From<Option<DateTime<FixedOffset>>> for Clock {
+    fn from(value: Option<DateTime<FixedOffset>>) -> Self {
+        value.map_or(Self::System, Self::Fixed)
+    }
+}
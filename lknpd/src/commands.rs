@@ -0,0 +1,10 @@
+pub mod batch;
+pub mod clients;
+pub mod config;
+pub mod init;
+pub mod ledger;
+pub mod queue;
+pub mod schedule;
+pub mod serve;
+pub mod statement;
+pub mod templates;
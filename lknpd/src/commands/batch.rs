@@ -0,0 +1,253 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use log::{debug, error, info};
+use serde_json::json;
+
+use crate::{
+    api::AuthorizedClient,
+    batch::{self, Entry},
+    cli,
+    clients::{self, Book},
+    clock::Clock,
+    config::{self, Config},
+    hooks::{self, Event},
+    ledger, mail,
+    model::Check,
+    output::Output,
+    queue, state,
+    template::compiled,
+};
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[arg(short = 'c', long, env = "LKNPD_CONFIG")]
+    config_path: Option<PathBuf>,
+
+    #[arg(long)]
+    #[arg(help = "Only check that every check in the file can be built")]
+    dry_run: bool,
+
+    #[arg(long)]
+    #[arg(help = "Email receipt links to the clients using [smtp] settings from config")]
+    send_email: bool,
+
+    #[arg()]
+    #[arg(help = "TOML file with [[checks]] entries")]
+    file: PathBuf,
+}
+
+/// Выписывает все чеки из файла. Чеки, которые не удалось выписать из-за
+/// недоступности сервиса, ставятся в очередь, остальные ошибки не прерывают
+/// выписку следующих чеков.
+///
+/// Идентификатор выписанного чека или номер в очереди записывается обратно в
+/// файл, поэтому при повторном запуске после ошибок выписываются только
+/// оставшиеся чеки.
+pub fn run(args: Args, output: Output) -> anyhow::Result<()> {
+    let cfg = config::load(args.config_path)?;
+
+    debug!("Подгружаем чеки из {:?}", args.file);
+    let mut batch = batch::load(&args.file)?;
+
+    debug!("Подгружаем адресную книгу из {:?}", cfg.clients_path);
+    let book = clients::load(&cfg.clients_path)?;
+
+    if args.send_email && cfg.smtp.is_none() {
+        return Err(anyhow!("--send-email requires [smtp] settings in config"));
+    }
+
+    if args.dry_run {
+        let mut checks = Vec::new();
+
+        for (i, entry) in batch.checks.iter().enumerate() {
+            if entry.is_done() {
+                continue;
+            }
+
+            let (_, check) = build(&cfg, &book, entry)
+                .map_err(|e| anyhow!("check #{} ({}): {:#}", i + 1, entry.template, e))?;
+            checks.push(check);
+        }
+
+        return output.print(&json!({ "checks": checks }), || {
+            for check in &checks {
+                let title: String = check.title.clone().into();
+                let price: u32 = check.price.clone().into();
+
+                println!("{}\t{}\t{}", check.date.format("%FT%X%:z"), price, title);
+            }
+        });
+    }
+
+    debug!("Подгружаем состояние из {:?}", cfg.state_path);
+    let mut state = state::load(&cfg.state_path)?;
+
+    let mut client = crate::get_client(&state, &cfg.http)?;
+
+    let mut issued: Vec<Event> = Vec::new();
+    let mut queued = Vec::new();
+    let mut failed = Vec::new();
+
+    for i in 0..batch.checks.len() {
+        let entry = batch.checks[i].clone();
+
+        if entry.is_done() {
+            info!(
+                "Чек #{} ({}) уже выписан или в очереди, пропускаем",
+                i + 1,
+                entry.template
+            );
+            continue;
+        }
+
+        match issue(&cfg, &book, &mut client, &entry) {
+            Ok(Issued::Event(event)) => {
+                if output == Output::Text {
+                    println!("Чек #{} доступен по URL: {}", i + 1, event.url);
+                }
+
+                batch.checks[i].receipt = Some(event.uuid.clone());
+                save(&batch, &args.file);
+
+                if args.send_email {
+                    send_email(&cfg, &book, &client, &entry, &event);
+                }
+
+                issued.push(event);
+            }
+            Ok(Issued::Queued(item)) => {
+                if output == Output::Text {
+                    println!("Чек #{} сохранён в очередь под номером {}", i + 1, item.id);
+                }
+
+                batch.checks[i].queued = Some(item.id.clone());
+                save(&batch, &args.file);
+
+                queued.push(item);
+            }
+            Err(e) => {
+                error!(
+                    "Не удалось выписать чек #{} ({}): {:#}",
+                    i + 1,
+                    entry.template,
+                    e
+                );
+                failed.push(json!({ "index": i + 1, "template": entry.template, "error": format!("{:#}", e) }));
+            }
+        }
+    }
+
+    info!(
+        "Выписано чеков: {}, в очереди: {}, с ошибками: {}",
+        issued.len(),
+        queued.len(),
+        failed.len()
+    );
+
+    debug!("Синхронизируем состояние с актуальными данными");
+    state.access_token = Some(client.get_access_token());
+    state.refresh_token = Some(client.get_refresh_token());
+    state.taxpayer_identification_number = Some(client.get_inn());
+
+    debug!("Сохраняем состояние в {:?}", cfg.state_path);
+    state::save(&state, &cfg.state_path)?;
+
+    output.print(
+        &json!({ "issued": issued, "queued": queued, "failed": failed }),
+        || {},
+    )?;
+
+    if !failed.is_empty() {
+        return Err(anyhow!("failed to issue {} checks", failed.len()));
+    }
+
+    Ok(())
+}
+
+/// Сохраняет отметки о выписанных чеках в файл. Чек уже выписан, поэтому
+/// ошибка только логируется.
+fn save(batch: &batch::Batch, path: &Path) {
+    debug!("Сохраняем отметки о выписке в {:?}", path);
+
+    if let Err(e) = batch::save(batch, path) {
+        error!(
+            "Не удалось сохранить отметки о выписке в {:?}, при повторном запуске чеки будут выписаны снова: {:#}",
+            path, e
+        );
+    }
+}
+
+/// Результат выписки одного чека.
+pub enum Issued {
+    Event(Event),
+    Queued(queue::Item),
+}
+
+/// Собирает чек по шаблону и значениям из файла.
+pub fn build(cfg: &Config, book: &Book, entry: &Entry) -> anyhow::Result<(Clock, Check)> {
+    let raw = cfg.template(&entry.template)?;
+
+    let clock = Clock::from(entry.now);
+    let tmpl = compiled::Template::new(raw, clock)?.with_rates(cfg.rates());
+
+    let values = cli::fill(tmpl.get_fields(), &entry.values, &book.clients, &clock)?;
+
+    Ok((clock, tmpl.build_check(&values)?))
+}
+
+/// Выписывает чек из файла.
+fn issue(
+    cfg: &Config,
+    book: &Book,
+    client: &mut AuthorizedClient,
+    entry: &Entry,
+) -> anyhow::Result<Issued> {
+    let (clock, check) = build(cfg, book, entry)?;
+
+    register(cfg, client, &entry.template, check, &clock)
+}
+
+/// Отправляет заказчику ссылку на чек из файла.
+fn send_email(cfg: &Config, book: &Book, client: &AuthorizedClient, entry: &Entry, event: &Event) {
+    // Шаблон уже успешно использован для сборки чека.
+    let email = cfg.template(&entry.template).ok().and_then(|t| t.email);
+
+    mail::notify(
+        cfg,
+        book,
+        email.as_deref(),
+        &client.get_name(),
+        event,
+        &Clock::from(entry.now),
+    );
+}
+
+/// Регистрирует собранный чек, записывает его в журнал и запускает хуки.
+/// Если сервис недоступен, то чек ставится в очередь.
+pub fn register(
+    cfg: &Config,
+    client: &mut AuthorizedClient,
+    template: &str,
+    check: Check,
+    clock: &Clock,
+) -> anyhow::Result<Issued> {
+    match client.register_income(check.clone()) {
+        Ok(receipt) => {
+            let event = Event::new(template, check, receipt, client.get_inn(), clock);
+            ledger::record(&cfg.ledger_path, &event);
+            hooks::run(&cfg.hooks, &cfg.http, &event, clock);
+
+            Ok(Issued::Event(event))
+        }
+        Err(e) if queue::is_temporary(&e) => {
+            error!("Не удалось выписать чек: {:#}", e);
+
+            let item = queue::Item::new(template, check, &e);
+            queue::push(&cfg.queue_path, item.clone())?;
+
+            Ok(Issued::Queued(item))
+        }
+        Err(e) => Err(e),
+    }
+}
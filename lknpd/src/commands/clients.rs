@@ -0,0 +1,103 @@
+use std::path::PathBuf;
+
+use log::debug;
+use serde_json::json;
+
+use crate::{
+    clients::{self, Client, Kind},
+    config,
+    output::Output,
+};
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[arg(short = 'c', long, env = "LKNPD_CONFIG")]
+    config_path: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    #[command(about = "Adds a client to the address book")]
+    #[command(long_about = None)]
+    Add {
+        #[arg()]
+        name: String,
+
+        #[arg()]
+        inn: String,
+
+        #[arg(long, value_enum, default_value_t = Kind::Organization)]
+        kind: Kind,
+
+        #[arg(long)]
+        phone: Option<String>,
+
+        #[arg(long)]
+        #[arg(help = "Email to send receipt links to with --send-email")]
+        email: Option<String>,
+    },
+
+    #[command(about = "Lists all clients from the address book")]
+    #[command(long_about = None)]
+    List,
+
+    #[command(about = "Removes a client by INN or name")]
+    #[command(long_about = None)]
+    Remove {
+        #[arg()]
+        client: String,
+    },
+}
+
+/// Исполняет команды для управления адресной книгой заказчиков.
+pub fn run(args: Args, output: Output) -> anyhow::Result<()> {
+    let cfg = config::load(args.config_path)?;
+
+    debug!("Подгружаем адресную книгу из {:?}", cfg.clients_path);
+    let mut book = clients::load(&cfg.clients_path)?;
+
+    // Результат выводится только после сохранения адресной книги.
+    let (result, message) = match args.command {
+        Command::Add {
+            name,
+            inn,
+            kind,
+            phone,
+            email,
+        } => {
+            let client = Client {
+                name,
+                inn,
+                kind,
+                phone,
+                email,
+            };
+            book.add(client.clone())?;
+            let message = format!("Добавлен заказчик {}", client);
+            (json!({ "added": client }), message)
+        }
+        Command::List => {
+            return output.print(&json!({ "clients": book.clients }), || {
+                for c in &book.clients {
+                    match &c.phone {
+                        Some(phone) => println!("{}\t{}\t{}\t{}", c.inn, c.kind, c.name, phone),
+                        None => println!("{}\t{}\t{}", c.inn, c.kind, c.name),
+                    }
+                }
+            });
+        }
+        Command::Remove { client } => {
+            let removed = book.remove(&client)?;
+            let message = format!("Удалён заказчик {}", removed);
+            (json!({ "removed": removed }), message)
+        }
+    };
+
+    debug!("Сохраняем адресную книгу в {:?}", cfg.clients_path);
+    clients::save(&book, &cfg.clients_path)?;
+
+    output.print(&result, || println!("{}", message))
+}
@@ -0,0 +1,112 @@
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use log::{debug, warn};
+use serde_json::json;
+
+use crate::{
+    config::{self, Sources},
+    output::Output,
+};
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[arg(short = 'c', long, env = "LKNPD_CONFIG")]
+    config_path: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    #[command(about = "Checks the config and reports every problem with its line and column")]
+    #[command(long_about = None)]
+    Check,
+
+    #[command(about = "Prints config files and environment variables the config is loaded from")]
+    #[command(long_about = None)]
+    Path,
+}
+
+/// Исполняет команды для работы с конфигом.
+pub fn run(args: Args, output: Output) -> anyhow::Result<()> {
+    match args.command {
+        Command::Check => check(args.config_path, output),
+        Command::Path => path(args.config_path, output),
+    }
+}
+
+fn check(path: Option<PathBuf>, output: Output) -> anyhow::Result<()> {
+    let files = Sources::new(path.clone()).files()?;
+
+    debug!("Проверяем конфиги {:?}", files);
+    let problems = config::check(path)?;
+
+    let value = json!({
+        "files": files,
+        "valid": problems.is_empty(),
+        "problems": problems,
+    });
+
+    output.print(&value, || {
+        if problems.is_empty() {
+            for file in &files {
+                println!("{}: ok", file.display());
+            }
+        }
+
+        for p in &problems {
+            println!("{}", p);
+        }
+    })?;
+
+    if !problems.is_empty() {
+        return Err(anyhow!("config has {} problems", problems.len()));
+    }
+
+    Ok(())
+}
+
+fn path(path: Option<PathBuf>, output: Output) -> anyhow::Result<()> {
+    let sources = Sources::new(path.clone());
+
+    // Источники полезно увидеть и тогда, когда конфиг не загружается.
+    let cfg = config::load(path)
+        .map_err(|e| warn!("Не удалось загрузить конфиг: {:#}", e))
+        .ok();
+
+    let file = |path: &PathBuf| json!({ "path": path, "exists": path.exists() });
+
+    let value = json!({
+        "user": sources.user.as_ref().map(file),
+        "local": file(&sources.local),
+        "env": sources.overrides,
+        "profile": cfg.as_ref().and_then(|c| c.profile.clone()),
+        "state_path": cfg.as_ref().map(|c| &c.state_path),
+    });
+
+    output.print(&value, || {
+        let describe = |path: &PathBuf| match path.exists() {
+            true => path.display().to_string(),
+            false => format!("{} (not found)", path.display()),
+        };
+
+        match &sources.user {
+            Some(path) => println!("user:  {}", describe(path)),
+            None => println!("user:  HOME is not set"),
+        }
+        println!("local: {}", describe(&sources.local));
+
+        for o in &sources.overrides {
+            println!("env:   {}={}", o.name, o.value);
+        }
+
+        if let Some(cfg) = &cfg {
+            if let Some(profile) = &cfg.profile {
+                println!("profile: {}", profile);
+            }
+            println!("state: {}", cfg.state_path.display());
+        }
+    })
+}
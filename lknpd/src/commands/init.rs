@@ -0,0 +1,89 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::anyhow;
+use inquire::Text;
+use log::{debug, warn};
+use serde_json::json;
+
+use crate::{
+    commands::templates,
+    config::{self, Sources},
+    output::Output,
+};
+
+/// Начало нового конфига. Остальные настройки описаны в README.
+const HEADER: &str = "# Конфиг lknpd, все настройки описаны в README.
+
+# Файл, в котором хранятся токены авторизации и запомненные значения переменных.
+# Рядом с ним по-умолчанию создаются адресная книга, журнал и очередь чеков.
+# По-умолчанию $XDG_STATE_HOME/lknpd/state.json.";
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[arg(short = 'c', long, env = "LKNPD_CONFIG")]
+    config_path: Option<PathBuf>,
+
+    #[arg(long)]
+    #[arg(help = "Overwrite the config if it already exists")]
+    force: bool,
+}
+
+/// Интерактивно создаёт конфиг с первым шаблоном.
+pub fn run(args: Args, output: Output) -> anyhow::Result<()> {
+    let sources = Sources::new(args.config_path.clone());
+
+    // Без явного пути создаётся конфиг пользователя, чтобы он не зависел от
+    // текущего каталога.
+    let path = match (&args.config_path, &sources.user) {
+        (Some(path), _) | (None, Some(path)) => path.clone(),
+        (None, None) => return Err(anyhow!("HOME is not set, pass the config path with -c")),
+    };
+
+    if path.exists() && !args.force {
+        return Err(anyhow!(
+            "config {:?} already exists, use --force to overwrite it",
+            path
+        ));
+    }
+
+    let mut prompt =
+        Text::new("Файл состояния").with_help_message("в нём хранятся токены авторизации");
+    let default = sources
+        .default_state_path(None)
+        .ok()
+        .map(|p| p.display().to_string());
+    if let Some(default) = &default {
+        prompt = prompt.with_default(default);
+    }
+    let state_path = prompt.prompt()?;
+
+    println!("Теперь опишем первый шаблон чека");
+    let (name, definition) = templates::prompt_template(Vec::new(), Vec::new())?;
+
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+
+    debug!("Сохраняем конфиг в {:?}", path);
+    // Путь по-умолчанию не записывается, чтобы он менялся вместе с профилем.
+    let state = toml::Value::String(state_path.clone());
+    let state = match default.as_ref() == Some(&state_path) {
+        true => format!("# state_path = {}", state),
+        false => format!("state_path = {}", state),
+    };
+    fs::write(&path, format!("{}\n{}\n", HEADER, state))?;
+    config::append_template(&path, &name, &definition)?;
+
+    // Шаблон проверяется при вводе, но лучше убедиться, что конфиг читается.
+    for problem in config::check(Some(path.clone()))? {
+        warn!("Проблема в созданном конфиге: {}", problem);
+    }
+
+    output.print(&json!({ "config_path": path, "template": name }), || {
+        println!("Конфиг создан в {:?}", path);
+        match &args.config_path {
+            Some(_) => println!("Выписать первый чек: lknpd check -c {:?} {}", path, name),
+            None => println!("Выписать первый чек: lknpd check {}", name),
+        }
+    })
+}
@@ -0,0 +1,226 @@
+use std::{io, path::PathBuf};
+
+use chrono::{DateTime, Datelike, Days, FixedOffset, NaiveDate, TimeZone};
+use log::{debug, info};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{
+    clock::Clock,
+    config,
+    ledger::{Entry, Group, Ledger, Row},
+    output::Output,
+    state,
+};
+
+#[derive(clap::Args)]
+pub struct SyncArgs {
+    #[arg(short = 'c', long, env = "LKNPD_CONFIG")]
+    config_path: Option<PathBuf>,
+
+    #[arg(long)]
+    #[arg(help = "First day of the period, by default January 1 of the current year")]
+    from: Option<NaiveDate>,
+
+    #[arg(long)]
+    #[arg(help = "Last day of the period, by default today")]
+    to: Option<NaiveDate>,
+}
+
+#[derive(clap::Args)]
+pub struct ReportArgs {
+    #[arg(short = 'c', long, env = "LKNPD_CONFIG")]
+    config_path: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = Group::Month)]
+    #[arg(help = "How to group checks")]
+    by: Group,
+
+    #[arg(long)]
+    #[arg(help = "First day of the period, by default January 1 of the current year")]
+    from: Option<NaiveDate>,
+
+    #[arg(long)]
+    #[arg(help = "Last day of the period, by default today")]
+    to: Option<NaiveDate>,
+
+    #[arg(long)]
+    #[arg(help = "Print the report as CSV")]
+    csv: bool,
+}
+
+/// Загружает в журнал все чеки за период из АПИ налоговой, включая
+/// выписанные не через lknpd, и обновляет информацию об аннулировании.
+pub fn sync(args: SyncArgs, output: Output) -> anyhow::Result<()> {
+    let cfg = config::load(args.config_path)?;
+
+    debug!("Подгружаем состояние из {:?}", cfg.state_path);
+    let mut state = state::load(&cfg.state_path)?;
+
+    let (from, to) = period(args.from, args.to);
+
+    let mut client = crate::get_client(&state, &cfg.http)?;
+    let incomes = client.incomes(from, to, usize::MAX);
+
+    debug!("Синхронизируем состояние с актуальными данными");
+    state.access_token = Some(client.get_access_token());
+    state.refresh_token = Some(client.get_refresh_token());
+    state.taxpayer_identification_number = Some(client.get_inn());
+
+    debug!("Сохраняем состояние в {:?}", cfg.state_path);
+    state::save(&state, &cfg.state_path)?;
+
+    let incomes = incomes?;
+
+    debug!("Открываем журнал {:?}", cfg.ledger_path);
+    let ledger = Ledger::open(&cfg.ledger_path)?;
+
+    let mut added = 0;
+    for income in &incomes {
+        let entry = Entry::from(income);
+
+        if ledger.get(&entry.uuid)?.is_none() {
+            added += 1;
+        }
+        ledger.save(&entry)?;
+    }
+
+    info!("Загружено {} чеков, из них новых {}", incomes.len(), added);
+
+    output.print(
+        &json!({ "from": from, "to": to, "synced": incomes.len(), "added": added }),
+        || println!("Загружено чеков: {}, новых: {}", incomes.len(), added),
+    )
+}
+
+/// Печатает отчёт по чекам из журнала.
+pub fn report(args: ReportArgs, output: Output) -> anyhow::Result<()> {
+    let cfg = config::load(args.config_path)?;
+
+    let (from, to) = period(args.from, args.to);
+
+    debug!("Открываем журнал {:?}", cfg.ledger_path);
+    let ledger = Ledger::open(&cfg.ledger_path)?;
+
+    let rows = ledger.report(args.by, from, to)?;
+
+    if args.csv {
+        return write_csv(&rows, io::stdout());
+    }
+
+    output.print(&json!({ "from": from, "to": to, "rows": rows }), || {
+        print_table(&rows)
+    })
+}
+
+/// Возвращает границы периода: с начала первого дня до начала дня, следующего
+/// за последним.
+fn period(
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> (DateTime<FixedOffset>, DateTime<FixedOffset>) {
+    let now = Clock::System.now();
+
+    let to = to.unwrap_or(now.date_naive());
+    let from = from
+        .or_else(|| NaiveDate::from_ymd_opt(to.year(), 1, 1))
+        .unwrap_or(to);
+
+    // Смещение фиксированное, поэтому время всегда однозначно.
+    let start = |d: NaiveDate| {
+        now.offset()
+            .from_local_datetime(&d.and_hms_opt(0, 0, 0).unwrap_or_default())
+            .unwrap()
+    };
+
+    (start(from), start(to + Days::new(1)))
+}
+
+fn print_table(rows: &[Row]) {
+    let mut table = vec![[
+        "Группа".to_owned(),
+        "Чеков".to_owned(),
+        "Аннулировано".to_owned(),
+        "Сумма".to_owned(),
+    ]];
+
+    for r in rows {
+        table.push([
+            r.group.clone(),
+            r.checks.to_string(),
+            r.cancelled.to_string(),
+            r.total.to_string(),
+        ]);
+    }
+
+    let width = |i: usize| {
+        table
+            .iter()
+            .map(|r| r[i].chars().count())
+            .max()
+            .unwrap_or_default()
+    };
+    let widths = [width(0), width(1), width(2), width(3)];
+
+    for [group, checks, cancelled, total] in &table {
+        println!(
+            "{:<w0$}  {:>w1$}  {:>w2$}  {:>w3$}",
+            group,
+            checks,
+            cancelled,
+            total,
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+        );
+    }
+}
+
+/// Печатает строки отчёта в CSV с заголовком.
+pub fn write_csv<T: Serialize>(rows: &[T], writer: impl io::Write) -> anyhow::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+
+    for r in rows {
+        writer.serialize(r)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    #[test]
+    fn report_csv() {
+        let rows = [
+            Row {
+                group: "ООО \"Ромашка\" (1234567890)".to_owned(),
+                checks: 2,
+                cancelled: 1,
+                total: Decimal::new(25005, 1),
+            },
+            Row {
+                group: "физ. лицо".to_owned(),
+                checks: 1,
+                cancelled: 0,
+                total: Decimal::from(700),
+            },
+        ];
+
+        let mut buf = Vec::new();
+        write_csv(&rows, &mut buf).unwrap();
+
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "group,checks,cancelled,total\n\
+             \"ООО \"\"Ромашка\"\" (1234567890)\",2,1,2500.5\n\
+             физ. лицо,1,0,700\n"
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use log::{debug, error, warn};
+use serde_json::json;
+
+use crate::{
+    clock::Clock,
+    config::{self, Config},
+    hooks::{self, Event},
+    ledger,
+    output::Output,
+    queue::{self, Queue},
+    state,
+};
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[arg(short = 'c', long, env = "LKNPD_CONFIG")]
+    config_path: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    #[command(about = "Lists queued checks with their deadlines")]
+    #[command(long_about = None)]
+    List,
+
+    #[command(about = "Issues all queued checks with their original time")]
+    #[command(long_about = None)]
+    Flush,
+
+    #[command(about = "Removes a check from the queue without issuing it")]
+    #[command(long_about = None)]
+    Drop {
+        #[arg()]
+        id: String,
+    },
+}
+
+/// Исполняет команды для управления очередью чеков.
+pub fn run(args: Args, output: Output) -> anyhow::Result<()> {
+    let cfg = config::load(args.config_path)?;
+
+    debug!("Подгружаем очередь из {:?}", cfg.queue_path);
+    let mut queue = queue::load(&cfg.queue_path)?;
+
+    match args.command {
+        Command::List => output.print(&json!({ "items": queue.items }), || {
+            for item in &queue.items {
+                println!(
+                    "{}\t{}\t{}\tдо {}\t{}",
+                    item.id,
+                    item.check.date.format("%FT%X%:z"),
+                    item.template,
+                    item.deadline().format("%FT%X%:z"),
+                    item.error
+                );
+            }
+        }),
+        Command::Flush => flush(&cfg, &mut queue, output),
+        Command::Drop { id } => {
+            let item = queue.remove(&id)?;
+
+            debug!("Сохраняем очередь в {:?}", cfg.queue_path);
+            queue::save(&queue, &cfg.queue_path)?;
+
+            output.print(&json!({ "dropped": item }), || {
+                println!("Чек {} удалён из очереди", item.id)
+            })
+        }
+    }
+}
+
+/// Выписывает чеки из очереди в порядке их добавления с исходным временем.
+/// Если сервис всё ещё недоступен, то оставшиеся чеки остаются в очереди.
+fn flush(cfg: &Config, queue: &mut Queue, output: Output) -> anyhow::Result<()> {
+    if queue.items.is_empty() {
+        return output.print(
+            &json!({ "issued": [], "failed": [], "remaining": 0 }),
+            || println!("Очередь пуста"),
+        );
+    }
+
+    debug!("Подгружаем состояние из {:?}", cfg.state_path);
+    let mut state = state::load(&cfg.state_path)?;
+
+    let mut client = crate::get_client(&state, &cfg.http)?;
+    let now = Clock::System.now();
+
+    let mut issued: Vec<Event> = Vec::new();
+    let mut failed: Vec<String> = Vec::new();
+
+    for item in queue.items.clone() {
+        if item.deadline() < now {
+            warn!(
+                "Срок выписки чека {} истёк {}",
+                item.id,
+                item.deadline().format("%FT%X%:z")
+            );
+        }
+
+        match client.register_income(item.check.clone()) {
+            Ok(receipt) => {
+                let event = Event::new(
+                    &item.template,
+                    item.check,
+                    receipt,
+                    client.get_inn(),
+                    &Clock::System,
+                );
+
+                if output == Output::Text {
+                    println!("Чек {} доступен по URL: {}", item.id, event.url);
+                }
+
+                // Функции в сообщениях хуков вычисляются относительно
+                // исходного времени чека, как и сам чек.
+                ledger::record(&cfg.ledger_path, &event);
+                hooks::run(
+                    &cfg.hooks,
+                    &cfg.http,
+                    &event,
+                    &Clock::Fixed(event.check.date),
+                );
+
+                queue.remove(&item.id)?;
+                issued.push(event);
+            }
+            Err(e) if queue::is_temporary(&e) => {
+                error!("Сервис всё ещё недоступен: {:#}", e);
+                failed.push(item.id);
+                break;
+            }
+            Err(e) => {
+                error!("Не удалось выписать чек {}: {:#}", item.id, e);
+                failed.push(item.id);
+            }
+        }
+
+        // Сохраняем очередь после каждого чека, чтобы при падении не
+        // выписать его повторно.
+        debug!("Сохраняем очередь в {:?}", cfg.queue_path);
+        queue::save(queue, &cfg.queue_path)?;
+    }
+
+    debug!("Синхронизируем состояние с актуальными данными");
+    state.access_token = Some(client.get_access_token());
+    state.refresh_token = Some(client.get_refresh_token());
+    state.taxpayer_identification_number = Some(client.get_inn());
+
+    debug!("Сохраняем состояние в {:?}", cfg.state_path);
+    state::save(&state, &cfg.state_path)?;
+
+    let remaining = queue.items.len();
+    output.print(
+        &json!({ "issued": issued, "failed": failed, "remaining": remaining }),
+        || {},
+    )?;
+
+    if !failed.is_empty() {
+        return Err(anyhow!(
+            "failed to issue {} checks, {} left in queue",
+            failed.len(),
+            remaining
+        ));
+    }
+
+    Ok(())
+}
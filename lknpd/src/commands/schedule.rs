@@ -0,0 +1,195 @@
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use chrono::{DateTime, Days, FixedOffset, TimeZone};
+use log::{debug, error, info};
+use serde_json::json;
+
+use crate::{
+    api::AuthorizedClient,
+    cli,
+    clients::{self, Book},
+    clock::Clock,
+    config::{self, Config},
+    hooks::{self, Event},
+    ledger,
+    output::Output,
+    schedule::Schedule,
+    state,
+    template::compiled,
+};
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[arg(short = 'c', long, env = "LKNPD_CONFIG")]
+    config_path: Option<PathBuf>,
+
+    #[arg(long, env = "LKNPD_NOW")]
+    #[arg(help = "Time used as current, e.g. 2024-03-31T18:00:00+03:00")]
+    now: Option<DateTime<FixedOffset>>,
+
+    #[arg(long)]
+    #[arg(help = "Only print due checks without issuing them")]
+    dry_run: bool,
+}
+
+/// Выписывает все чеки по расписанию, время которых уже наступило.
+///
+/// Для каждого расписания в состоянии хранится момент, за который был выписан
+/// последний чек, поэтому каждый чек выписывается ровно один раз, а пропущенные
+/// запуски наверстываются при следующем. Если расписание запускается впервые,
+/// то выписывается только чек за последний наступивший момент.
+pub fn run_due(args: Args, output: Output) -> anyhow::Result<()> {
+    let cfg = config::load(args.config_path)?;
+
+    debug!("Подгружаем состояние из {:?}", cfg.state_path);
+    let mut state = state::load(&cfg.state_path)?;
+
+    debug!("Подгружаем адресную книгу из {:?}", cfg.clients_path);
+    let book = clients::load(&cfg.clients_path)?;
+
+    let now = Clock::from(args.now).now();
+
+    let mut names: Vec<&String> = cfg.schedule.keys().collect();
+    names.sort();
+
+    let mut due = Vec::new();
+
+    for name in names {
+        let rule = &cfg.schedule[name].rule;
+
+        let occurrences = match state.schedule.get(name) {
+            Some(last) => rule.occurrences(
+                last.with_timezone(now.offset()).naive_local(),
+                now.naive_local(),
+            ),
+            None => {
+                let since = now.naive_local() - Days::new(366);
+                rule.occurrences(since, now.naive_local())
+                    .pop()
+                    .into_iter()
+                    .collect()
+            }
+        };
+
+        debug!(
+            "Для расписания {} наступило {} чеков",
+            name,
+            occurrences.len()
+        );
+
+        for at in occurrences {
+            // Смещение фиксированное, поэтому время всегда однозначно.
+            let at = now.offset().from_local_datetime(&at).unwrap();
+            due.push((name, at));
+        }
+    }
+
+    if due.is_empty() {
+        info!("Нет чеков, которые нужно выписать");
+    }
+
+    if due.is_empty() || args.dry_run {
+        let value = json!({
+            "due": due
+                .iter()
+                .map(|(name, at)| json!({ "schedule": name, "at": at }))
+                .collect::<Vec<_>>(),
+        });
+
+        return output.print(&value, || {
+            for (name, at) in &due {
+                println!("{}: {}", name, at.format("%FT%X%:z"));
+            }
+        });
+    }
+
+    let mut client = crate::get_client(&state, &cfg.http)?;
+    let mut failed: Vec<&String> = Vec::new();
+    let mut issued = Vec::new();
+
+    for (name, at) in due {
+        // Не выписываем более поздние чеки по расписанию, если не удалось
+        // выписать более ранний, чтобы не потерять его.
+        if failed.contains(&name) {
+            continue;
+        }
+
+        match issue(&cfg, &cfg.schedule[name], &book, &mut client, at) {
+            Ok(event) => {
+                if output == Output::Text {
+                    println!(
+                        "{}: чек за {} доступен по URL: {}",
+                        name,
+                        at.format("%FT%X%:z"),
+                        event.url
+                    );
+                }
+
+                state.schedule.insert(name.clone(), at);
+                issued.push(json!({ "schedule": name, "at": at, "check": event }));
+            }
+            Err(e) => {
+                error!("Не удалось выписать чек {} за {}: {:#}", name, at, e);
+                failed.push(name);
+            }
+        }
+
+        // Сохраняем состояние после каждого чека, чтобы при падении не
+        // выписать его повторно.
+        state.access_token = Some(client.get_access_token());
+        state.refresh_token = Some(client.get_refresh_token());
+        state.taxpayer_identification_number = Some(client.get_inn());
+
+        debug!("Сохраняем состояние в {:?}", cfg.state_path);
+        state::save(&state, &cfg.state_path)?;
+    }
+
+    output.print(&json!({ "issued": issued, "failed": failed }), || {})?;
+
+    if !failed.is_empty() {
+        return Err(anyhow!(
+            "failed to issue checks for {} schedules",
+            failed.len()
+        ));
+    }
+
+    Ok(())
+}
+
+fn issue(
+    cfg: &Config,
+    schedule: &Schedule,
+    book: &Book,
+    client: &mut AuthorizedClient,
+    at: DateTime<FixedOffset>,
+) -> anyhow::Result<Event> {
+    let raw = cfg.template(&schedule.template)?;
+
+    // Функции в шаблоне вычисляются относительно момента срабатывания
+    // расписания, так что наверстанные чеки получат правильные даты и периоды.
+    let tmpl = compiled::Template::new(raw, Clock::Fixed(at))?.with_rates(cfg.rates());
+
+    let values = cli::fill(
+        tmpl.get_fields(),
+        &schedule.values,
+        &book.clients,
+        &Clock::Fixed(at),
+    )?;
+
+    let check = tmpl.build_check(&values)?;
+
+    let receipt = client.register_income(check.clone())?;
+
+    let event = Event::new(
+        &schedule.template,
+        check,
+        receipt,
+        client.get_inn(),
+        &Clock::System,
+    );
+    ledger::record(&cfg.ledger_path, &event);
+    hooks::run(&cfg.hooks, &cfg.http, &event, &Clock::Fixed(at));
+
+    Ok(event)
+}
@@ -0,0 +1,437 @@
+use std::{collections::HashMap, io::Read, path::PathBuf, sync::Mutex, thread};
+
+use anyhow::anyhow;
+use chrono::{DateTime, Days, FixedOffset, NaiveDate, TimeZone};
+use log::{debug, error, info};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{
+    api::{AuthorizedClient, CancelReason},
+    cli, clients,
+    clock::Clock,
+    config::{self, Config},
+    hooks, ledger,
+    output::Output,
+    state,
+    template::compiled::{self, FieldValues},
+};
+
+/// Максимальный размер тела запроса.
+const MAX_BODY_SIZE: u64 = 1024 * 1024;
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[arg(short = 'c', long, env = "LKNPD_CONFIG")]
+    config_path: Option<PathBuf>,
+
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    #[arg(help = "Address to listen on")]
+    listen: String,
+
+    #[arg(long, env = "LKNPD_SERVE_TOKEN", hide_env_values = true)]
+    #[arg(help = "Token which clients should pass in the `Authorization: Bearer` header")]
+    token: String,
+
+    #[arg(long, default_value_t = 4)]
+    #[arg(help = "Number of threads handling requests")]
+    workers: usize,
+}
+
+/// Запускает локальный HTTP сервер с JSON АПИ для выписки чеков.
+///
+/// Все обращения к АПИ налоговой идут через один авторизованный клиент под
+/// мьютексом, чтобы обновление токенов не происходило одновременно из разных
+/// запросов, а после каждого обращения токены сохраняются в состояние.
+pub fn run(args: Args, output: Output) -> anyhow::Result<()> {
+    if args.token.trim().is_empty() {
+        return Err(anyhow!("token shouldn't be empty"));
+    }
+
+    let cfg = config::load(args.config_path)?;
+
+    debug!("Подгружаем состояние из {:?}", cfg.state_path);
+    let state = state::load(&cfg.state_path)?;
+
+    let client = crate::get_client(&state, &cfg.http)?;
+    save_tokens(&client, &cfg)?;
+
+    let server =
+        Server::http(&args.listen).map_err(|e| anyhow!("listen on {}: {}", args.listen, e))?;
+
+    info!("Слушаем на {}", args.listen);
+    output.print(&json!({ "listen": args.listen }), || {})?;
+
+    let service = Service {
+        cfg,
+        token: args.token,
+        client: Mutex::new(client),
+    };
+
+    thread::scope(|s| {
+        for _ in 0..args.workers.max(1) {
+            s.spawn(|| loop {
+                match server.recv() {
+                    Ok(request) => service.handle(request),
+                    Err(e) => {
+                        error!("Не удалось принять запрос: {}", e);
+                        break;
+                    }
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+struct Service {
+    cfg: Config,
+    token: String,
+    client: Mutex<AuthorizedClient>,
+}
+
+/// Маршрут запроса.
+#[derive(Debug, PartialEq, Eq)]
+enum Route {
+    Health,
+    Issue,
+    List,
+    Cancel(String),
+    NotFound,
+    MethodNotAllowed,
+}
+
+#[derive(Deserialize)]
+struct IssueRequest {
+    template: String,
+
+    #[serde(default)]
+    values: FieldValues,
+
+    #[serde(default)]
+    now: Option<DateTime<FixedOffset>>,
+}
+
+#[derive(Deserialize)]
+struct CancelRequest {
+    reason: CancelReason,
+}
+
+/// Ошибка обработки запроса, которая будет отдана клиенту.
+#[derive(Debug)]
+struct HttpError {
+    status: u16,
+    message: String,
+}
+
+impl HttpError {
+    fn new(status: u16, e: impl std::fmt::Display) -> Self {
+        Self {
+            status,
+            message: format!("{:#}", e),
+        }
+    }
+}
+
+impl Service {
+    fn handle(&self, mut request: Request) {
+        let route = route(request.method(), request.url());
+        debug!(
+            "Запрос {} {} -> {:?}",
+            request.method(),
+            request.url(),
+            route
+        );
+
+        let result = if route != Route::Health && !self.is_authorized(&request) {
+            Err(HttpError::new(401, "unauthorized"))
+        } else {
+            self.dispatch(route, &mut request)
+        };
+
+        let (status, body) = match result {
+            Ok(v) => v,
+            Err(e) => {
+                if e.status >= 500 {
+                    error!("Не удалось обработать {}: {}", request.url(), e.message);
+                }
+                (e.status, json!({ "error": e.message }))
+            }
+        };
+
+        let header = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+            .expect("valid header");
+        let response = Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header(header);
+
+        if let Err(e) = request.respond(response) {
+            error!("Не удалось отправить ответ: {}", e);
+        }
+    }
+
+    fn dispatch(&self, route: Route, request: &mut Request) -> Result<(u16, Value), HttpError> {
+        match route {
+            Route::Health => Ok((200, json!({ "status": "ok" }))),
+            Route::Issue => {
+                let req: IssueRequest = read_json(request)?;
+                self.issue(req).map(|v| (201, v))
+            }
+            Route::List => {
+                let query = parse_query(request.url());
+                self.list(&query).map(|v| (200, v))
+            }
+            Route::Cancel(uuid) => {
+                let body = read_body(request)?;
+                let reason = if body.trim().is_empty() {
+                    CancelReason::Mistake
+                } else {
+                    serde_json::from_str::<CancelRequest>(&body)
+                        .map_err(|e| HttpError::new(400, e))?
+                        .reason
+                };
+                self.with_client(|c| c.cancel_income(&uuid, reason))?;
+                ledger::record_cancel(&self.cfg.ledger_path, &uuid, reason);
+                Ok((200, json!({ "uuid": uuid, "cancelled": true })))
+            }
+            Route::NotFound => Err(HttpError::new(404, "not found")),
+            Route::MethodNotAllowed => Err(HttpError::new(405, "method not allowed")),
+        }
+    }
+
+    fn issue(&self, req: IssueRequest) -> Result<Value, HttpError> {
+        let raw = self
+            .cfg
+            .template(&req.template)
+            .map_err(|e| HttpError::new(404, e))?;
+
+        let clock = Clock::from(req.now);
+        let tmpl = compiled::Template::new(raw, clock)
+            .map_err(|e| HttpError::new(400, e))?
+            .with_rates(self.cfg.rates());
+
+        // Адресную книгу перечитываем, чтобы видеть заказчиков, добавленных
+        // после запуска сервера.
+        let book = clients::load(&self.cfg.clients_path).map_err(|e| HttpError::new(500, e))?;
+
+        let values = cli::fill(tmpl.get_fields(), &req.values, &book.clients, &clock)
+            .map_err(|e| HttpError::new(400, e))?;
+
+        let check = tmpl
+            .build_check(&values)
+            .map_err(|e| HttpError::new(400, e))?;
+
+        let (receipt, inn) =
+            self.with_client(|c| Ok((c.register_income(check.clone())?, c.get_inn())))?;
+
+        info!("Выписан чек {} по шаблону {}", receipt.uuid, req.template);
+
+        let response = json!({ "uuid": receipt.uuid, "url": receipt.url });
+
+        let event = hooks::Event::new(&req.template, check, receipt, inn, &clock);
+        ledger::record(&self.cfg.ledger_path, &event);
+        hooks::run(&self.cfg.hooks, &self.cfg.http, &event, &clock);
+
+        Ok(response)
+    }
+
+    fn list(&self, query: &HashMap<String, String>) -> Result<Value, HttpError> {
+        let now = Clock::System.now();
+
+        let date = |name: &str| -> Result<Option<NaiveDate>, HttpError> {
+            query
+                .get(name)
+                .map(|v| NaiveDate::parse_from_str(v, "%Y-%m-%d"))
+                .transpose()
+                .map_err(|e| HttpError::new(400, anyhow!("{}: {}", name, e)))
+        };
+
+        let to = date("to")?.unwrap_or(now.date_naive());
+        let from = date("from")?.unwrap_or(to - Days::new(30));
+
+        let limit = match query.get("limit") {
+            Some(l) => l
+                .parse::<usize>()
+                .map_err(|e| HttpError::new(400, anyhow!("limit: {}", e)))?,
+            None => 50,
+        };
+
+        let start = |d: NaiveDate| -> DateTime<FixedOffset> {
+            now.offset()
+                .from_local_datetime(&d.and_hms_opt(0, 0, 0).unwrap_or_default())
+                .unwrap()
+        };
+
+        let incomes = self
+            .with_client(|c| c.incomes(start(from), start(to + Days::new(1)), limit.min(1000)))?;
+
+        Ok(json!({ "checks": incomes }))
+    }
+
+    /// Выполняет запрос к АПИ налоговой с эксклюзивным доступом к клиенту и
+    /// сохраняет токены после него.
+    fn with_client<T>(
+        &self,
+        f: impl FnOnce(&mut AuthorizedClient) -> anyhow::Result<T>,
+    ) -> Result<T, HttpError> {
+        let mut client = self
+            .client
+            .lock()
+            .map_err(|_| HttpError::new(500, "client lock is poisoned"))?;
+
+        let result = f(&mut client);
+
+        if let Err(e) = save_tokens(&client, &self.cfg) {
+            error!("Не удалось сохранить состояние: {:#}", e);
+        }
+
+        result.map_err(|e| HttpError::new(502, e))
+    }
+
+    fn is_authorized(&self, request: &Request) -> bool {
+        request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Authorization"))
+            .map(|h| is_valid_token(h.value.as_str(), &self.token))
+            .unwrap_or_default()
+    }
+}
+
+/// Сохраняет актуальные токены клиента в состояние.
+/// Состояние перечитывается, чтобы не затереть изменения, сделанные другими
+/// командами, пока сервер работал.
+fn save_tokens(client: &AuthorizedClient, cfg: &Config) -> anyhow::Result<()> {
+    let mut state = state::load(&cfg.state_path)?;
+
+    state.access_token = Some(client.get_access_token());
+    state.refresh_token = Some(client.get_refresh_token());
+    state.taxpayer_identification_number = Some(client.get_inn());
+
+    debug!("Сохраняем состояние в {:?}", cfg.state_path);
+    state::save(&state, &cfg.state_path)?;
+
+    Ok(())
+}
+
+fn route(method: &Method, url: &str) -> Route {
+    let path = url
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches('/');
+    let segments: Vec<&str> = path.split('/').skip(1).collect();
+
+    match (method, segments.as_slice()) {
+        (Method::Get, ["health"]) => Route::Health,
+        (Method::Post, ["checks"]) => Route::Issue,
+        (Method::Get, ["checks"]) => Route::List,
+        (Method::Post, ["checks", uuid, "cancel"]) => Route::Cancel(uuid.to_string()),
+        (_, ["health"]) | (_, ["checks"]) | (_, ["checks", _, "cancel"]) => Route::MethodNotAllowed,
+        _ => Route::NotFound,
+    }
+}
+
+/// Разбирает параметры запроса, раскодируя их как значения формы: `%2B`
+/// превращается в `+`, а `+` в пробел.
+fn parse_query(url: &str) -> HashMap<String, String> {
+    url.split_once('?')
+        .map(|(_, query)| {
+            url::form_urlencoded::parse(query.as_bytes())
+                .into_owned()
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Сравнивает токен из заголовка с ожидаемым за время, не зависящее от
+/// места первого несовпадения.
+fn is_valid_token(header: &str, token: &str) -> bool {
+    let Some(given) = header.strip_prefix("Bearer ") else {
+        return false;
+    };
+
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+fn read_body(request: &mut Request) -> Result<String, HttpError> {
+    let mut body = String::new();
+
+    request
+        .as_reader()
+        .take(MAX_BODY_SIZE)
+        .read_to_string(&mut body)
+        .map_err(|e| HttpError::new(400, e))?;
+
+    Ok(body)
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(request: &mut Request) -> Result<T, HttpError> {
+    let body = read_body(request)?;
+    serde_json::from_str(&body).map_err(|e| HttpError::new(400, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes() {
+        let cases = [
+            (Method::Get, "/health", Route::Health),
+            (Method::Post, "/checks", Route::Issue),
+            (Method::Get, "/checks/?from=2024-01-01", Route::List),
+            (
+                Method::Post,
+                "/checks/abc-123/cancel",
+                Route::Cancel("abc-123".to_owned()),
+            ),
+            (Method::Delete, "/checks", Route::MethodNotAllowed),
+            (
+                Method::Get,
+                "/checks/abc-123/cancel",
+                Route::MethodNotAllowed,
+            ),
+            (Method::Get, "/", Route::NotFound),
+            (Method::Get, "/checks/abc-123", Route::NotFound),
+        ];
+
+        for (method, url, expected) in cases {
+            assert_eq!(route(&method, url), expected, "{} {}", method, url);
+        }
+    }
+
+    #[test]
+    fn query() {
+        assert_eq!(
+            parse_query("/checks?from=2024-01-01&limit=10&flag"),
+            HashMap::from([
+                ("from".to_owned(), "2024-01-01".to_owned()),
+                ("limit".to_owned(), "10".to_owned()),
+                ("flag".to_owned(), String::new()),
+            ])
+        );
+        assert_eq!(
+            parse_query("/checks?from=2024-03-01T00%3A00%3A00%2B03%3A00")["from"],
+            "2024-03-01T00:00:00+03:00"
+        );
+        assert_eq!(parse_query("/checks?q=a+b")["q"], "a b");
+        assert!(parse_query("/checks").is_empty());
+    }
+
+    #[test]
+    fn tokens() {
+        assert!(is_valid_token("Bearer secret", "secret"));
+        assert!(!is_valid_token("Bearer secret2", "secret"));
+        assert!(!is_valid_token("Bearer sekret", "secret"));
+        assert!(!is_valid_token("secret", "secret"));
+        assert!(!is_valid_token("Basic secret", "secret"));
+    }
+}
@@ -0,0 +1,563 @@
+use std::{io, path::PathBuf};
+
+use anyhow::anyhow;
+use chrono::{DateTime, Days, FixedOffset, NaiveDate, TimeZone};
+use inquire::Select;
+use log::{debug, info, warn};
+use rust_decimal::Decimal;
+use serde_json::json;
+
+use crate::{
+    batch::{self, Batch, Entry},
+    cli,
+    clients::{self, Book},
+    clock::Clock,
+    commands::{
+        batch::{build, register, Issued},
+        ledger::write_csv,
+    },
+    config::{self, Config},
+    ledger::{Entry as LedgerEntry, Ledger},
+    output::Output,
+    state::{self, State},
+    statement::{self, Discrepancy, Payment, Row, Tolerance, MATCH_AMOUNT, MATCH_DAYS},
+    template::{
+        compiled::{self, ClientProperty, FieldValues, VariableKind},
+        raw::Counterparty,
+    },
+};
+
+#[derive(clap::Args)]
+pub struct ImportArgs {
+    #[arg(short = 'c', long, env = "LKNPD_CONFIG")]
+    config_path: Option<PathBuf>,
+
+    #[arg(long)]
+    #[arg(help = "Write checks for the payments to a batch file instead of issuing them")]
+    batch: Option<PathBuf>,
+
+    #[arg()]
+    #[arg(help = "Bank statement in the 1CClientBankExchange format or CSV")]
+    file: PathBuf,
+}
+
+#[derive(clap::Args)]
+pub struct ReconcileArgs {
+    #[arg(short = 'c', long, env = "LKNPD_CONFIG")]
+    config_path: Option<PathBuf>,
+
+    #[arg(long)]
+    #[arg(help = "Take checks from the tax service API instead of the local ledger")]
+    api: bool,
+
+    #[arg(long)]
+    #[arg(help = "First day of the period, by default the date of the first payment")]
+    from: Option<NaiveDate>,
+
+    #[arg(long)]
+    #[arg(help = "Last day of the period, by default the date of the last payment")]
+    to: Option<NaiveDate>,
+
+    #[arg(long, default_value_t = MATCH_AMOUNT)]
+    #[arg(help = "Allowed difference between the payment and the check amounts")]
+    amount_tolerance: Decimal,
+
+    #[arg(long, default_value_t = MATCH_DAYS)]
+    #[arg(help = "Allowed number of days between the payment and the check")]
+    days: u64,
+
+    #[arg(long)]
+    #[arg(help = "Print discrepancies as CSV")]
+    csv: bool,
+
+    #[arg()]
+    #[arg(help = "Bank statement in the 1CClientBankExchange format or CSV")]
+    file: PathBuf,
+}
+
+const SKIP: &str = "Пропустить";
+
+/// Шаблон, подходящий для платежа.
+struct Candidate {
+    template: String,
+
+    /// Переменная типа `client`, в которую подставляется плательщик.
+    client_variable: Option<String>,
+}
+
+/// Предлагает выписать чеки на поступления из банковской выписки, для которых
+/// ещё нет чеков в журнале.
+pub fn import(args: ImportArgs, output: Output) -> anyhow::Result<()> {
+    let cfg = config::load(args.config_path)?;
+
+    debug!("Подгружаем состояние из {:?}", cfg.state_path);
+    let state = state::load(&cfg.state_path)?;
+
+    debug!("Подгружаем адресную книгу из {:?}", cfg.clients_path);
+    let book = clients::load(&cfg.clients_path)?;
+
+    debug!("Подгружаем выписку из {:?}", args.file);
+    let payments: Vec<Payment> =
+        statement::load(&args.file, state.taxpayer_identification_number.as_deref())?
+            .into_iter()
+            .filter(|p| p.incoming)
+            .collect();
+
+    let payments = without_receipts(&cfg, payments)?;
+    info!("Поступлений без чеков: {}", payments.len());
+
+    match args.batch {
+        Some(path) => write_batch(&cfg, &book, &payments, path, output),
+        None => walk(&cfg, &book, state, &payments, output),
+    }
+}
+
+/// Сверяет чеки из журнала или АПИ налоговой с поступлениями из выписки и
+/// печатает платежи без чеков, чеки без платежей и несовпадения сумм.
+pub fn reconcile(args: ReconcileArgs, output: Output) -> anyhow::Result<()> {
+    let cfg = config::load(args.config_path)?;
+
+    debug!("Подгружаем состояние из {:?}", cfg.state_path);
+    let mut state = state::load(&cfg.state_path)?;
+
+    debug!("Подгружаем выписку из {:?}", args.file);
+    let payments: Vec<Payment> =
+        statement::load(&args.file, state.taxpayer_identification_number.as_deref())?
+            .into_iter()
+            .filter(|p| p.incoming)
+            .collect();
+
+    let from = args.from.or(payments.iter().map(|p| p.date).min());
+    let to = args.to.or(payments.iter().map(|p| p.date).max());
+
+    let (Some(from), Some(to)) = (from, to) else {
+        return Err(anyhow!(
+            "no incoming payments in the statement, specify the period"
+        ));
+    };
+
+    let payments: Vec<Payment> = payments
+        .into_iter()
+        .filter(|p| (from..=to).contains(&p.date))
+        .collect();
+
+    let tolerance = Tolerance {
+        amount: args.amount_tolerance,
+        days: args.days,
+    };
+
+    // Чеки берём с запасом, чтобы сопоставить платежи на границах периода.
+    let start = at(from - Days::new(tolerance.days), 0);
+    let end = at(to + Days::new(tolerance.days + 1), 0);
+
+    let entries: Vec<LedgerEntry> = if args.api {
+        let mut client = crate::get_client(&state, &cfg.http)?;
+        let incomes = client.incomes(start, end, usize::MAX);
+
+        debug!("Синхронизируем состояние с актуальными данными");
+        state.access_token = Some(client.get_access_token());
+        state.refresh_token = Some(client.get_refresh_token());
+        state.taxpayer_identification_number = Some(client.get_inn());
+
+        debug!("Сохраняем состояние в {:?}", cfg.state_path);
+        state::save(&state, &cfg.state_path)?;
+
+        incomes?.iter().map(LedgerEntry::from).collect()
+    } else {
+        debug!("Открываем журнал {:?}", cfg.ledger_path);
+        Ledger::open(&cfg.ledger_path)?.entries(start, end)?
+    };
+
+    let mut result = statement::reconcile(&payments, &entries, &tolerance);
+
+    // Чеки за пределами периода нужны только для сопоставления, их платежи
+    // могут быть в другой выписке.
+    result
+        .rows
+        .retain(|r| r.discrepancy != Discrepancy::OrphanReceipt || (from..=to).contains(&r.date));
+
+    info!(
+        "Платежей: {}, сопоставлено с чеками: {}, расхождений: {}",
+        payments.len(),
+        result.matched,
+        result.rows.len()
+    );
+
+    if args.csv {
+        return write_csv(&result.rows, io::stdout());
+    }
+
+    output.print(
+        &json!({
+            "from": from,
+            "to": to,
+            "payments": payments.len(),
+            "matched": result.matched,
+            "rows": result.rows,
+        }),
+        || {
+            if result.rows.is_empty() {
+                println!("Расхождений нет");
+            } else {
+                print_table(&result.rows);
+            }
+        },
+    )
+}
+
+fn print_table(rows: &[Row]) {
+    let amount = |a: Option<Decimal>| a.map(|a| a.to_string()).unwrap_or_default();
+
+    let mut table = vec![[
+        "Расхождение".to_owned(),
+        "Дата".to_owned(),
+        "Платёж".to_owned(),
+        "Плательщик".to_owned(),
+        "Сумма платежа".to_owned(),
+        "Чек".to_owned(),
+        "Сумма чека".to_owned(),
+    ]];
+
+    for r in rows {
+        let payer = match (&r.payer, &r.payer_inn) {
+            (Some(payer), Some(inn)) => format!("{} ({})", payer, inn),
+            (None, Some(inn)) => inn.clone(),
+            (payer, None) => payer.clone().unwrap_or_default(),
+        };
+
+        table.push([
+            r.discrepancy.to_string(),
+            r.date.format("%d.%m.%Y").to_string(),
+            r.payment_number.clone().unwrap_or_default(),
+            payer,
+            amount(r.payment_amount),
+            r.receipt_uuid.clone().unwrap_or_default(),
+            amount(r.receipt_amount),
+        ]);
+    }
+
+    let widths: Vec<usize> = (0..table[0].len())
+        .map(|i| {
+            table
+                .iter()
+                .map(|r| r[i].chars().count())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    for row in &table {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+
+        println!("{}", line.join("  ").trim_end());
+    }
+}
+
+/// Убирает платежи, для которых в журнале уже есть чеки.
+fn without_receipts(cfg: &Config, payments: Vec<Payment>) -> anyhow::Result<Vec<Payment>> {
+    let (Some(first), Some(last)) = (
+        payments.iter().map(|p| p.date).min(),
+        payments.iter().map(|p| p.date).max(),
+    ) else {
+        return Ok(payments);
+    };
+
+    debug!("Открываем журнал {:?}", cfg.ledger_path);
+    let ledger = Ledger::open(&cfg.ledger_path)?;
+
+    let entries = ledger.entries(
+        at(first - Days::new(MATCH_DAYS), 0),
+        at(last + Days::new(MATCH_DAYS + 1), 0),
+    )?;
+
+    let matches = statement::match_receipts(&payments, &entries, &Tolerance::default());
+
+    Ok(payments
+        .into_iter()
+        .zip(matches)
+        .filter_map(|(p, m)| match m {
+            Some(i) => {
+                debug!(
+                    "Для платежа №{} от {} уже есть чек {}",
+                    p.number, p.date, entries[i].uuid
+                );
+                None
+            }
+            None => Some(p),
+        })
+        .collect())
+}
+
+/// Спрашивает у пользователя, по какому шаблону выписать чек на каждый платёж,
+/// и выписывает его.
+fn walk(
+    cfg: &Config,
+    book: &Book,
+    mut state: State,
+    payments: &[Payment],
+    output: Output,
+) -> anyhow::Result<()> {
+    if payments.is_empty() {
+        return output.print(
+            &json!({ "issued": [], "queued": [], "skipped": [] }),
+            || println!("Все поступления из выписки уже есть в журнале"),
+        );
+    }
+
+    let mut names: Vec<&String> = cfg.templates.keys().collect();
+    names.sort();
+
+    let mut client = crate::get_client(&state, &cfg.http)?;
+
+    let mut issued = Vec::new();
+    let mut queued = Vec::new();
+    let mut skipped = Vec::new();
+
+    for payment in payments {
+        let candidates = candidates(cfg, book, payment);
+
+        // Подходящие шаблоны предлагаем первыми, остальные после пропуска.
+        let mut options: Vec<String> = candidates.iter().map(|c| c.template.clone()).collect();
+        options.push(SKIP.to_owned());
+        options.extend(
+            names
+                .iter()
+                .filter(|n| !candidates.iter().any(|c| &c.template == **n))
+                .map(|n| n.to_string()),
+        );
+
+        let title = format!("Шаблон для чека на {}", describe(payment));
+        let choice = Select::new(&title, options).prompt()?;
+        if choice == SKIP {
+            skipped.push(payment);
+            continue;
+        }
+
+        let client_variable = candidates
+            .into_iter()
+            .find(|c| c.template == choice)
+            .and_then(|c| c.client_variable);
+
+        let raw = cfg.template(&choice)?;
+
+        let clock = Clock::Fixed(payment_time(payment));
+        let tmpl = compiled::Template::new(raw, clock)?.with_rates(cfg.rates());
+        let no_history = tmpl.no_history();
+
+        // Значения из платежа важнее запомненных, а плательщик выбран в
+        // списке заказчиков по-умолчанию.
+        // Переменные со значением-функцией, например `date:now()`, считаются
+        // от времени платежа, а не берутся из запомненных.
+        let mut last = state.last_values(&choice);
+        last.retain(|name, _| !no_history.contains(name));
+        last.extend(payment_values(payment));
+        if let (Some(var), Some(inn)) = (&client_variable, &payment.payer_inn) {
+            last.insert(ClientProperty::Inn.key(var), inn.clone());
+        }
+
+        let values = cli::ask(tmpl.get_fields(), &clock, &last, &book.clients)?;
+        let check = tmpl.build_check(&values)?;
+
+        match register(cfg, &mut client, &choice, check, &clock)? {
+            Issued::Event(event) => {
+                if output == Output::Text {
+                    println!("Чек доступен по URL: {}", event.url);
+                }
+                issued.push(json!({ "payment": payment, "check": event }));
+            }
+            Issued::Queued(item) => {
+                if output == Output::Text {
+                    println!("Чек сохранён в очередь под номером {}", item.id);
+                }
+                queued.push(json!({ "payment": payment, "item": item }));
+            }
+        }
+
+        state.remember(
+            &choice,
+            values.into_values().flatten().collect(),
+            &no_history,
+        );
+
+        // Сохраняем состояние после каждого чека, чтобы не потерять токены,
+        // если пользователь прервёт ввод.
+        state.access_token = Some(client.get_access_token());
+        state.refresh_token = Some(client.get_refresh_token());
+        state.taxpayer_identification_number = Some(client.get_inn());
+
+        debug!("Сохраняем состояние в {:?}", cfg.state_path);
+        state::save(&state, &cfg.state_path)?;
+    }
+
+    output.print(
+        &json!({ "issued": issued, "queued": queued, "skipped": skipped }),
+        || {},
+    )
+}
+
+/// Записывает в файл для команды `batch` чеки на платежи, для которых нашёлся
+/// шаблон и удалось заполнить все его переменные.
+fn write_batch(
+    cfg: &Config,
+    book: &Book,
+    payments: &[Payment],
+    path: PathBuf,
+    output: Output,
+) -> anyhow::Result<()> {
+    let mut batch = Batch::default();
+    let mut skipped = Vec::new();
+
+    for payment in payments {
+        let Some(candidate) = candidates(cfg, book, payment).into_iter().next() else {
+            warn!("Не найден шаблон для платежа: {}", describe(payment));
+            skipped.push(payment);
+            continue;
+        };
+
+        let mut values = payment_values(payment);
+        if let (Some(var), Some(inn)) = (&candidate.client_variable, &payment.payer_inn) {
+            values.insert(var.clone(), inn.clone());
+        }
+
+        let entry = Entry {
+            template: candidate.template,
+            now: Some(payment_time(payment)),
+            values,
+            comment: Some(describe(payment)),
+            receipt: None,
+            queued: None,
+        };
+
+        if let Err(e) = build(cfg, book, &entry) {
+            warn!(
+                "Не удалось собрать чек по шаблону {} для платежа: {}: {:#}",
+                entry.template,
+                describe(payment),
+                e
+            );
+            skipped.push(payment);
+            continue;
+        }
+
+        batch.checks.push(entry);
+    }
+
+    debug!("Сохраняем чеки в {:?}", path);
+    batch::save(&batch, &path)?;
+
+    output.print(
+        &json!({ "file": path, "checks": batch.checks, "skipped": skipped }),
+        || {
+            println!(
+                "Записано чеков в {}: {}, пропущено платежей: {}",
+                path.display(),
+                batch.checks.len(),
+                skipped.len()
+            )
+        },
+    )
+}
+
+/// Возвращает шаблоны, подходящие для платежа: сначала шаблоны, в которых
+/// заказчик — организация с ИНН плательщика, затем шаблоны с переменной типа
+/// `client`, если плательщик есть в адресной книге.
+fn candidates(cfg: &Config, book: &Book, payment: &Payment) -> Vec<Candidate> {
+    let Some(inn) = &payment.payer_inn else {
+        return Vec::new();
+    };
+
+    let mut names: Vec<&String> = cfg.templates.keys().collect();
+    names.sort();
+
+    let mut exact = Vec::new();
+    let mut by_client = Vec::new();
+
+    let known = book.clients.iter().any(|c| &c.inn == inn);
+
+    for name in names {
+        let raw = match cfg.template(name) {
+            Ok(raw) => raw,
+            Err(e) => {
+                debug!("Пропускаем шаблон {}: {:#}", name, e);
+                continue;
+            }
+        };
+
+        if let Counterparty::Organization { inn: tmpl_inn, .. } = &raw.counterparty {
+            if tmpl_inn.trim() == inn {
+                exact.push(Candidate {
+                    template: name.clone(),
+                    client_variable: None,
+                });
+                continue;
+            }
+        }
+
+        if !known {
+            continue;
+        }
+
+        let variable = compiled::Template::new(raw, Clock::System)
+            .ok()
+            .and_then(|t| {
+                t.variables()
+                    .into_iter()
+                    .find(|v| v.kind == VariableKind::Client)
+            });
+
+        if let Some(var) = variable {
+            by_client.push(Candidate {
+                template: name.clone(),
+                client_variable: Some(var.name),
+            });
+        }
+    }
+
+    exact.extend(by_client);
+    exact
+}
+
+/// Значения переменных шаблона, которые берутся из платежа.
+fn payment_values(payment: &Payment) -> FieldValues {
+    FieldValues::from([
+        ("amount".to_owned(), payment.amount.normalize().to_string()),
+        ("purpose".to_owned(), payment.purpose.clone()),
+        ("payer".to_owned(), payment.payer.clone()),
+    ])
+}
+
+/// Момент, которым выписывается чек на платёж: полдень дня платежа, но не
+/// позже текущего момента.
+fn payment_time(payment: &Payment) -> DateTime<FixedOffset> {
+    at(payment.date, 12).min(Clock::System.now())
+}
+
+/// Начало указанного часа дня в текущем часовом поясе.
+fn at(date: NaiveDate, hour: u32) -> DateTime<FixedOffset> {
+    // Смещение фиксированное, поэтому время всегда однозначно.
+    Clock::System
+        .now()
+        .offset()
+        .from_local_datetime(&date.and_hms_opt(hour, 0, 0).unwrap_or_default())
+        .unwrap()
+}
+
+fn describe(payment: &Payment) -> String {
+    let payer = match &payment.payer_inn {
+        Some(inn) => format!("{} (ИНН {})", payment.payer, inn),
+        None => payment.payer.clone(),
+    };
+
+    format!(
+        "платёж №{} от {} на {} ₽ от {}: {}",
+        payment.number,
+        payment.date.format("%d.%m.%Y"),
+        payment.amount,
+        payer,
+        payment.purpose
+    )
+}
@@ -0,0 +1,407 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use enum_iterator::all;
+use inquire::{validator::Validation, Select, Text};
+use log::debug;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{
+    clock::Clock,
+    config::{self, Config, Sources},
+    output::Output,
+    template::{
+        compiled::{self, FieldName, Node, Placeholder},
+        raw,
+    },
+};
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[arg(short = 'c', long, env = "LKNPD_CONFIG")]
+    config_path: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    #[command(about = "Lists all templates with their variables")]
+    #[command(long_about = None)]
+    List,
+
+    #[command(about = "Shows raw template and its parsed placeholders")]
+    #[command(long_about = None)]
+    Show {
+        #[arg()]
+        name: String,
+    },
+
+    #[command(about = "Compiles all templates and reports every error")]
+    #[command(long_about = None)]
+    Validate,
+
+    #[command(about = "Interactively creates a new template in the config")]
+    #[command(long_about = None)]
+    New,
+}
+
+/// Исполняет команды для управления шаблонами.
+pub fn run(args: Args, output: Output) -> anyhow::Result<()> {
+    let cfg = config::load(args.config_path.clone())?;
+
+    match args.command {
+        Command::List => list(&cfg, output),
+        Command::Show { name } => show(&cfg, &name, output),
+        Command::Validate => validate(&cfg, output),
+        Command::New => new(&cfg, &Sources::new(args.config_path).target(), output),
+    }
+}
+
+/// Краткое описание шаблона для списка шаблонов.
+#[derive(Serialize)]
+struct Summary {
+    name: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    counterparty: Option<raw::Counterparty>,
+
+    variables: Vec<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+fn list(cfg: &Config, output: Output) -> anyhow::Result<()> {
+    let templates: Vec<Summary> = template_names(cfg)
+        .into_iter()
+        .map(|name| match compile(cfg, name) {
+            Ok((raw, tmpl)) => Summary {
+                name: name.clone(),
+                counterparty: Some(raw.counterparty),
+                variables: tmpl.variables().into_iter().map(|v| v.name).collect(),
+                error: None,
+            },
+            Err(e) => Summary {
+                name: name.clone(),
+                counterparty: None,
+                variables: Vec::new(),
+                error: Some(format!("{:#}", e)),
+            },
+        })
+        .collect();
+
+    output.print(&json!({ "templates": templates }), || {
+        print_list(&templates)
+    })
+}
+
+fn print_list(templates: &[Summary]) {
+    let mut rows = vec![[
+        "Шаблон".to_owned(),
+        "Заказчик".to_owned(),
+        "Переменные".to_owned(),
+    ]];
+
+    for t in templates {
+        let row = match &t.counterparty {
+            Some(counterparty) => [
+                t.name.clone(),
+                counterparty_kind(counterparty).to_owned(),
+                t.variables.join(", "),
+            ],
+            None => [
+                t.name.clone(),
+                "-".to_owned(),
+                "шаблон с ошибкой, см. templates validate".to_owned(),
+            ],
+        };
+
+        rows.push(row);
+    }
+
+    let name_width = rows.iter().map(|r| r[0].chars().count()).max();
+    let kind_width = rows.iter().map(|r| r[1].chars().count()).max();
+
+    for [name, kind, variables] in &rows {
+        println!(
+            "{:<name_width$}  {:<kind_width$}  {}",
+            name,
+            kind,
+            variables,
+            name_width = name_width.unwrap_or_default(),
+            kind_width = kind_width.unwrap_or_default(),
+        );
+    }
+}
+
+fn show(cfg: &Config, name: &str, output: Output) -> anyhow::Result<()> {
+    let (raw, tmpl) = compile(cfg, name)?;
+    let extends = cfg.templates.get(name).and_then(|d| d.extends.as_ref());
+
+    let fields: serde_json::Map<String, serde_json::Value> = all::<FieldName>()
+        .filter_map(|field_name| {
+            let field = tmpl.get_fields().get(&field_name)?;
+            let value = json!({
+                "source": field.source,
+                "nodes": nodes_json(&field.source, &field.nodes),
+            });
+
+            Some((field_name.to_string(), value))
+        })
+        .collect();
+
+    let value = json!({
+        "name": name,
+        "extends": extends,
+        "template": raw,
+        "variables": tmpl.variables().into_iter().map(|v| v.name).collect::<Vec<_>>(),
+        "fields": fields,
+    });
+
+    output.print(&value, || {
+        println!("Шаблон: {}", name);
+        if let Some(base) = extends {
+            println!("Основан на: {}", base);
+        }
+        println!("Заказчик: {}", counterparty_kind(&raw.counterparty));
+
+        for field_name in all::<FieldName>() {
+            let Some(field) = tmpl.get_fields().get(&field_name) else {
+                continue;
+            };
+
+            println!();
+            println!("{}: {}", field_name, field.source);
+            print_nodes(&field.source, &field.nodes, 1);
+        }
+    })
+}
+
+fn print_nodes(source: &str, nodes: &[Node], depth: usize) {
+    let indent = "  ".repeat(depth);
+
+    for node in nodes {
+        match node {
+            Node::Text(_) => {}
+            Node::Placeholder { placeholder, span } => {
+                let (line, column) = span.location(source);
+                let kind = match placeholder {
+                    Placeholder::Variable { .. } => "переменная",
+                    Placeholder::Client { .. } => "заказчик",
+                    Placeholder::Money { .. } => "сумма в валюте",
+                    Placeholder::Function { .. } => "функция",
+                    Placeholder::Expression { .. } => "выражение",
+                };
+                println!("{}{}:{} {} {}", indent, line, column, kind, placeholder);
+            }
+            Node::If {
+                condition,
+                then,
+                otherwise,
+                span,
+            } => {
+                let (line, column) = span.location(source);
+                println!("{}{}:{} если {}", indent, line, column, condition);
+                print_nodes(source, then, depth + 1);
+
+                if !otherwise.is_empty() {
+                    println!("{}иначе", indent);
+                    print_nodes(source, otherwise, depth + 1);
+                }
+            }
+        }
+    }
+}
+
+/// Превращает разобранные плейсхолдеры поля в JSON с местом, где они
+/// найдены. Текст между плейсхолдерами пропускается, как и при выводе текстом.
+fn nodes_json(source: &str, nodes: &[Node]) -> Vec<serde_json::Value> {
+    nodes
+        .iter()
+        .filter_map(|node| match node {
+            Node::Text(_) => None,
+            Node::Placeholder { placeholder, span } => {
+                let (line, column) = span.location(source);
+                let kind = match placeholder {
+                    Placeholder::Variable { .. } => "variable",
+                    Placeholder::Client { .. } => "client",
+                    Placeholder::Money { .. } => "money",
+                    Placeholder::Function { .. } => "function",
+                    Placeholder::Expression { .. } => "expression",
+                };
+
+                Some(json!({
+                    "kind": kind,
+                    "placeholder": placeholder.to_string(),
+                    "line": line,
+                    "column": column,
+                }))
+            }
+            Node::If {
+                condition,
+                then,
+                otherwise,
+                span,
+            } => {
+                let (line, column) = span.location(source);
+
+                Some(json!({
+                    "kind": "if",
+                    "condition": condition,
+                    "line": line,
+                    "column": column,
+                    "then": nodes_json(source, then),
+                    "otherwise": nodes_json(source, otherwise),
+                }))
+            }
+        })
+        .collect()
+}
+
+fn validate(cfg: &Config, output: Output) -> anyhow::Result<()> {
+    let templates = template_names(cfg);
+
+    let results: Vec<(&String, Option<String>)> = templates
+        .iter()
+        .map(|name| (*name, compile(cfg, name).err().map(|e| format!("{:#}", e))))
+        .collect();
+
+    let value = json!({
+        "templates": results
+            .iter()
+            .map(|(name, error)| json!({ "name": name, "valid": error.is_none(), "error": error }))
+            .collect::<Vec<_>>(),
+    });
+
+    output.print(&value, || {
+        for (name, error) in &results {
+            match error {
+                None => println!("{}: ok", name),
+                Some(e) => println!("{}: {}", name, e),
+            }
+        }
+    })?;
+
+    let failed = results.iter().filter(|(_, e)| e.is_some()).count();
+    if failed > 0 {
+        return Err(anyhow!(
+            "{} of {} templates are invalid",
+            failed,
+            templates.len()
+        ));
+    }
+
+    Ok(())
+}
+
+fn new(cfg: &Config, config_path: &Path, output: Output) -> anyhow::Result<()> {
+    let existing: Vec<String> = cfg.templates.keys().cloned().collect();
+
+    // Кроме заказчика "по месту" можно выбрать одного из заказчиков из конфига.
+    let mut named: Vec<&str> = cfg.counterparties.keys().map(|n| n.as_str()).collect();
+    named.sort();
+
+    let (name, definition) = prompt_template(existing, named)?;
+
+    debug!("Сохраняем шаблон {} в {:?}", name, config_path);
+    config::append_template(config_path, &name, &definition)?;
+
+    output.print(
+        &json!({ "name": name, "config_path": config_path, "template": definition }),
+        || println!("Шаблон {} добавлен в {:?}", name, config_path),
+    )
+}
+
+/// Интерактивно запрашивает название и поля нового шаблона. Заказчиком можно
+/// выбрать физ. лицо, организацию или одного из переданных заказчиков.
+pub fn prompt_template(
+    existing: Vec<String>,
+    counterparties: Vec<&str>,
+) -> anyhow::Result<(String, raw::Definition)> {
+    let name = Text::new("Название шаблона")
+        .with_validator(move |s: &str| {
+            if s.trim().is_empty() {
+                return Ok(Validation::Invalid("required".into()));
+            }
+            if existing.iter().any(|n| n == s) {
+                return Ok(Validation::Invalid("template already exists".into()));
+            }
+            Ok(Validation::Valid)
+        })
+        .prompt()?;
+
+    let title = prompt_field("Название услуги", None)?;
+    let price = prompt_field("Цена", None)?;
+    let date = prompt_field("Дата", Some("{{ now() }}"))?;
+
+    const PERSON: &str = "физ. лицо";
+    const ORGANIZATION: &str = "юр. лицо";
+
+    let options = [PERSON, ORGANIZATION]
+        .into_iter()
+        .chain(counterparties)
+        .collect();
+
+    let counterparty = match Select::new("Заказчик", options).prompt()? {
+        PERSON => raw::CounterpartyRef::Inline(raw::Counterparty::Person),
+        ORGANIZATION => raw::CounterpartyRef::Inline(raw::Counterparty::Organization {
+            name: prompt_field("Название организации", None)?,
+            inn: prompt_field("ИНН организации", None)?,
+        }),
+        n => raw::CounterpartyRef::Named(n.to_owned()),
+    };
+
+    let definition = raw::Definition {
+        title: Some(title),
+        price: Some(price),
+        date: Some(date),
+        counterparty: Some(counterparty),
+        ..Default::default()
+    };
+
+    Ok((name, definition))
+}
+
+/// Запрашивает значение поля шаблона и сразу проверяет, что оно разбирается.
+fn prompt_field(title: &str, default: Option<&str>) -> anyhow::Result<String> {
+    let mut prompt = Text::new(title)
+        .with_help_message("можно использовать {{ }}")
+        .with_validator(|s: &str| {
+            if s.trim().is_empty() {
+                return Ok(Validation::Invalid("required".into()));
+            }
+            match compiled::Field::new(s) {
+                Ok(_) => Ok(Validation::Valid),
+                Err(e) => Ok(Validation::Invalid(e.to_string().into())),
+            }
+        });
+
+    if let Some(d) = default {
+        prompt = prompt.with_default(d);
+    }
+
+    Ok(prompt.prompt()?)
+}
+
+fn template_names(cfg: &Config) -> Vec<&String> {
+    let mut names: Vec<_> = cfg.templates.keys().collect();
+    names.sort();
+    names
+}
+
+/// Собирает шаблон с учётом наследования и компилирует его.
+fn compile(cfg: &Config, name: &str) -> anyhow::Result<(raw::Template, compiled::Template)> {
+    let raw = cfg.template(name)?;
+    let tmpl = compiled::Template::new(raw.clone(), Clock::System)?;
+    Ok((raw, tmpl))
+}
+
+fn counterparty_kind(counterparty: &raw::Counterparty) -> &'static str {
+    match counterparty {
+        raw::Counterparty::Person => "физ. лицо",
+        raw::Counterparty::Organization { name: _, inn: _ } => "юр. лицо",
+    }
+}
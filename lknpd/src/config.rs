@@ -1,31 +1,538 @@
-use std::{collections::HashMap, path::PathBuf};
+mod check;
+mod sources;
 
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Context};
+use log::debug;
 use serde::{Deserialize, Serialize};
 
-use crate::template::raw::Template;
+use crate::{
+    api::HttpSettings,
+    hooks::Hook,
+    mail::Smtp,
+    rates,
+    schedule::Schedule,
+    template::raw::{Counterparty, CounterpartyRef, Definition, Organization, Template},
+};
 use resolve_path::PathResolveExt;
 
+pub use check::check;
+pub use sources::Sources;
+
 #[derive(Serialize, Deserialize, Default, Debug)]
 pub struct Config {
     /// Путь до файла с состоянием.
+    /// По-умолчанию `$XDG_STATE_HOME/lknpd/state.json`, а для профиля
+    /// `$XDG_STATE_HOME/lknpd/<профиль>/state.json`.
+    #[serde(default)]
     pub state_path: PathBuf,
 
+    /// Профиль, настройки из секции которого в `profiles` применяются поверх
+    /// остальных. Переопределяется переменной окружения `LKNPD_PROFILE`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+
+    /// Профили: название -> настройки, например для разных аккаунтов.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub profiles: HashMap<String, toml::Table>,
+
+    /// Путь до файла с адресной книгой заказчиков.
+    /// По-умолчанию `clients.json` рядом с файлом состояния.
+    #[serde(default)]
+    pub clients_path: PathBuf,
+
+    /// Путь до журнала выписанных чеков.
+    /// По-умолчанию `ledger.sqlite` рядом с файлом состояния.
+    #[serde(default)]
+    pub ledger_path: PathBuf,
+
+    /// Путь до файла с очередью чеков, которые не удалось выписать.
+    /// По-умолчанию `queue.json` рядом с файлом состояния.
+    #[serde(default)]
+    pub queue_path: PathBuf,
+
+    /// Путь до файла или каталога с курсами ЦБ в формате ежедневных котировок.
+    /// По-умолчанию каталог `rates` рядом с файлом состояния.
+    #[serde(default)]
+    pub rates_path: PathBuf,
+
+    /// TrueType шрифт с кириллицей для PDF чеков.
+    /// По-умолчанию ищется DejaVu Sans или Arial среди системных шрифтов.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receipt_font: Option<PathBuf>,
+
+    /// Список каталогов с шаблонами.
+    /// Каждый `*.toml` или `*.yaml` файл в каталоге описывает один шаблон,
+    /// название шаблона берётся из имени файла.
+    #[serde(default)]
+    pub include: Vec<PathBuf>,
+
+    /// Заказчики, на которых можно сослаться из шаблонов по названию.
+    #[serde(default)]
+    pub counterparties: HashMap<String, Organization>,
+
     /// Список шаблонов.
-    pub templates: HashMap<String, Template>,
+    #[serde(default)]
+    pub templates: HashMap<String, Definition>,
+
+    /// Регулярные чеки, которые выписываются командой `run-due`.
+    #[serde(default)]
+    pub schedule: HashMap<String, Schedule>,
+
+    /// Действия, которые выполняются после выписки каждого чека.
+    #[serde(default)]
+    pub hooks: Vec<Hook>,
+
+    /// Настройки HTTP клиента: прокси, сертификаты, таймауты.
+    #[serde(default)]
+    pub http: HttpSettings,
+
+    /// SMTP сервер для отправки чеков заказчикам с флагом `--send-email`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smtp: Option<Smtp>,
 }
 
-/// Загружает конфигурацию.
-pub fn load(path: PathBuf) -> anyhow::Result<Config> {
-    let mut cfg: Config = confy::load_path(path)?;
+impl Config {
+    /// Возвращает источник курсов валют для пересчёта сумм в шаблонах.
+    pub fn rates(&self) -> Arc<dyn rates::Provider> {
+        Arc::new(rates::Cbr::new(&self.rates_path))
+    }
+
+    /// Возвращает шаблон с указанным названием, готовый к компиляции.
+    /// Незаполненные поля берутся из шаблонов, указанных в `extends`, а
+    /// заказчик, заданный по названию, подставляется из `counterparties`.
+    pub fn template(&self, name: &str) -> anyhow::Result<Template> {
+        let mut chain: Vec<&str> = Vec::new();
+        let mut merged = Definition::default();
+        let mut current = Some(name);
+
+        while let Some(n) = current {
+            if chain.contains(&n) {
+                return Err(anyhow!(
+                    "template {} has cyclic extends: {} -> {}",
+                    name,
+                    chain.join(" -> "),
+                    n
+                ));
+            }
+
+            let def = match (self.templates.get(n), chain.last()) {
+                (Some(def), _) => def,
+                (None, None) => return Err(anyhow!("template {} not found", n)),
+                (None, Some(child)) => {
+                    return Err(anyhow!("template {} extends unknown template {}", child, n))
+                }
+            };
+
+            merged.title = merged.title.or_else(|| def.title.clone());
+            merged.price = merged.price.or_else(|| def.price.clone());
+            merged.date = merged.date.or_else(|| def.date.clone());
+            merged.counterparty = merged.counterparty.or_else(|| def.counterparty.clone());
+            merged.no_history = merged.no_history.or_else(|| def.no_history.clone());
+            merged.email = merged.email.or_else(|| def.email.clone());
+
+            chain.push(n);
+            current = def.extends.as_deref();
+        }
+
+        let missing = |field: &str| anyhow!("template {} has no {}", name, field);
+
+        let mut email = merged.email;
+
+        let counterparty = match merged.counterparty.ok_or_else(|| missing("counterparty"))? {
+            CounterpartyRef::Inline(c) => c,
+            CounterpartyRef::Named(n) => {
+                let org = self
+                    .counterparties
+                    .get(&n)
+                    .ok_or(anyhow!("counterparty {} not found", n))?;
+
+                email = email.or_else(|| org.email.clone());
+
+                Counterparty::Organization {
+                    name: org.name.clone(),
+                    inn: org.inn.clone(),
+                }
+            }
+        };
 
+        Ok(Template {
+            title: merged.title.ok_or_else(|| missing("title"))?,
+            price: merged.price.ok_or_else(|| missing("price"))?,
+            date: merged.date.ok_or_else(|| missing("date"))?,
+            counterparty,
+            no_history: merged.no_history.unwrap_or_default(),
+            email,
+        })
+    }
+}
+
+/// Загружает конфигурацию из конфига пользователя, конфига проекта и
+/// переменных окружения. Неизвестные ключи считаются ошибкой, чтобы опечатки в
+/// названиях настроек не игнорировались молча.
+pub fn load(path: Option<PathBuf>) -> anyhow::Result<Config> {
+    let sources = Sources::new(path);
+    let mut tables = Vec::new();
+
+    for file in sources.files()? {
+        debug!("Подгружаем конфиг из {:?}", file);
+        let content = read(&file)?;
+
+        let (mut table, problems) =
+            check::parse(&content).map_err(|p| anyhow!("invalid config {:?}: {}", file, p))?;
+
+        if !problems.is_empty() {
+            let problems: Vec<String> = problems.iter().map(|p| format!("  {}", p)).collect();
+            return Err(anyhow!(
+                "invalid config {:?}:\n{}\nrun `lknpd config check` to see all problems",
+                file,
+                problems.join("\n")
+            ));
+        }
+
+        sources::relative_to(&mut table, file.parent().unwrap_or(Path::new("")));
+        tables.push(table);
+    }
+
+    for o in &sources.overrides {
+        debug!("Настройка переопределена переменной окружения {}", o.name);
+    }
+
+    let mut cfg = sources.merge(tables)?;
     normalize(&mut cfg)?;
 
     Ok(cfg)
 }
 
+fn read(path: &Path) -> anyhow::Result<String> {
+    fs::read_to_string(path).with_context(|| format!("failed to read config {:?}", path))
+}
+
 pub fn normalize(cfg: &mut Config) -> anyhow::Result<()> {
     // Чтобы правильно обработать относительные пути.
     cfg.state_path = cfg.state_path.try_resolve()?.into_owned();
 
+    if cfg.clients_path.as_os_str().is_empty() {
+        cfg.clients_path = cfg.state_path.with_file_name("clients.json");
+    }
+    cfg.clients_path = cfg.clients_path.try_resolve()?.into_owned();
+
+    if cfg.ledger_path.as_os_str().is_empty() {
+        cfg.ledger_path = cfg.state_path.with_file_name("ledger.sqlite");
+    }
+    cfg.ledger_path = cfg.ledger_path.try_resolve()?.into_owned();
+
+    if cfg.queue_path.as_os_str().is_empty() {
+        cfg.queue_path = cfg.state_path.with_file_name("queue.json");
+    }
+    cfg.queue_path = cfg.queue_path.try_resolve()?.into_owned();
+
+    if cfg.rates_path.as_os_str().is_empty() {
+        cfg.rates_path = cfg.state_path.with_file_name("rates");
+    }
+    cfg.rates_path = cfg.rates_path.try_resolve()?.into_owned();
+
+    if let Some(font) = cfg.receipt_font.as_mut() {
+        *font = font.try_resolve()?.into_owned();
+    }
+
+    for cert in cfg.http.root_certificates.iter_mut() {
+        *cert = cert.try_resolve()?.into_owned();
+    }
+
+    for dir in cfg.include.iter_mut() {
+        *dir = dir.try_resolve()?.into_owned();
+    }
+
+    include_templates(&mut cfg.templates, &cfg.include)?;
+
     Ok(())
 }
+
+/// Дополняет шаблоны из конфига шаблонами из переданных каталогов.
+/// Если шаблон с таким названием уже есть, то возвращается ошибка с указанием
+/// откуда были загружены оба шаблона.
+fn include_templates(
+    templates: &mut HashMap<String, Definition>,
+    dirs: &[PathBuf],
+) -> anyhow::Result<()> {
+    let mut sources: HashMap<String, PathBuf> = HashMap::new();
+
+    for dir in dirs {
+        debug!("Подгружаем шаблоны из {:?}", dir);
+
+        let mut paths = fs::read_dir(dir)
+            .with_context(|| format!("failed to read templates directory {:?}", dir))?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Чтобы ошибки и порядок загрузки не зависели от файловой системы.
+        paths.sort();
+
+        for path in paths {
+            let Some(ext) = path.extension().and_then(|e| e.to_str()) else {
+                continue;
+            };
+            if !matches!(ext, "toml" | "yaml" | "yml") || !path.is_file() {
+                continue;
+            }
+
+            let name = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .ok_or(anyhow!("invalid template file name {:?}", path))?
+                .to_owned();
+
+            if templates.contains_key(&name) {
+                return Err(match sources.get(&name) {
+                    Some(other) => anyhow!(
+                        "template {} from {:?} conflicts with template from {:?}",
+                        name,
+                        path,
+                        other
+                    ),
+                    None => anyhow!(
+                        "template {} from {:?} conflicts with template from config",
+                        name,
+                        path
+                    ),
+                });
+            }
+
+            let template = read_template(&path)
+                .with_context(|| format!("failed to load template from {:?}", path))?;
+
+            debug!("Загружен шаблон {} из {:?}", name, path);
+            templates.insert(name.clone(), template);
+            sources.insert(name, path);
+        }
+    }
+
+    Ok(())
+}
+
+fn read_template(path: &Path) -> anyhow::Result<Definition> {
+    let content = fs::read_to_string(path)?;
+
+    let template = match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => toml::from_str(&content)?,
+        // serde_yaml ждёт варианты перечислений в виде тегов (`!Organization`),
+        // поэтому идём через json, чтобы заказчик описывался так же как в toml.
+        _ => serde_json::from_value(serde_yaml::from_str(&content)?)?,
+    };
+
+    Ok(template)
+}
+
+/// Дописывает новый шаблон в конец файла конфигурации.
+/// Остальное содержимое файла, включая комментарии, остаётся нетронутым.
+pub fn append_template(path: &Path, name: &str, template: &Definition) -> anyhow::Result<()> {
+    #[derive(Serialize)]
+    struct Entry<'a> {
+        templates: HashMap<&'a str, &'a Definition>,
+    }
+
+    let content = toml::to_string(&Entry {
+        templates: HashMap::from([(name, template)]),
+    })?;
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+
+    write!(file, "\n{}", content)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lknpd-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn template(title: &str) -> Definition {
+        Definition {
+            title: Some(title.to_owned()),
+            price: Some("1000".to_owned()),
+            date: Some("{{ now() }}".to_owned()),
+            counterparty: Some(CounterpartyRef::Inline(Counterparty::Person)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn include_templates_from_directories() {
+        let dir = temp_dir("include");
+        fs::write(
+            dir.join("toml.toml"),
+            "title = \"from toml\"\nprice = \"1\"\ndate = \"{{ now() }}\"\ncounterparty = \"Person\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("yaml.yaml"),
+            "title: from yaml\nprice: \"2\"\ndate: \"{{ now() }}\"\ncounterparty:\n  Organization:\n    name: ООО Ромашка\n    inn: \"1234567890\"\n",
+        )
+        .unwrap();
+        fs::write(dir.join("README.md"), "не шаблон").unwrap();
+
+        let mut templates = HashMap::from([("inline".to_owned(), template("inline"))]);
+        include_templates(&mut templates, std::slice::from_ref(&dir)).unwrap();
+
+        let mut names: Vec<_> = templates.keys().cloned().collect();
+        names.sort();
+        assert_eq!(names, ["inline", "toml", "yaml"]);
+        assert_eq!(templates["toml"].title.as_deref(), Some("from toml"));
+        assert!(matches!(
+            &templates["yaml"].counterparty,
+            Some(CounterpartyRef::Inline(Counterparty::Organization { name, inn }))
+                if name == "ООО Ромашка" && inn == "1234567890"
+        ));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn include_templates_conflicts() {
+        let first = temp_dir("conflict-first");
+        let second = temp_dir("conflict-second");
+        let content = "title: t\nprice: \"1\"\ndate: d\ncounterparty: Person\n";
+        fs::write(first.join("monthly.yaml"), content).unwrap();
+        fs::write(second.join("monthly.yml"), content).unwrap();
+
+        let mut templates = HashMap::from([("monthly".to_owned(), template("inline"))]);
+        let err = include_templates(&mut templates, std::slice::from_ref(&first)).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("conflicts with template from config"));
+
+        let mut templates = HashMap::new();
+        let err = include_templates(&mut templates, &[first.clone(), second.clone()]).unwrap_err();
+        assert!(
+            err.to_string().contains("monthly.yml") && err.to_string().contains("monthly.yaml"),
+            "{}",
+            err
+        );
+
+        fs::remove_dir_all(first).unwrap();
+        fs::remove_dir_all(second).unwrap();
+    }
+
+    fn config(content: &str) -> Config {
+        toml::from_str(content).unwrap()
+    }
+
+    #[test]
+    fn template_extends_and_named_counterparty() {
+        let cfg = config(
+            r#"
+            state_path = "state.json"
+
+            [counterparties.acme]
+            name = "ООО Ромашка"
+            inn = "1234567890"
+            email = "acme@example.com"
+
+            [templates.base]
+            title = "Разработка ПО"
+            price = "{{ hours * 2500 }}"
+            date = "{{ now() }}"
+            counterparty = "acme"
+
+            [templates.support]
+            extends = "base"
+            title = "Поддержка ПО"
+
+            [templates.urgent]
+            extends = "support"
+            price = "50000"
+            counterparty = "Person"
+
+            [templates.other]
+            extends = "base"
+            email = "other@example.com"
+            "#,
+        );
+
+        let base = cfg.template("base").unwrap();
+        assert_eq!(base.title, "Разработка ПО");
+        assert!(matches!(
+            base.counterparty,
+            Counterparty::Organization { name, inn } if name == "ООО Ромашка" && inn == "1234567890"
+        ));
+
+        let support = cfg.template("support").unwrap();
+        assert_eq!(support.title, "Поддержка ПО");
+        assert_eq!(support.price, "{{ hours * 2500 }}");
+        assert!(matches!(
+            support.counterparty,
+            Counterparty::Organization { .. }
+        ));
+
+        let urgent = cfg.template("urgent").unwrap();
+        assert_eq!(urgent.title, "Поддержка ПО");
+        assert_eq!(urgent.price, "50000");
+        assert_eq!(urgent.date, "{{ now() }}");
+        assert!(matches!(urgent.counterparty, Counterparty::Person));
+        assert_eq!(urgent.email, None);
+
+        assert_eq!(support.email.as_deref(), Some("acme@example.com"));
+        assert_eq!(
+            cfg.template("other").unwrap().email.as_deref(),
+            Some("other@example.com")
+        );
+    }
+
+    #[test]
+    fn template_resolve_errors() {
+        let cfg = config(
+            r#"
+            state_path = "state.json"
+
+            [templates.partial]
+            title = "Без цены"
+            date = "{{ now() }}"
+            counterparty = "Person"
+
+            [templates.orphan]
+            extends = "missing"
+
+            [templates.first]
+            extends = "second"
+
+            [templates.second]
+            extends = "first"
+
+            [templates.stranger]
+            extends = "partial"
+            price = "1"
+            counterparty = "unknown"
+            "#,
+        );
+
+        let cases = [
+            ("nothing", "template nothing not found"),
+            ("partial", "template partial has no price"),
+            ("orphan", "template orphan extends unknown template missing"),
+            (
+                "first",
+                "template first has cyclic extends: first -> second -> first",
+            ),
+            ("stranger", "counterparty unknown not found"),
+        ];
+
+        for (name, expected) in cases {
+            assert_eq!(cfg.template(name).unwrap_err().to_string(), expected);
+        }
+    }
+}
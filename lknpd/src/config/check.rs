@@ -0,0 +1,436 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+use toml::Table;
+
+use super::{normalize, read, sources::relative_to, Config, Sources};
+use crate::{clock::Clock, template::compiled};
+
+/// Проблема в конфиге с местом, где она найдена, если его удалось определить.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// Конфиг, в котором найдена проблема.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+
+    pub message: String,
+}
+
+impl Problem {
+    fn new(position: Option<(usize, usize)>, message: String) -> Self {
+        Self {
+            file: None,
+            line: position.map(|p| p.0),
+            column: position.map(|p| p.1),
+            message,
+        }
+    }
+
+    fn in_file(self, file: &Path) -> Self {
+        Self {
+            file: Some(file.to_owned()),
+            ..self
+        }
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file.display())?;
+
+            if self.line.is_none() {
+                write!(f, " ")?;
+            }
+        }
+
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "{}:{}: {}", line, column, self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Проверяет все конфиги и возвращает найденные проблемы: синтаксические
+/// ошибки, неизвестные ключи, ошибки в шаблонах и расписаниях.
+pub fn check(path: Option<PathBuf>) -> anyhow::Result<Vec<Problem>> {
+    let sources = Sources::new(path);
+    let mut files = Vec::new();
+    let mut tables = Vec::new();
+    let mut problems = Vec::new();
+
+    for file in sources.files()? {
+        let content = read(&file)?;
+
+        match parse(&content) {
+            Ok((mut table, found)) => {
+                problems.extend(found.into_iter().map(|p| p.in_file(&file)));
+                relative_to(&mut table, file.parent().unwrap_or(Path::new("")));
+                tables.push(table);
+            }
+            Err(problem) => problems.push(problem.in_file(&file)),
+        }
+
+        files.push((file, content));
+    }
+
+    // Без разобранных конфигов остальные проверки бессмысленны.
+    if tables.len() != files.len() {
+        return Ok(problems);
+    }
+
+    // Ошибки в шаблонах ищутся в конфиге, который применяется последним.
+    let locate_any = |path: &[String]| {
+        files
+            .iter()
+            .rev()
+            .find_map(|(file, content)| locate(content, path).map(|position| (file, position)))
+    };
+    let problem = |path: &[String], message: String| match locate_any(path) {
+        Some((file, position)) => Problem::new(Some(position), message).in_file(file),
+        None => Problem::new(None, message),
+    };
+
+    let mut cfg = match sources.merge(tables) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            problems.push(Problem::new(None, format!("{:#}", e)));
+            return Ok(problems);
+        }
+    };
+
+    if let Err(e) = normalize(&mut cfg) {
+        problems.push(Problem::new(None, format!("{:#}", e)));
+        return Ok(problems);
+    }
+
+    let mut names: Vec<&String> = cfg.templates.keys().collect();
+    names.sort();
+
+    for name in names {
+        let result = cfg
+            .template(name)
+            .and_then(|raw| compiled::Template::new(raw, Clock::System));
+
+        if let Err(e) = result {
+            problems.push(problem(
+                &["templates".to_owned(), name.clone()],
+                format!("template {}: {:#}", name, e),
+            ));
+        }
+    }
+
+    let mut schedules: Vec<(&String, &String)> = cfg
+        .schedule
+        .iter()
+        .map(|(name, s)| (name, &s.template))
+        .collect();
+    schedules.sort();
+
+    for (name, template) in schedules {
+        if !cfg.templates.contains_key(template) {
+            problems.push(problem(
+                &["schedule".to_owned(), name.clone(), "template".to_owned()],
+                format!("schedule {} uses unknown template {}", name, template),
+            ));
+        }
+    }
+
+    Ok(problems)
+}
+
+/// Разбирает один конфиг. Ключи, которые конфиг не читает, в том числе в
+/// профилях, возвращаются как проблемы, а ошибка разбора возвращается как
+/// единственная проблема.
+pub fn parse(content: &str) -> Result<(Table, Vec<Problem>), Problem> {
+    let error = |e: toml::de::Error| {
+        Problem::new(
+            e.span().map(|s| position(content, s.start)),
+            e.message().to_owned(),
+        )
+    };
+
+    let mut unknown: Vec<Vec<String>> = Vec::new();
+
+    let cfg: Config = serde_ignored::deserialize(toml::Deserializer::new(content), |path| {
+        unknown.push(segments(&path))
+    })
+    .map_err(error)?;
+
+    let mut problems = Vec::new();
+
+    let mut profiles: Vec<(&String, &Table)> = cfg.profiles.iter().collect();
+    profiles.sort_by_key(|(name, _)| name.as_str());
+
+    // Профиль описывается теми же ключами, что и весь конфиг.
+    for (name, profile) in profiles {
+        let prefix = ["profiles".to_owned(), name.clone()];
+
+        let result: Result<Config, _> =
+            serde_ignored::deserialize(toml::Value::Table(profile.clone()), |path| {
+                unknown.push(prefix.iter().cloned().chain(segments(&path)).collect())
+            });
+
+        if let Err(e) = result {
+            problems.push(Problem::new(
+                locate(content, &prefix),
+                format!("profile {}: {}", name, e.message()),
+            ));
+        }
+    }
+
+    problems.extend(unknown.into_iter().map(|path| {
+        Problem::new(
+            locate(content, &path),
+            format!("unknown key {}", path.join(".")),
+        )
+    }));
+
+    let table = toml::from_str(content).map_err(error)?;
+
+    Ok((table, problems))
+}
+
+/// Превращает путь до неизвестного ключа в список ключей.
+fn segments(path: &serde_ignored::Path) -> Vec<String> {
+    use serde_ignored::Path;
+
+    match path {
+        Path::Root => Vec::new(),
+        Path::Seq { parent, index } => {
+            let mut result = segments(parent);
+            result.push(index.to_string());
+            result
+        }
+        Path::Map { parent, key } => {
+            let mut result = segments(parent);
+            result.push(key.clone());
+            result
+        }
+        Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => segments(parent),
+    }
+}
+
+/// Ищет строку и столбец, где задан ключ с указанным путём. Понимает заголовки
+/// таблиц, массивы таблиц и ключи через точку, чего достаточно для конфига.
+/// Если ключ задан внутри inline таблицы, то возвращается место ближайшего
+/// ключа, в котором он находится.
+fn locate(content: &str, path: &[String]) -> Option<(usize, usize)> {
+    let mut table: Vec<String> = Vec::new();
+    let mut arrays: Vec<(Vec<String>, usize)> = Vec::new();
+    let mut closest: Option<(usize, (usize, usize))> = None;
+
+    for (i, line) in content.lines().enumerate() {
+        let trimmed = line.trim_start();
+        let column = line[..line.len() - trimmed.len()].chars().count() + 1;
+
+        let full = if let Some(header) = trimmed.strip_prefix("[[") {
+            let keys = split_keys(header.split("]]").next().unwrap_or_default());
+
+            // Элементы массива таблиц нумеруются так же, как в пути.
+            let index = match arrays.iter_mut().find(|(k, _)| *k == keys) {
+                Some((_, count)) => {
+                    *count += 1;
+                    *count
+                }
+                None => {
+                    arrays.push((keys.clone(), 0));
+                    0
+                }
+            };
+
+            table = keys;
+            table.push(index.to_string());
+            table.clone()
+        } else if let Some(header) = trimmed.strip_prefix('[') {
+            table = split_keys(header.split(']').next().unwrap_or_default());
+            table.clone()
+        } else if trimmed.starts_with('#') || !trimmed.contains('=') {
+            continue;
+        } else {
+            let key = trimmed.split('=').next().unwrap_or_default();
+            table.iter().cloned().chain(split_keys(key)).collect()
+        };
+
+        if full.starts_with(path) {
+            return Some((i + 1, column));
+        }
+
+        if path.starts_with(&full) && closest.is_none_or(|(len, _)| full.len() > len) {
+            closest = Some((full.len(), (i + 1, column)));
+        }
+    }
+
+    closest.map(|(_, position)| position)
+}
+
+/// Разбивает ключ вида `templates."my template".title` на части.
+fn split_keys(key: &str) -> Vec<String> {
+    let mut keys = Vec::new();
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+
+    for c in key.chars() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if c == q => quote = None,
+            (None, '.') => keys.push(std::mem::take(&mut current).trim().to_owned()),
+            _ => current.push(c),
+        }
+    }
+    keys.push(current.trim().to_owned());
+
+    keys
+}
+
+/// Возвращает строку и столбец, начиная с единицы, для смещения в байтах.
+fn position(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .count()
+        + 1;
+
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_keys() {
+        let content = r#"
+state_path = "state.json"
+inn = ""
+
+[auth]
+token = ""
+
+[templates.support]
+title = "Поддержка ПО"
+price = "1000"
+date = "{{ now() }}"
+counterparty = "Person"
+titel = "Опечатка"
+
+[[hooks]]
+command = "true"
+
+[[hooks]]
+url = "https://example.com"
+
+[http]
+timeout = 10
+proxi = "http://proxy:3128"
+"#;
+
+        let (_, problems) = parse(content).unwrap();
+        let found: Vec<String> = problems.iter().map(|p| p.to_string()).collect();
+
+        assert_eq!(
+            found,
+            [
+                "3:1: unknown key inn",
+                "5:1: unknown key auth",
+                "13:1: unknown key templates.support.titel",
+                "23:1: unknown key http.proxi",
+            ]
+        );
+    }
+
+    #[test]
+    fn profile_keys() {
+        let content = r#"
+[profiles.work]
+state_path = "work.json"
+stat_path = "typo.json"
+
+[profiles.home.http]
+timeout = "long"
+"#;
+
+        let (_, problems) = parse(content).unwrap();
+        let found: Vec<String> = problems.iter().map(|p| p.to_string()).collect();
+
+        assert_eq!(found.len(), 2, "{:?}", found);
+        assert!(found[0].starts_with("6:1: profile home: "), "{}", found[0]);
+        assert_eq!(found[1], "4:1: unknown key profiles.work.stat_path");
+
+        let problem = Problem::new(None, "oops".to_owned()).in_file(Path::new("config.toml"));
+        assert_eq!(problem.to_string(), "config.toml: oops");
+    }
+
+    #[test]
+    fn parse_errors() {
+        let problem =
+            parse("state_path = \"state.json\"\ntemplates = {}\nhooks = 1\n").unwrap_err();
+        assert_eq!(problem.line, Some(3));
+        assert!(problem.message.contains("sequence"), "{}", problem.message);
+
+        let problem = parse("state_path = \n").unwrap_err();
+        assert_eq!(problem.line, Some(1));
+    }
+
+    #[test]
+    fn locate_keys() {
+        let content = r#"
+[templates."my template"]
+title = "a"
+counterparty = { Organization = { name = "a", inn = "1" } }
+
+[[hooks]]
+command = "a"
+
+[[hooks]]
+  command = "b"
+"#;
+        let path = |p: &[&str]| p.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            locate(content, &path(&["templates", "my template"])),
+            Some((2, 1))
+        );
+        assert_eq!(
+            locate(
+                content,
+                &path(&[
+                    "templates",
+                    "my template",
+                    "counterparty",
+                    "Organization",
+                    "kind"
+                ])
+            ),
+            Some((4, 1))
+        );
+        assert_eq!(
+            locate(content, &path(&["hooks", "1", "command"])),
+            Some((10, 3))
+        );
+        assert_eq!(locate(content, &path(&["schedule"])), None);
+    }
+
+    #[test]
+    fn example_config_is_valid() {
+        let (table, problems) = parse(include_str!("../../config.toml.example")).unwrap();
+
+        assert_eq!(problems, []);
+        assert!(table["templates"].get("inherited").is_some());
+    }
+}
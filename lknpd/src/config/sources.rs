@@ -0,0 +1,527 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use serde::Serialize;
+use toml::{Table, Value};
+
+use super::Config;
+
+/// Конфиг проекта, который ищется в текущем каталоге.
+const LOCAL: &str = "./config.toml";
+
+/// Переменные окружения, которые переопределяют настройки из конфигов:
+/// переменная -> путь до настройки.
+const OVERRIDES: &[(&str, &[&str])] = &[
+    ("LKNPD_STATE_PATH", &["state_path"]),
+    ("LKNPD_PROFILE", &["profile"]),
+    ("LKNPD_BASE_URL", &["http", "base_url"]),
+];
+
+/// Настройки с путями. Относительные пути в них считаются от каталога
+/// конфига, в котором они заданы.
+const PATHS: &[&[&str]] = &[
+    &["state_path"],
+    &["clients_path"],
+    &["ledger_path"],
+    &["queue_path"],
+    &["rates_path"],
+    &["receipt_font"],
+    &["include"],
+    &["http", "root_certificates"],
+];
+
+/// Настройка, переопределённая переменной окружения.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Override {
+    pub name: &'static str,
+    pub value: String,
+
+    #[serde(skip)]
+    key: &'static [&'static str],
+}
+
+/// Откуда берутся настройки. Конфиг пользователя дополняется конфигом
+/// проекта, затем секцией выбранного профиля и переменными окружения.
+#[derive(Debug)]
+pub struct Sources {
+    /// Конфиг пользователя `$XDG_CONFIG_HOME/lknpd/config.toml`.
+    /// Его нет, если не удалось определить домашний каталог.
+    pub user: Option<PathBuf>,
+
+    /// Конфиг проекта, по-умолчанию `./config.toml`.
+    pub local: PathBuf,
+
+    /// Конфиг проекта указан явно, поэтому он должен существовать.
+    explicit: bool,
+
+    pub overrides: Vec<Override>,
+
+    /// Каталог для файлов состояния `$XDG_STATE_HOME/lknpd`.
+    state_dir: Option<PathBuf>,
+}
+
+impl Sources {
+    /// Определяет источники настроек по переменным окружения процесса.
+    pub fn new(local: Option<PathBuf>) -> Self {
+        Self::with_env(local, |name| std::env::var(name).ok())
+    }
+
+    fn with_env(local: Option<PathBuf>, env: impl Fn(&str) -> Option<String>) -> Self {
+        let overrides = OVERRIDES
+            .iter()
+            .filter_map(|&(name, key)| {
+                env(name)
+                    .filter(|v| !v.is_empty())
+                    .map(|value| Override { name, value, key })
+            })
+            .collect();
+
+        Self {
+            user: xdg_dir(&env, "XDG_CONFIG_HOME", ".config").map(|d| d.join("config.toml")),
+            explicit: local.is_some(),
+            local: local.unwrap_or_else(|| PathBuf::from(LOCAL)),
+            overrides,
+            state_dir: xdg_dir(&env, "XDG_STATE_HOME", ".local/state"),
+        }
+    }
+
+    /// Возвращает существующие конфиги в порядке применения.
+    pub fn files(&self) -> anyhow::Result<Vec<PathBuf>> {
+        if self.explicit && !self.local.exists() {
+            return Err(anyhow!(
+                "config {:?} not found, create it with `lknpd init`",
+                self.local
+            ));
+        }
+
+        let mut files: Vec<PathBuf> = self.user.iter().cloned().collect();
+        if !files.iter().any(|f| same_file(f, &self.local)) {
+            files.push(self.local.clone());
+        }
+        files.retain(|f| f.exists());
+
+        if files.is_empty() {
+            return Err(anyhow!(
+                "config not found in {}, create it with `lknpd init`",
+                self.user
+                    .iter()
+                    .chain([&self.local])
+                    .map(|p| format!("{:?}", p))
+                    .collect::<Vec<_>>()
+                    .join(" or ")
+            ));
+        }
+
+        Ok(files)
+    }
+
+    /// Возвращает конфиг, в который дописываются новые шаблоны: указанный явно
+    /// или найденный в текущем каталоге, иначе конфиг пользователя.
+    pub fn target(&self) -> PathBuf {
+        match &self.user {
+            Some(user) if !self.explicit && !self.local.exists() => user.clone(),
+            _ => self.local.clone(),
+        }
+    }
+
+    /// Собирает конфиг из содержимого конфигов в порядке применения.
+    pub fn merge(&self, tables: Vec<Table>) -> anyhow::Result<Config> {
+        let mut merged = Table::new();
+        for table in tables {
+            merge(&mut merged, table);
+        }
+
+        // Профиль может быть задан в переменной окружения, поэтому его секция
+        // применяется после неё, а остальные переменные ещё раз поверх.
+        self.apply_overrides(&mut merged);
+
+        let profile = merged
+            .get("profile")
+            .and_then(Value::as_str)
+            .map(str::to_owned);
+
+        if let Some(profile) = &profile {
+            let profiles = merged.get("profiles").and_then(Value::as_table);
+
+            let section = profiles
+                .and_then(|p| p.get(profile.as_str()))
+                .and_then(Value::as_table)
+                .cloned()
+                .ok_or_else(|| {
+                    let known: Vec<&str> = profiles
+                        .map(|p| p.keys().map(String::as_str).collect())
+                        .unwrap_or_default();
+
+                    anyhow!(
+                        "profile {} not found in [profiles], known profiles: {}",
+                        profile,
+                        match known.is_empty() {
+                            true => "none".to_owned(),
+                            false => known.join(", "),
+                        }
+                    )
+                })?;
+
+            merge(&mut merged, section);
+            self.apply_overrides(&mut merged);
+        }
+
+        let mut cfg: Config = Value::Table(merged).try_into().context("invalid config")?;
+
+        if cfg.state_path.as_os_str().is_empty() {
+            cfg.state_path = self.default_state_path(profile.as_deref())?;
+        }
+
+        Ok(cfg)
+    }
+
+    /// Файл состояния по-умолчанию. У каждого профиля свой каталог, чтобы
+    /// токены разных аккаунтов не перезаписывали друг друга.
+    pub fn default_state_path(&self, profile: Option<&str>) -> anyhow::Result<PathBuf> {
+        let dir = self.state_dir.clone().ok_or(anyhow!(
+            "can't find state directory, set state_path in config or LKNPD_STATE_PATH"
+        ))?;
+
+        Ok(match profile {
+            Some(profile) => dir.join(profile).join("state.json"),
+            None => dir.join("state.json"),
+        })
+    }
+
+    fn apply_overrides(&self, table: &mut Table) {
+        for o in &self.overrides {
+            let mut value = Value::String(o.value.clone());
+            for key in o.key.iter().rev() {
+                value = Value::Table(Table::from_iter([(key.to_string(), value)]));
+            }
+
+            if let Value::Table(overlay) = value {
+                merge(table, overlay);
+            }
+        }
+    }
+}
+
+/// Делает относительные пути в содержимом конфига относительными каталога
+/// этого конфига, чтобы они не зависели от текущего каталога.
+pub fn relative_to(table: &mut Table, dir: &Path) {
+    for key in PATHS {
+        resolve(table, key, dir);
+    }
+
+    if let Some(Value::Table(profiles)) = table.get_mut("profiles") {
+        for (_, profile) in profiles.iter_mut() {
+            if let Value::Table(profile) = profile {
+                for key in PATHS {
+                    resolve(profile, key, dir);
+                }
+            }
+        }
+    }
+}
+
+fn resolve(mut table: &mut Table, key: &[&str], dir: &Path) {
+    let Some((last, parents)) = key.split_last() else {
+        return;
+    };
+
+    for k in parents {
+        table = match table.get_mut(*k) {
+            Some(Value::Table(t)) => t,
+            _ => return,
+        };
+    }
+
+    match table.get_mut(*last) {
+        Some(Value::String(path)) => join(path, dir),
+        Some(Value::Array(values)) => {
+            for value in values {
+                if let Value::String(path) = value {
+                    join(path, dir);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn join(path: &mut String, dir: &Path) {
+    // Пути от домашнего каталога раскрываются позже, а пустые значения
+    // означают значение по-умолчанию.
+    if path.is_empty() || path.starts_with('~') || Path::new(path.as_str()).is_absolute() {
+        return;
+    }
+
+    *path = dir.join(path.as_str()).to_string_lossy().into_owned();
+}
+
+/// Дополняет таблицу значениями из другой. Вложенные таблицы объединяются,
+/// а остальные значения, в том числе массивы, заменяются целиком.
+fn merge(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(overlay)) => merge(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Возвращает каталог lknpd по спецификации XDG. Относительные пути в
+/// переменных по спецификации игнорируются.
+fn xdg_dir(env: impl Fn(&str) -> Option<String>, var: &str, fallback: &str) -> Option<PathBuf> {
+    env(var)
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| {
+            env("HOME")
+                .filter(|h| !h.is_empty())
+                .map(|h| PathBuf::from(h).join(fallback))
+        })
+        .map(|d| d.join("lknpd"))
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn sources(vars: &[(&str, &str)]) -> Sources {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        Sources::with_env(Some(PathBuf::from("project.toml")), |name| {
+            vars.get(name).cloned()
+        })
+    }
+
+    fn table(content: &str) -> Table {
+        toml::from_str(content).unwrap()
+    }
+
+    #[test]
+    fn xdg_locations() {
+        let s = sources(&[("HOME", "/home/user")]);
+        assert_eq!(
+            s.user,
+            Some(PathBuf::from("/home/user/.config/lknpd/config.toml"))
+        );
+        assert_eq!(
+            s.default_state_path(None).unwrap(),
+            PathBuf::from("/home/user/.local/state/lknpd/state.json")
+        );
+
+        let s = sources(&[
+            ("HOME", "/home/user"),
+            ("XDG_CONFIG_HOME", "/etc/xdg"),
+            ("XDG_STATE_HOME", "relative"),
+        ]);
+        assert_eq!(s.user, Some(PathBuf::from("/etc/xdg/lknpd/config.toml")));
+        assert_eq!(
+            s.default_state_path(Some("work")).unwrap(),
+            PathBuf::from("/home/user/.local/state/lknpd/work/state.json")
+        );
+
+        let s = sources(&[]);
+        assert_eq!(s.user, None);
+        assert!(s.default_state_path(None).is_err());
+    }
+
+    #[test]
+    fn merge_layers() {
+        let user = table(
+            r#"
+            [http]
+            proxy = "socks5h://127.0.0.1:1080"
+            timeout = 30
+
+            [templates.support]
+            title = "Поддержка ПО"
+            price = "1000"
+            date = "{{ now() }}"
+            counterparty = "Person"
+
+            [[hooks]]
+            command = "user"
+            "#,
+        );
+        let local = table(
+            r#"
+            [http]
+            timeout = 10
+
+            [templates.support]
+            price = "2000"
+
+            [templates.local]
+            extends = "support"
+
+            [[hooks]]
+            command = "local"
+            "#,
+        );
+
+        let cfg = sources(&[("HOME", "/home/user")])
+            .merge(vec![user, local])
+            .unwrap();
+
+        assert_eq!(
+            cfg.state_path,
+            PathBuf::from("/home/user/.local/state/lknpd/state.json")
+        );
+        assert_eq!(cfg.http.proxy.as_deref(), Some("socks5h://127.0.0.1:1080"));
+        assert_eq!(cfg.http.timeout, 10);
+        assert_eq!(cfg.template("support").unwrap().title, "Поддержка ПО");
+        assert_eq!(cfg.template("local").unwrap().price, "2000");
+        assert_eq!(cfg.hooks.len(), 1);
+    }
+
+    #[test]
+    fn profiles_and_overrides() {
+        let content = || {
+            table(
+                r#"
+                profile = "home"
+
+                [profiles.home]
+                http = { timeout = 20 }
+
+                [profiles.work]
+                state_path = "/work/state.json"
+                http = { base_url = "http://localhost:8080" }
+                "#,
+            )
+        };
+
+        let cfg = sources(&[("HOME", "/home/user")])
+            .merge(vec![content()])
+            .unwrap();
+        assert_eq!(cfg.profile.as_deref(), Some("home"));
+        assert_eq!(cfg.http.timeout, 20);
+        assert_eq!(
+            cfg.state_path,
+            PathBuf::from("/home/user/.local/state/lknpd/home/state.json")
+        );
+
+        let cfg = sources(&[("HOME", "/home/user"), ("LKNPD_PROFILE", "work")])
+            .merge(vec![content()])
+            .unwrap();
+        assert_eq!(cfg.http.timeout, 5);
+        assert_eq!(cfg.http.base_url, "http://localhost:8080");
+        assert_eq!(cfg.state_path, PathBuf::from("/work/state.json"));
+
+        let s = sources(&[
+            ("LKNPD_PROFILE", "work"),
+            ("LKNPD_STATE_PATH", "/tmp/state.json"),
+            ("LKNPD_BASE_URL", "http://127.0.0.1:9000"),
+        ]);
+        assert_eq!(
+            s.overrides.iter().map(|o| o.name).collect::<Vec<_>>(),
+            ["LKNPD_STATE_PATH", "LKNPD_PROFILE", "LKNPD_BASE_URL"]
+        );
+
+        let cfg = s.merge(vec![content()]).unwrap();
+        assert_eq!(cfg.http.base_url, "http://127.0.0.1:9000");
+        assert_eq!(cfg.state_path, PathBuf::from("/tmp/state.json"));
+    }
+
+    #[test]
+    fn unknown_profile() {
+        let content = table(
+            r#"
+            [profiles.home]
+            [profiles.work]
+            "#,
+        );
+
+        let err = sources(&[("HOME", "/home/user"), ("LKNPD_PROFILE", "wrok")])
+            .merge(vec![content])
+            .unwrap_err();
+        let message = err.to_string();
+        assert!(
+            message.starts_with("profile wrok not found in [profiles], known profiles: "),
+            "{}",
+            message
+        );
+        assert!(
+            message.contains("home") && message.contains("work"),
+            "{}",
+            message
+        );
+
+        let err = sources(&[("HOME", "/home/user"), ("LKNPD_PROFILE", "work")])
+            .merge(Vec::new())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "profile work not found in [profiles], known profiles: none"
+        );
+    }
+
+    #[test]
+    fn paths_relative_to_config() {
+        let mut content = table(
+            r#"
+            state_path = "state.json"
+            ledger_path = "/var/lib/lknpd/ledger.sqlite"
+            rates_path = "~/rates"
+            include = ["templates", "/etc/lknpd/templates"]
+
+            [http]
+            root_certificates = ["ca.pem"]
+
+            [profiles.work]
+            state_path = "work/state.json"
+            "#,
+        );
+        relative_to(&mut content, Path::new("/home/user/.config/lknpd"));
+
+        let cfg = sources(&[("HOME", "/home/user"), ("LKNPD_PROFILE", "work")])
+            .merge(vec![content])
+            .unwrap();
+
+        assert_eq!(
+            cfg.state_path,
+            PathBuf::from("/home/user/.config/lknpd/work/state.json")
+        );
+        assert_eq!(
+            cfg.ledger_path,
+            PathBuf::from("/var/lib/lknpd/ledger.sqlite")
+        );
+        assert_eq!(cfg.rates_path, PathBuf::from("~/rates"));
+        assert_eq!(
+            cfg.include,
+            [
+                PathBuf::from("/home/user/.config/lknpd/templates"),
+                PathBuf::from("/etc/lknpd/templates")
+            ]
+        );
+        assert_eq!(
+            cfg.http.root_certificates,
+            [PathBuf::from("/home/user/.config/lknpd/ca.pem")]
+        );
+    }
+
+    #[test]
+    fn missing_files() {
+        let err = sources(&[]).files().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "config \"project.toml\" not found, create it with `lknpd init`"
+        );
+
+        let s = Sources::with_env(None, |_| None);
+        assert_eq!(s.target(), PathBuf::from("./config.toml"));
+    }
+}
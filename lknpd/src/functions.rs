@@ -1,35 +1,158 @@
-use chrono::{Datelike, Local};
+use chrono::{Datelike, Days, Months, NaiveDate};
+
+use crate::clock::Clock;
 
 /// Исполняет запрошенную функцию и возвращает результат выполнения.
-pub fn execute(name: &str) -> Result {
+/// Текущий момент времени берётся из переданных часов.
+pub fn execute(name: &str, clock: &Clock) -> Result {
+    let now = clock.now();
+
     let value = match name {
-        "now" => Local::now().format("%FT%X%:z").to_string(),
-        "year" => Local::now().year().to_string(),
-        "month" => month(),
-        _ => return Err(Error::UnknownFunction(name.to_owned())),
+        "now" => now.format("%FT%X%:z").to_string(),
+        _ => return period(name, now.date_naive()),
     };
 
     Ok(value)
 }
 
-fn month() -> String {
-    match Local::now().month0() {
-        0 => "Январь",
-        1 => "Февраль",
-        2 => "Март",
-        3 => "Апрель",
-        4 => "Май",
-        5 => "Июнь",
-        6 => "Июль",
-        7 => "Август",
-        8 => "Сентябрь",
-        9 => "Октябрь",
-        10 => "Ноябрь",
-        11 => "Декабрь",
-        _ => unreachable!("unknown month"),
+/// Исполняет функции, связанные с периодами: годом, кварталом и месяцем.
+///
+/// Имя функции собирается из необязательного префикса `prev`/`next`, который
+/// сдвигает период на один назад или вперёд, названия периода и необязательного
+/// суффикса, задающего формат. Например `month`, `prevMonthGenitive`,
+/// `nextQuarterPeriod`.
+fn period(name: &str, today: NaiveDate) -> Result {
+    let unknown = || Error::UnknownFunction(name.to_owned());
+
+    let (shift, rest) = if let Some(rest) = name.strip_prefix("prev") {
+        (-1, rest)
+    } else if let Some(rest) = name.strip_prefix("next") {
+        (1, rest)
+    } else {
+        (0, name)
+    };
+
+    // После префикса название периода пишется с заглавной буквы.
+    let rest = match (shift, rest.split_at_checked(1)) {
+        (0, _) => rest.to_owned(),
+        (_, Some((first, tail))) if first.chars().all(|c| c.is_ascii_uppercase()) => {
+            first.to_ascii_lowercase() + tail
+        }
+        _ => return Err(unknown()),
+    };
+
+    if let Some(format) = rest.strip_prefix("month") {
+        let start = shift_months(month_start(today), shift).ok_or_else(unknown)?;
+        let end = shift_months(start, 1)
+            .and_then(|d| d.pred_opt())
+            .ok_or_else(unknown)?;
+
+        let value = match format {
+            "" => month_name(start.month0(), Case::Nominative),
+            "Lower" => month_name(start.month0(), Case::Nominative).to_lowercase(),
+            "Genitive" => month_name(start.month0(), Case::Genitive),
+            "GenitiveLower" => month_name(start.month0(), Case::Genitive).to_lowercase(),
+            "Prepositional" => month_name(start.month0(), Case::Prepositional),
+            "PrepositionalLower" => month_name(start.month0(), Case::Prepositional).to_lowercase(),
+            "Number" => format!("{:02}", start.month()),
+            "Year" => start.year().to_string(),
+            "Period" => period_range(start, end),
+            _ => return Err(unknown()),
+        };
+
+        return Ok(value);
+    }
+
+    if let Some(format) = rest.strip_prefix("quarter") {
+        let start = shift_months(quarter_start(today), 3 * shift).ok_or_else(unknown)?;
+        let end = shift_months(start, 3)
+            .and_then(|d| d.pred_opt())
+            .ok_or_else(unknown)?;
+
+        let value = match format {
+            "" => (start.month0() / 3 + 1).to_string(),
+            "Year" => start.year().to_string(),
+            "Period" => period_range(start, end),
+            _ => return Err(unknown()),
+        };
+
+        return Ok(value);
+    }
+
+    if let Some(format) = rest.strip_prefix("year") {
+        let start = shift_months(year_start(today), 12 * shift).ok_or_else(unknown)?;
+        let end = shift_months(start, 12)
+            .and_then(|d| d.pred_opt())
+            .ok_or_else(unknown)?;
+
+        let value = match format {
+            "" => start.year().to_string(),
+            "Period" => period_range(start, end),
+            _ => return Err(unknown()),
+        };
+
+        return Ok(value);
+    }
+
+    Err(unknown())
+}
+
+fn month_start(date: NaiveDate) -> NaiveDate {
+    date - Days::new(date.day0().into())
+}
+
+fn quarter_start(date: NaiveDate) -> NaiveDate {
+    let start = month_start(date);
+    start - Months::new(start.month0() % 3)
+}
+
+fn year_start(date: NaiveDate) -> NaiveDate {
+    let start = month_start(date);
+    start - Months::new(start.month0())
+}
+
+fn shift_months(date: NaiveDate, months: i32) -> Option<NaiveDate> {
+    if months < 0 {
+        date.checked_sub_months(Months::new(months.unsigned_abs()))
+    } else {
+        date.checked_add_months(Months::new(months.unsigned_abs()))
     }
-    .to_owned()
-    .clone()
+}
+
+fn period_range(start: NaiveDate, end: NaiveDate) -> String {
+    format!("{}–{}", start.format("%d.%m.%Y"), end.format("%d.%m.%Y"))
+}
+
+/// Падеж, в котором нужно вернуть название месяца.
+#[derive(Debug, Clone, Copy)]
+enum Case {
+    /// Именительный: "Март".
+    Nominative,
+
+    /// Родительный: "Марта".
+    Genitive,
+
+    /// Предложный: "Марте".
+    Prepositional,
+}
+
+fn month_name(month0: u32, case: Case) -> String {
+    const NAMES: [[&str; 3]; 12] = [
+        ["Январь", "Января", "Январе"],
+        ["Февраль", "Февраля", "Феврале"],
+        ["Март", "Марта", "Марте"],
+        ["Апрель", "Апреля", "Апреле"],
+        ["Май", "Мая", "Мае"],
+        ["Июнь", "Июня", "Июне"],
+        ["Июль", "Июля", "Июле"],
+        ["Август", "Августа", "Августе"],
+        ["Сентябрь", "Сентября", "Сентябре"],
+        ["Октябрь", "Октября", "Октябре"],
+        ["Ноябрь", "Ноября", "Ноябре"],
+        ["Декабрь", "Декабря", "Декабре"],
+    ];
+
+    NAMES[month0 as usize][case as usize].to_owned()
 }
 
 pub type Result = std::result::Result<String, Error>;
@@ -39,3 +162,130 @@ pub enum Error {
     #[error("unknown function \"{0}\"")]
     UnknownFunction(String),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    fn at(now: &str) -> Clock {
+        Clock::Fixed(DateTime::parse_from_rfc3339(now).unwrap())
+    }
+
+    fn check(now: &str, cases: &[(&str, &str)]) {
+        let clock = at(now);
+        for (name, expected) in cases {
+            assert_eq!(
+                execute(name, &clock).unwrap(),
+                *expected,
+                "function {} at {}",
+                name,
+                now
+            );
+        }
+    }
+
+    #[test]
+    fn now_and_year() {
+        check(
+            "2024-03-31T18:00:00+03:00",
+            &[
+                ("now", "2024-03-31T18:00:00+03:00"),
+                ("year", "2024"),
+                ("prevYear", "2023"),
+                ("nextYear", "2025"),
+                ("yearPeriod", "01.01.2024–31.12.2024"),
+            ],
+        );
+    }
+
+    #[test]
+    fn month_cases() {
+        check(
+            "2024-03-31T18:00:00+03:00",
+            &[
+                ("month", "Март"),
+                ("monthLower", "март"),
+                ("monthGenitive", "Марта"),
+                ("monthGenitiveLower", "марта"),
+                ("monthPrepositional", "Марте"),
+                ("monthPrepositionalLower", "марте"),
+                ("monthNumber", "03"),
+                ("monthYear", "2024"),
+                ("monthPeriod", "01.03.2024–31.03.2024"),
+            ],
+        );
+    }
+
+    #[test]
+    fn prev_and_next_month() {
+        check(
+            "2024-03-31T18:00:00+03:00",
+            &[
+                ("prevMonth", "Февраль"),
+                ("prevMonthPeriod", "01.02.2024–29.02.2024"),
+                ("nextMonthGenitiveLower", "апреля"),
+                ("nextMonthPeriod", "01.04.2024–30.04.2024"),
+            ],
+        );
+
+        // Переход через границу года.
+        check(
+            "2024-01-01T00:30:00+03:00",
+            &[
+                ("prevMonthLower", "декабрь"),
+                ("prevMonthYear", "2023"),
+                ("prevMonthPeriod", "01.12.2023–31.12.2023"),
+            ],
+        );
+        check(
+            "2023-12-15T12:00:00+03:00",
+            &[
+                ("nextMonthPrepositional", "Январе"),
+                ("nextMonthYear", "2024"),
+            ],
+        );
+    }
+
+    #[test]
+    fn quarters() {
+        check(
+            "2024-05-20T12:00:00+03:00",
+            &[
+                ("quarter", "2"),
+                ("quarterYear", "2024"),
+                ("quarterPeriod", "01.04.2024–30.06.2024"),
+                ("prevQuarter", "1"),
+                ("prevQuarterPeriod", "01.01.2024–31.03.2024"),
+                ("nextQuarter", "3"),
+            ],
+        );
+        check(
+            "2024-02-01T12:00:00+03:00",
+            &[
+                ("prevQuarter", "4"),
+                ("prevQuarterYear", "2023"),
+                ("prevQuarterPeriod", "01.10.2023–31.12.2023"),
+            ],
+        );
+    }
+
+    #[test]
+    fn unknown_functions() {
+        let clock = at("2024-03-31T18:00:00+03:00");
+        for name in [
+            "foo",
+            "prev",
+            "prevmonth",
+            "prevNow",
+            "monthFoo",
+            "quarterLower",
+        ] {
+            assert!(
+                matches!(execute(name, &clock), Err(Error::UnknownFunction(n)) if n == name),
+                "function {}",
+                name
+            );
+        }
+    }
+}
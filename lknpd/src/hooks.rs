@@ -0,0 +1,412 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use anyhow::anyhow;
+use chrono::{DateTime, FixedOffset};
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::{HttpSettings, Receipt},
+    clock::Clock,
+    model::{Check, Counterparty},
+    template::compiled::{Field, FieldValues},
+};
+
+/// Действие, которое выполняется после выписки чека.
+///
+/// Если указано сообщение, то оно рендерится как шаблон, в котором доступны
+/// данные чека, и передаётся вместо JSON с данными чека.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Hook {
+    /// Shell команда. Данные чека передаются в переменных окружения с
+    /// префиксом `LKNPD_`, а сообщение или JSON с данными чека в stdin.
+    Command {
+        command: String,
+
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+    },
+
+    /// POST запрос на указанный URL.
+    Webhook {
+        url: String,
+
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+
+        /// Дополнительные заголовки запроса.
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        headers: HashMap<String, String>,
+    },
+}
+
+/// Выписанный чек, данные которого передаются в хуки.
+#[derive(Serialize, Debug, Clone)]
+pub struct Event {
+    /// Название шаблона, по которому выписан чек.
+    pub template: String,
+
+    /// Идентификатор чека.
+    pub uuid: String,
+
+    /// Ссылка на печатную форму чека.
+    pub url: String,
+
+    /// ИНН самозанятого.
+    pub inn: String,
+
+    /// Момент, когда чек был выписан.
+    pub issued_at: DateTime<FixedOffset>,
+
+    /// Выписанный чек.
+    pub check: Check,
+}
+
+impl Event {
+    pub fn new(template: &str, check: Check, receipt: Receipt, inn: String, clock: &Clock) -> Self {
+        Self {
+            template: template.to_owned(),
+            uuid: receipt.uuid,
+            url: receipt.url,
+            inn,
+            issued_at: clock.now(),
+            check,
+        }
+    }
+
+    /// Возвращает данные чека в виде значений переменных для шаблона
+    /// сообщения.
+    pub fn values(&self) -> FieldValues {
+        let check = self.check.clone();
+
+        let (client_name, client_inn) = match check.counterparty {
+            Counterparty::Person => (String::new(), String::new()),
+            Counterparty::Organization { name, inn } => (name.into(), inn.into()),
+        };
+        let price: u32 = check.price.into();
+
+        FieldValues::from([
+            ("template".to_owned(), self.template.clone()),
+            ("uuid".to_owned(), self.uuid.clone()),
+            ("url".to_owned(), self.url.clone()),
+            ("inn".to_owned(), self.inn.clone()),
+            (
+                "issuedAt".to_owned(),
+                self.issued_at.format("%FT%X%:z").to_string(),
+            ),
+            ("title".to_owned(), check.title.into()),
+            ("price".to_owned(), price.to_string()),
+            ("date".to_owned(), check.date.format("%FT%X%:z").to_string()),
+            ("clientName".to_owned(), client_name),
+            ("clientInn".to_owned(), client_inn),
+        ])
+    }
+}
+
+/// Выполняет все хуки по очереди. Ошибки хуков только логируются, так как чек
+/// к этому моменту уже выписан. Запросы вебхуков идут через HTTP клиент с
+/// настройками из секции `http`.
+pub fn run(hooks: &[Hook], http: &HttpSettings, event: &Event, clock: &Clock) {
+    for (i, hook) in hooks.iter().enumerate() {
+        debug!("Выполняем хук #{} для чека {}", i, event.uuid);
+
+        if let Err(e) = hook.run(http, event, clock) {
+            error!(
+                "Хук #{} для чека {} завершился ошибкой: {:#}",
+                i, event.uuid, e
+            );
+        }
+    }
+}
+
+impl Hook {
+    fn run(&self, http: &HttpSettings, event: &Event, clock: &Clock) -> anyhow::Result<()> {
+        match self {
+            Self::Command { command, message } => {
+                let input = Self::body(message, event, clock)?;
+                run_command(command, event, &input)
+            }
+            Self::Webhook {
+                url,
+                message,
+                headers,
+            } => {
+                let body = Self::body(message, event, clock)?;
+
+                // Без сообщения отправляется JSON с данными чека, а сообщение
+                // это обычный текст.
+                let content_type = match message {
+                    Some(_) => "text/plain; charset=utf-8",
+                    None => "application/json",
+                };
+
+                post(&http.client()?, url, content_type, headers, body)
+            }
+        }
+    }
+
+    /// Возвращает отрендеренное сообщение или JSON с данными чека, если
+    /// сообщение не указано.
+    fn body(message: &Option<String>, event: &Event, clock: &Clock) -> anyhow::Result<String> {
+        match message {
+            Some(m) => {
+                let field = Field::new(m).map_err(|e| anyhow!(e).context("message"))?;
+                let body = field
+                    .render(&event.values(), clock)
+                    .map_err(|e| anyhow!(e).context("message"))?;
+                Ok(body)
+            }
+            None => Ok(serde_json::to_string(event)?),
+        }
+    }
+}
+
+fn run_command(command: &str, event: &Event, input: &str) -> anyhow::Result<()> {
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+
+    let mut child = Command::new(shell)
+        .arg(flag)
+        .arg(command)
+        .envs(event.values().into_iter().map(|(k, v)| (env_name(&k), v)))
+        .stdin(Stdio::piped())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // Команда может не читать stdin и завершиться раньше, это не ошибка.
+        let _ = stdin.write_all(input.as_bytes());
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(anyhow!("command {:?} exited with {}", command, status));
+    }
+
+    info!("Выполнена команда {:?}", command);
+
+    Ok(())
+}
+
+/// Превращает название переменной в название переменной окружения:
+/// `clientInn` -> `LKNPD_CLIENT_INN`.
+fn env_name(name: &str) -> String {
+    let mut result = String::from("LKNPD_");
+    for c in name.chars() {
+        if c.is_ascii_uppercase() {
+            result.push('_');
+        }
+        result.push(c.to_ascii_uppercase());
+    }
+    result
+}
+
+fn post(
+    client: &reqwest::blocking::Client,
+    url: &str,
+    content_type: &str,
+    headers: &HashMap<String, String>,
+    body: String,
+) -> anyhow::Result<()> {
+    let mut req = client.post(url);
+
+    // Заголовок из настроек хука важнее типа по-умолчанию.
+    if !headers
+        .keys()
+        .any(|k| k.eq_ignore_ascii_case(reqwest::header::CONTENT_TYPE.as_str()))
+    {
+        req = req.header(reqwest::header::CONTENT_TYPE, content_type);
+    }
+
+    for (name, value) in headers {
+        req = req.header(name, value);
+    }
+
+    let resp = req.body(body).send()?;
+
+    let status = resp.status();
+    if !status.is_success() {
+        return Err(anyhow!(
+            "{} responded with {}: {}",
+            url,
+            status,
+            resp.text()?
+        ));
+    }
+
+    info!("Отправлен запрос на {}", url);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::model::{OrganizationINN, OrganizationName, Price, Title};
+
+    fn event() -> Event {
+        Event::new(
+            "support",
+            Check {
+                title: Title::new("Поддержка ПО").unwrap(),
+                price: Price::new(25000),
+                date: DateTime::parse_from_rfc3339("2024-03-31T18:00:00+03:00").unwrap(),
+                counterparty: Counterparty::Organization {
+                    name: OrganizationName::new("ООО Ромашка").unwrap(),
+                    inn: OrganizationINN::new("1234567890").unwrap(),
+                },
+                conversion: None,
+            },
+            Receipt {
+                uuid: "abc".to_owned(),
+                url: HttpSettings::default().receipt_url("123", "abc"),
+            },
+            "123".to_owned(),
+            &Clock::Fixed(DateTime::parse_from_rfc3339("2024-03-31T18:05:00+03:00").unwrap()),
+        )
+    }
+
+    #[test]
+    fn message() {
+        let body = Hook::body(
+            &Some("{{ clientName }}: {{ title }} на {{ price }} ₽, {{ url }}".to_owned()),
+            &event(),
+            &Clock::System,
+        )
+        .unwrap();
+        assert_eq!(
+            body,
+            format!("ООО Ромашка: Поддержка ПО на 25000 ₽, {}", event().url)
+        );
+
+        let body = Hook::body(&None, &event(), &Clock::System).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["uuid"], "abc");
+        assert_eq!(json["check"]["price"], 25000);
+
+        let err =
+            Hook::body(&Some("{{ unknown }}".to_owned()), &event(), &Clock::System).unwrap_err();
+        assert!(format!("{:#}", err).contains("unknown"), "{:#}", err);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn command() {
+        let path = std::env::temp_dir().join(format!("lknpd-hook-{}", std::process::id()));
+
+        let hook = Hook::Command {
+            command: format!(
+                "echo \"$LKNPD_UUID $LKNPD_CLIENT_INN\" > {:?}; cat >> {:?}",
+                path, path
+            ),
+            message: Some("{{ title }}".to_owned()),
+        };
+        hook.run(&HttpSettings::default(), &event(), &Clock::System)
+            .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "abc 1234567890\nПоддержка ПО"
+        );
+        fs::remove_file(path).unwrap();
+
+        let hook = Hook::Command {
+            command: "exit 3".to_owned(),
+            message: None,
+        };
+        assert!(hook
+            .run(&HttpSettings::default(), &event(), &Clock::System)
+            .is_err());
+    }
+
+    #[test]
+    fn webhook() {
+        use std::{io::Read, net::TcpListener};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+        // Принимает один запрос и возвращает его заголовки и тело.
+        let receive = move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut data = Vec::new();
+            let mut buf = [0; 4096];
+
+            loop {
+                let n = stream.read(&mut buf).unwrap();
+                data.extend_from_slice(&buf[..n]);
+
+                let text = String::from_utf8_lossy(&data).to_string();
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|l| {
+                            l.to_ascii_lowercase()
+                                .strip_prefix("content-length: ")
+                                .map(str::to_owned)
+                        })
+                        .and_then(|l| l.trim().parse::<usize>().ok())
+                        .unwrap_or_default();
+
+                    if body.len() >= length {
+                        stream
+                            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                            .unwrap();
+                        return (head.to_ascii_lowercase(), body.to_owned());
+                    }
+                }
+            }
+        };
+
+        let server = std::thread::spawn(move || (receive(), receive()));
+
+        let http = HttpSettings {
+            user_agent: "lknpd-test".to_owned(),
+            ..Default::default()
+        };
+        let hook = |message: Option<&str>| Hook::Webhook {
+            url: url.clone(),
+            message: message.map(str::to_owned),
+            headers: HashMap::new(),
+        };
+
+        hook(None).run(&http, &event(), &Clock::System).unwrap();
+        hook(Some("{{ title }}"))
+            .run(&http, &event(), &Clock::System)
+            .unwrap();
+
+        let ((json_head, json_body), (text_head, text_body)) = server.join().unwrap();
+
+        assert!(
+            json_head.contains("content-type: application/json"),
+            "{}",
+            json_head
+        );
+        assert!(
+            json_head.contains("user-agent: lknpd-test"),
+            "{}",
+            json_head
+        );
+        assert!(
+            json_body.contains("\"issued_at\":\"2024-03-31T18:05:00+03:00\""),
+            "{}",
+            json_body
+        );
+
+        assert!(
+            text_head.contains("content-type: text/plain; charset=utf-8"),
+            "{}",
+            text_head
+        );
+        assert_eq!(text_body, "Поддержка ПО");
+    }
+}
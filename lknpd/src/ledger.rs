@@ -0,0 +1,550 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use chrono::{DateTime, FixedOffset, Local};
+use log::{debug, error};
+use rusqlite::{params, Connection, OptionalExtension};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{
+    api::{CancelReason, Income},
+    clients::Kind,
+    hooks::Event,
+    model::{Conversion, Counterparty},
+};
+
+/// Локальный журнал выписанных и аннулированных чеков.
+pub struct Ledger {
+    conn: Connection,
+}
+
+/// Запись о чеке в журнале.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// Идентификатор чека.
+    pub uuid: String,
+
+    /// Название оказанной услуги.
+    pub title: String,
+
+    /// Сумма чека.
+    pub amount: Decimal,
+
+    /// Время, на которое выписан чек.
+    pub operation_time: DateTime<FixedOffset>,
+
+    /// Тип заказчика.
+    pub kind: Kind,
+
+    /// Название заказчика, если известно.
+    pub client_name: Option<String>,
+
+    /// ИНН заказчика, если известен.
+    pub client_inn: Option<String>,
+
+    /// Время аннулирования чека.
+    pub cancelled_at: Option<DateTime<FixedOffset>>,
+
+    /// Причина аннулирования чека.
+    pub cancel_reason: Option<String>,
+}
+
+impl From<&Event> for Entry {
+    fn from(event: &Event) -> Self {
+        let check = event.check.clone();
+
+        let (kind, client_name, client_inn) = match check.counterparty {
+            Counterparty::Person => (Kind::Person, None, None),
+            Counterparty::Organization { name, inn } => {
+                (Kind::Organization, Some(name.into()), Some(inn.into()))
+            }
+        };
+        let price: u32 = check.price.into();
+
+        Self {
+            uuid: event.uuid.clone(),
+            title: check.title.into(),
+            amount: Decimal::from(price),
+            operation_time: check.date,
+            kind,
+            client_name,
+            client_inn,
+            cancelled_at: None,
+            cancel_reason: None,
+        }
+    }
+}
+
+impl From<&Income> for Entry {
+    fn from(income: &Income) -> Self {
+        let kind = match income.income_type.as_deref() {
+            Some("FROM_INDIVIDUAL") => Kind::Person,
+            Some(_) => Kind::Organization,
+            None if income.client_inn.is_some() => Kind::Organization,
+            None => Kind::Person,
+        };
+
+        // Из информации об аннулировании нам нужны только время и причина,
+        // остальное нигде не используется.
+        let cancellation = income.cancellation_info.as_ref().filter(|c| !c.is_null());
+        let cancelled_at = cancellation.map(|c| {
+            c["operationTime"]
+                .as_str()
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                .unwrap_or(income.operation_time)
+        });
+        let cancel_reason = cancellation.and_then(|c| c["comment"].as_str().map(str::to_owned));
+
+        Self {
+            uuid: income.approved_receipt_uuid.clone(),
+            title: income.name.clone(),
+            amount: income.total_amount,
+            operation_time: income.operation_time,
+            kind,
+            client_name: income.client_display_name.clone(),
+            client_inn: income.client_inn.clone(),
+            cancelled_at,
+            cancel_reason,
+        }
+    }
+}
+
+/// Способ группировки чеков в отчёте.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Group {
+    /// По месяцам.
+    Month,
+
+    /// По заказчикам.
+    Client,
+
+    /// По типу заказчика.
+    Kind,
+}
+
+/// Строка отчёта.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Row {
+    /// Значение, по которому сгруппированы чеки.
+    pub group: String,
+
+    /// Количество действующих чеков.
+    pub checks: usize,
+
+    /// Количество аннулированных чеков.
+    pub cancelled: usize,
+
+    /// Сумма действующих чеков.
+    pub total: Decimal,
+}
+
+impl Ledger {
+    /// Открывает журнал, создавая его при необходимости.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        fs::create_dir_all(path.parent().unwrap_or(Path::new("")))?;
+
+        let conn = Connection::open(path)?;
+
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS receipts (
+                uuid TEXT PRIMARY KEY,
+                title TEXT NOT NULL,
+                amount TEXT NOT NULL,
+                operation_time TEXT NOT NULL,
+                operation_ts INTEGER NOT NULL,
+                kind TEXT NOT NULL,
+                client_name TEXT,
+                client_inn TEXT,
+                cancelled_at TEXT,
+                cancel_reason TEXT
+            );
+            CREATE INDEX IF NOT EXISTS receipts_operation_ts ON receipts (operation_ts);
+            CREATE TABLE IF NOT EXISTS conversions (
+                uuid TEXT PRIMARY KEY,
+                currency TEXT NOT NULL,
+                amount TEXT NOT NULL,
+                rate TEXT NOT NULL,
+                rate_date TEXT NOT NULL
+            );",
+        )?;
+
+        Ok(Self { conn })
+    }
+
+    /// Сохраняет чек в журнал. Если чек уже есть, то он обновляется, но
+    /// известная информация об аннулировании не теряется.
+    pub fn save(&self, entry: &Entry) -> anyhow::Result<()> {
+        self.conn.execute(
+            "INSERT INTO receipts (
+                uuid, title, amount, operation_time, operation_ts, kind,
+                client_name, client_inn, cancelled_at, cancel_reason
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+            ON CONFLICT (uuid) DO UPDATE SET
+                title = excluded.title,
+                amount = excluded.amount,
+                operation_time = excluded.operation_time,
+                operation_ts = excluded.operation_ts,
+                kind = excluded.kind,
+                client_name = coalesce(excluded.client_name, client_name),
+                client_inn = coalesce(excluded.client_inn, client_inn),
+                cancelled_at = coalesce(excluded.cancelled_at, cancelled_at),
+                cancel_reason = coalesce(excluded.cancel_reason, cancel_reason)",
+            params![
+                entry.uuid,
+                entry.title,
+                entry.amount.to_string(),
+                entry.operation_time.to_rfc3339(),
+                entry.operation_time.timestamp(),
+                kind_to_str(entry.kind),
+                entry.client_name,
+                entry.client_inn,
+                entry.cancelled_at.map(|t| t.to_rfc3339()),
+                entry.cancel_reason,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Сохраняет курс, по которому цена чека пересчитана из валюты.
+    pub fn save_conversion(&self, uuid: &str, conversion: &Conversion) -> anyhow::Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO conversions (uuid, currency, amount, rate, rate_date)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                uuid,
+                conversion.currency,
+                conversion.amount.to_string(),
+                conversion.rate.to_string(),
+                conversion.rate_date.to_string(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Отмечает чек как аннулированный.
+    pub fn cancel(
+        &self,
+        uuid: &str,
+        at: DateTime<FixedOffset>,
+        reason: &str,
+    ) -> anyhow::Result<()> {
+        self.conn.execute(
+            "UPDATE receipts SET cancelled_at = ?2, cancel_reason = ?3 WHERE uuid = ?1",
+            params![uuid, at.to_rfc3339(), reason],
+        )?;
+
+        Ok(())
+    }
+
+    /// Возвращает чек по идентификатору.
+    pub fn get(&self, uuid: &str) -> anyhow::Result<Option<Entry>> {
+        let entry = self
+            .conn
+            .query_row(
+                &format!("SELECT {} FROM receipts WHERE uuid = ?1", COLUMNS),
+                params![uuid],
+                read_entry,
+            )
+            .optional()?;
+
+        Ok(entry)
+    }
+
+    /// Возвращает чеки, выписанные в указанный промежуток, в порядке выписки.
+    pub fn entries(
+        &self,
+        from: DateTime<FixedOffset>,
+        to: DateTime<FixedOffset>,
+    ) -> anyhow::Result<Vec<Entry>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM receipts
+            WHERE operation_ts >= ?1 AND operation_ts < ?2
+            ORDER BY operation_ts, uuid",
+            COLUMNS
+        ))?;
+
+        let entries = stmt
+            .query_map(params![from.timestamp(), to.timestamp()], read_entry)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(entries)
+    }
+
+    /// Строит отчёт по чекам, выписанным в указанный промежуток.
+    pub fn report(
+        &self,
+        group: Group,
+        from: DateTime<FixedOffset>,
+        to: DateTime<FixedOffset>,
+    ) -> anyhow::Result<Vec<Row>> {
+        Ok(report(&self.entries(from, to)?, group))
+    }
+}
+
+/// Записывает выписанный чек в журнал. Ошибки только логируются, так как чек к
+/// этому моменту уже выписан, а журнал можно восстановить командой `sync`.
+pub fn record(path: &Path, event: &Event) {
+    debug!("Записываем чек {} в журнал {:?}", event.uuid, path);
+
+    let result = Ledger::open(path).and_then(|l| {
+        l.save(&Entry::from(event))?;
+
+        match &event.check.conversion {
+            Some(c) => l.save_conversion(&event.uuid, c),
+            None => Ok(()),
+        }
+    });
+
+    if let Err(e) = result {
+        error!("Не удалось записать чек {} в журнал: {:#}", event.uuid, e);
+    }
+}
+
+/// Отмечает чек в журнале как аннулированный. Ошибки только логируются, как и
+/// в [`record`].
+pub fn record_cancel(path: &Path, uuid: &str, reason: CancelReason) {
+    debug!(
+        "Отмечаем чек {} в журнале {:?} как аннулированный",
+        uuid, path
+    );
+
+    let at = Local::now().fixed_offset();
+    if let Err(e) = Ledger::open(path).and_then(|l| l.cancel(uuid, at, &reason.to_string())) {
+        error!("Не удалось отметить чек {} в журнале: {:#}", uuid, e);
+    }
+}
+
+const COLUMNS: &str = "uuid, title, amount, operation_time, kind, client_name, client_inn, cancelled_at, cancel_reason";
+
+fn read_entry(row: &rusqlite::Row) -> rusqlite::Result<Entry> {
+    let conversion = |i: usize, e: anyhow::Error| {
+        rusqlite::Error::FromSqlConversionFailure(i, rusqlite::types::Type::Text, e.into())
+    };
+    let time =
+        |i: usize, v: String| DateTime::parse_from_rfc3339(&v).map_err(|e| conversion(i, e.into()));
+
+    Ok(Entry {
+        uuid: row.get(0)?,
+        title: row.get(1)?,
+        amount: row
+            .get::<_, String>(2)?
+            .parse()
+            .map_err(|e: rust_decimal::Error| conversion(2, e.into()))?,
+        operation_time: time(3, row.get(3)?)?,
+        kind: kind_from_str(&row.get::<_, String>(4)?).map_err(|e| conversion(4, e))?,
+        client_name: row.get(5)?,
+        client_inn: row.get(6)?,
+        cancelled_at: row
+            .get::<_, Option<String>>(7)?
+            .map(|v| time(7, v))
+            .transpose()?,
+        cancel_reason: row.get(8)?,
+    })
+}
+
+fn kind_to_str(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Organization => "organization",
+        Kind::Person => "person",
+    }
+}
+
+fn kind_from_str(kind: &str) -> anyhow::Result<Kind> {
+    match kind {
+        "organization" => Ok(Kind::Organization),
+        "person" => Ok(Kind::Person),
+        _ => Err(anyhow::anyhow!("unknown client kind {}", kind)),
+    }
+}
+
+/// Группирует чеки для отчёта. Строки отсортированы по значению группы.
+fn report(entries: &[Entry], group: Group) -> Vec<Row> {
+    let mut rows: BTreeMap<String, Row> = BTreeMap::new();
+
+    for entry in entries {
+        let key = match group {
+            Group::Month => entry.operation_time.format("%Y-%m").to_string(),
+            Group::Client => match (&entry.client_name, &entry.client_inn) {
+                (Some(name), Some(inn)) => format!("{} ({})", name, inn),
+                (Some(name), None) => name.clone(),
+                (None, Some(inn)) => inn.clone(),
+                (None, None) => Kind::Person.to_string(),
+            },
+            Group::Kind => entry.kind.to_string(),
+        };
+
+        let row = rows.entry(key.clone()).or_insert_with(|| Row {
+            group: key,
+            checks: 0,
+            cancelled: 0,
+            total: Decimal::ZERO,
+        });
+
+        if entry.cancelled_at.is_some() {
+            row.cancelled += 1;
+        } else {
+            row.checks += 1;
+            row.total += entry.amount;
+        }
+    }
+
+    rows.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(s: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(s).unwrap()
+    }
+
+    fn entry(uuid: &str, at: &str, amount: i64, client: Option<(&str, &str)>) -> Entry {
+        Entry {
+            uuid: uuid.to_owned(),
+            title: "Поддержка ПО".to_owned(),
+            amount: Decimal::from(amount),
+            operation_time: time(at),
+            kind: if client.is_some() {
+                Kind::Organization
+            } else {
+                Kind::Person
+            },
+            client_name: client.map(|(name, _)| name.to_owned()),
+            client_inn: client.map(|(_, inn)| inn.to_owned()),
+            cancelled_at: None,
+            cancel_reason: None,
+        }
+    }
+
+    #[test]
+    fn save_cancel_and_report() {
+        let ledger = Ledger::open(Path::new(":memory:")).unwrap();
+
+        let acme = Some(("ООО Ромашка", "1234567890"));
+        ledger
+            .save(&entry("a", "2024-01-31T23:30:00+03:00", 1000, acme))
+            .unwrap();
+        ledger
+            .save(&entry("b", "2024-02-10T12:00:00+03:00", 2500, acme))
+            .unwrap();
+        ledger
+            .save(&entry("c", "2024-02-11T12:00:00+03:00", 700, None))
+            .unwrap();
+        ledger
+            .save(&entry("d", "2024-03-01T12:00:00+03:00", 100, None))
+            .unwrap();
+
+        ledger
+            .cancel("b", time("2024-02-12T10:00:00+03:00"), "Возврат средств")
+            .unwrap();
+
+        // Повторное сохранение, например при синхронизации без информации об
+        // аннулировании, не отменяет аннулирование.
+        ledger
+            .save(&entry("b", "2024-02-10T12:00:00+03:00", 2500, acme))
+            .unwrap();
+
+        let b = ledger.get("b").unwrap().unwrap();
+        assert_eq!(b.cancelled_at, Some(time("2024-02-12T10:00:00+03:00")));
+        assert_eq!(b.cancel_reason.as_deref(), Some("Возврат средств"));
+        assert_eq!(b.client_inn.as_deref(), Some("1234567890"));
+        assert!(ledger.get("unknown").unwrap().is_none());
+
+        let from = time("2024-01-01T00:00:00+03:00");
+        let to = time("2024-03-01T00:00:00+03:00");
+
+        let uuids: Vec<_> = ledger
+            .entries(from, to)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.uuid)
+            .collect();
+        assert_eq!(uuids, ["a", "b", "c"]);
+
+        let row = |group: &str, checks, cancelled, total: i64| Row {
+            group: group.to_owned(),
+            checks,
+            cancelled,
+            total: Decimal::from(total),
+        };
+
+        assert_eq!(
+            ledger.report(Group::Month, from, to).unwrap(),
+            [row("2024-01", 1, 0, 1000), row("2024-02", 1, 1, 700)]
+        );
+        assert_eq!(
+            ledger.report(Group::Client, from, to).unwrap(),
+            [
+                row("ООО Ромашка (1234567890)", 1, 1, 1000),
+                row("физ. лицо", 1, 0, 700)
+            ]
+        );
+        assert_eq!(
+            ledger.report(Group::Kind, from, to).unwrap(),
+            [row("физ. лицо", 1, 0, 700), row("юр. лицо", 1, 1, 1000)]
+        );
+    }
+
+    #[test]
+    fn entry_from_income() {
+        let income: Income = serde_json::from_value(serde_json::json!({
+            "approvedReceiptUuid": "abc",
+            "name": "Поддержка ПО",
+            "operationTime": "2024-02-10T12:00:00+03:00",
+            "totalAmount": 2500.5,
+            "incomeType": "FROM_LEGAL_ENTITY",
+            "clientInn": "1234567890",
+            "clientDisplayName": "ООО Ромашка",
+            "cancellationInfo": {
+                "operationTime": "2024-02-12T10:00:00+03:00",
+                "comment": "Чек сформирован ошибочно"
+            }
+        }))
+        .unwrap();
+
+        let entry = Entry::from(&income);
+        assert_eq!(entry.kind, Kind::Organization);
+        assert_eq!(entry.amount, "2500.5".parse().unwrap());
+        assert_eq!(entry.cancelled_at, Some(time("2024-02-12T10:00:00+03:00")));
+        assert_eq!(
+            entry.cancel_reason.as_deref(),
+            Some("Чек сформирован ошибочно")
+        );
+    }
+
+    #[test]
+    fn save_conversion() {
+        let ledger = Ledger::open(Path::new(":memory:")).unwrap();
+
+        let conversion = Conversion {
+            currency: "USD".to_owned(),
+            amount: "1000.5".parse().unwrap(),
+            rate: "91.3336".parse().unwrap(),
+            rate_date: chrono::NaiveDate::from_ymd_opt(2024, 3, 5).unwrap(),
+        };
+        ledger.save_conversion("a", &conversion).unwrap();
+        ledger.save_conversion("a", &conversion).unwrap();
+
+        let saved: (String, String, String, String) = ledger
+            .conn
+            .query_row(
+                "SELECT currency, amount, rate, rate_date FROM conversions WHERE uuid = 'a'",
+                [],
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)),
+            )
+            .unwrap();
+
+        assert_eq!(
+            saved,
+            (
+                "USD".to_owned(),
+                "1000.5".to_owned(),
+                "91.3336".to_owned(),
+                "2024-03-05".to_owned()
+            )
+        );
+    }
+}
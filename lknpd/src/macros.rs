@@ -4,6 +4,7 @@ macro_rules! newtype {
     ($tname:ident, String, "String", $validate_fn:ident) => {
         #[derive(
             std::fmt::Debug,
+            std::clone::Clone,
             serde::Serialize,
             serde::Deserialize,
             std::cmp::PartialEq,
@@ -43,6 +44,7 @@ macro_rules! newtype {
     ($tname:ident, $type:ty, $try_from:literal, $validate_fn:ident) => {
         #[derive(
             std::fmt::Debug,
+            std::clone::Clone,
             serde::Serialize,
             serde::Deserialize,
             std::cmp::PartialEq,
@@ -82,6 +84,7 @@ macro_rules! newtype {
     ($tname:ident, $type:ty, $try_from:literal) => {
         #[derive(
             std::fmt::Debug,
+            std::clone::Clone,
             serde::Serialize,
             serde::Deserialize,
             std::cmp::PartialEq,
@@ -0,0 +1,437 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use lettre::{
+    message::{header::ContentType, Attachment, Mailbox, MultiPart, SinglePart},
+    transport::smtp::authentication::Credentials,
+    Message, SmtpTransport, Transport,
+};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    clients::Book,
+    clock::Clock,
+    config::Config,
+    hooks::Event,
+    model::Counterparty,
+    receipt::{self, Format},
+    template::compiled::Field,
+};
+
+const DEFAULT_SUBJECT: &str = "Чек на {{ price }} ₽";
+
+const DEFAULT_MESSAGE: &str = "Здравствуйте!
+
+Чек за «{{ title }}» на {{ price }} ₽ доступен по ссылке:
+{{ url }}
+";
+
+/// Настройки SMTP сервера для отправки чеков заказчикам.
+///
+/// Тема и текст письма рендерятся как шаблоны, в которых доступны те же
+/// переменные, что и в сообщениях хуков.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Smtp {
+    pub host: String,
+
+    /// По-умолчанию 465 для `tls`, 587 для `starttls` и 25 для `none`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+
+    #[serde(default)]
+    pub security: Security,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+
+    /// Отправитель, например `Иван Иванов <ivan@example.com>`.
+    pub from: String,
+
+    #[serde(default = "default_subject")]
+    pub subject: String,
+
+    #[serde(default = "default_message")]
+    pub message: String,
+
+    /// Какой файл с чеком приложить к письму.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attach: Option<Attach>,
+}
+
+fn default_subject() -> String {
+    DEFAULT_SUBJECT.to_owned()
+}
+
+fn default_message() -> String {
+    DEFAULT_MESSAGE.to_owned()
+}
+
+/// Способ защиты соединения с SMTP сервером.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Security {
+    Tls,
+    #[default]
+    Starttls,
+    /// Без шифрования, например для локального SMTP сервера.
+    None,
+}
+
+/// Файл с чеком, который прикладывается к письму.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Attach {
+    /// Печатная форма чека, скачанная из сервиса налоговой.
+    Download,
+    /// Чек, отрендеренный в HTML.
+    Html,
+    /// Чек, отрендеренный в PDF.
+    Pdf,
+}
+
+/// Возвращает email заказчика: указанный в шаблоне или у заказчика с тем же
+/// ИНН в конфиге или адресной книге.
+pub fn recipient(cfg: &Config, book: &Book, email: Option<&str>, event: &Event) -> Option<String> {
+    if let Some(email) = email {
+        return Some(email.to_owned());
+    }
+
+    let inn: String = match event.check.counterparty.clone() {
+        Counterparty::Person => return None,
+        Counterparty::Organization { inn, .. } => inn.into(),
+    };
+
+    cfg.counterparties
+        .values()
+        .filter(|o| o.inn == inn)
+        .find_map(|o| o.email.clone())
+        .or_else(|| {
+            book.clients
+                .iter()
+                .filter(|c| c.inn == inn)
+                .find_map(|c| c.email.clone())
+        })
+}
+
+/// Отправляет заказчику письмо со ссылкой на чек. Ошибки только логируются,
+/// так как чек к этому моменту уже выписан.
+pub fn notify(
+    cfg: &Config,
+    book: &Book,
+    email: Option<&str>,
+    seller: &str,
+    event: &Event,
+    clock: &Clock,
+) {
+    let Some(smtp) = &cfg.smtp else {
+        warn!(
+            "SMTP не настроен, письмо с чеком {} не отправлено",
+            event.uuid
+        );
+        return;
+    };
+
+    let Some(to) = recipient(cfg, book, email, event) else {
+        warn!(
+            "Не найден email заказчика, письмо с чеком {} не отправлено",
+            event.uuid
+        );
+        return;
+    };
+
+    let result =
+        attachment(cfg, smtp, seller, event).and_then(|file| send(smtp, &to, event, file, clock));
+
+    match result {
+        Ok(()) => info!("Чек {} отправлен на {}", event.uuid, to),
+        Err(e) => error!("Не удалось отправить чек {} на {}: {:#}", event.uuid, to, e),
+    }
+}
+
+/// Файл, приложенный к письму.
+pub struct File {
+    pub name: String,
+    pub content_type: String,
+    pub content: Vec<u8>,
+}
+
+fn attachment(
+    cfg: &Config,
+    smtp: &Smtp,
+    seller: &str,
+    event: &Event,
+) -> anyhow::Result<Option<File>> {
+    let format = match smtp.attach {
+        None => return Ok(None),
+        Some(Attach::Download) => return download(cfg, event).map(Some),
+        Some(Attach::Html) => Format::Html,
+        Some(Attach::Pdf) => Format::Pdf,
+    };
+
+    Ok(Some(File {
+        name: format!("receipt-{}.{}", event.uuid, format.extension()),
+        content_type: format.content_type().to_owned(),
+        content: receipt::to_bytes(event, seller, format, cfg.receipt_font.as_deref())?,
+    }))
+}
+
+/// Скачивает печатную форму чека из сервиса налоговой.
+fn download(cfg: &Config, event: &Event) -> anyhow::Result<File> {
+    let resp = cfg
+        .http
+        .client()?
+        .get(&event.url)
+        .send()?
+        .error_for_status()?;
+
+    let content_type = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_owned();
+
+    let extension = match content_type.split(';').next().unwrap_or_default().trim() {
+        "image/png" => "png",
+        "image/jpeg" => "jpg",
+        "application/pdf" => "pdf",
+        "text/html" => "html",
+        _ => "bin",
+    };
+
+    Ok(File {
+        name: format!("receipt-{}.{}", event.uuid, extension),
+        content_type,
+        content: resp.bytes()?.to_vec(),
+    })
+}
+
+/// Отправляет письмо со ссылкой на чек и, если передан, файлом с чеком.
+pub fn send(
+    smtp: &Smtp,
+    to: &str,
+    event: &Event,
+    file: Option<File>,
+    clock: &Clock,
+) -> anyhow::Result<()> {
+    let values = event.values();
+    let render = |name: &str, template: &str| {
+        Field::new(template)
+            .map_err(|e| anyhow!(e).context(name.to_owned()))?
+            .render(&values, clock)
+            .map_err(|e| anyhow!(e).context(name.to_owned()))
+    };
+
+    let from: Mailbox = smtp
+        .from
+        .parse()
+        .with_context(|| format!("invalid from address {:?}", smtp.from))?;
+    let to: Mailbox = to
+        .parse()
+        .with_context(|| format!("invalid email {:?}", to))?;
+
+    let builder = Message::builder()
+        .from(from)
+        .to(to)
+        .subject(render("subject", &smtp.subject)?);
+    let text = render("message", &smtp.message)?;
+
+    let message = match file {
+        None => builder.singlepart(SinglePart::plain(text))?,
+        Some(file) => builder.multipart(
+            MultiPart::mixed()
+                .singlepart(SinglePart::plain(text))
+                .singlepart(
+                    Attachment::new(file.name)
+                        .body(file.content, ContentType::parse(&file.content_type)?),
+                ),
+        )?,
+    };
+
+    let mut transport = match smtp.security {
+        Security::Tls => SmtpTransport::relay(&smtp.host)?,
+        Security::Starttls => SmtpTransport::starttls_relay(&smtp.host)?,
+        Security::None => SmtpTransport::builder_dangerous(&smtp.host),
+    }
+    .timeout(Some(Duration::from_secs(10)));
+
+    if let Some(port) = smtp.port {
+        transport = transport.port(port);
+    }
+    if let (Some(username), Some(password)) = (&smtp.username, &smtp.password) {
+        transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+
+    transport.build().send(&message)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::{
+        api::{HttpSettings, Receipt},
+        clients::{Client, Kind},
+        model::{Check, OrganizationINN, OrganizationName, Price, Title},
+    };
+
+    fn event() -> Event {
+        Event::new(
+            "support",
+            Check {
+                title: Title::new("Поддержка ПО").unwrap(),
+                price: Price::new(25000),
+                date: DateTime::parse_from_rfc3339("2024-03-31T18:00:00+03:00").unwrap(),
+                counterparty: Counterparty::Organization {
+                    name: OrganizationName::new("ООО Ромашка").unwrap(),
+                    inn: OrganizationINN::new("1234567890").unwrap(),
+                },
+                conversion: None,
+            },
+            Receipt {
+                uuid: "abc".to_owned(),
+                url: HttpSettings::default().receipt_url("123", "abc"),
+            },
+            "123".to_owned(),
+            &Clock::System,
+        )
+    }
+
+    /// Минимальный SMTP сервер, который принимает одно письмо и возвращает
+    /// его содержимое.
+    fn sink() -> (u16, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut data = String::new();
+            let mut in_data = false;
+
+            stream.write_all(b"220 localhost\r\n").unwrap();
+
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+
+                if in_data {
+                    if line == ".\r\n" {
+                        in_data = false;
+                        stream.write_all(b"250 OK\r\n").unwrap();
+                    } else {
+                        data.push_str(&line);
+                    }
+                    continue;
+                }
+
+                let command = line.to_ascii_uppercase();
+                if command.starts_with("DATA") {
+                    in_data = true;
+                    stream.write_all(b"354 Go ahead\r\n").unwrap();
+                } else if command.starts_with("QUIT") {
+                    stream.write_all(b"221 Bye\r\n").unwrap();
+                    break;
+                } else {
+                    stream.write_all(b"250 OK\r\n").unwrap();
+                }
+            }
+
+            data
+        });
+
+        (port, handle)
+    }
+
+    #[test]
+    fn send_to_local_sink() {
+        let (port, handle) = sink();
+
+        let smtp = Smtp {
+            host: "127.0.0.1".to_owned(),
+            port: Some(port),
+            security: Security::None,
+            username: None,
+            password: None,
+            from: "ivan@example.com".to_owned(),
+            subject: "Receipt {{ uuid }}".to_owned(),
+            message: "Link: {{ url }}".to_owned(),
+            attach: Some(Attach::Html),
+        };
+        let file = File {
+            name: "receipt-abc.html".to_owned(),
+            content_type: "text/html; charset=utf-8".to_owned(),
+            content: b"<html></html>".to_vec(),
+        };
+
+        send(
+            &smtp,
+            "acme@example.com",
+            &event(),
+            Some(file),
+            &Clock::System,
+        )
+        .unwrap();
+
+        let data = handle.join().unwrap();
+        assert!(data.contains("Subject: Receipt abc"), "{}", data);
+        assert!(data.contains("To: acme@example.com"), "{}", data);
+        assert!(data.contains(&format!("Link: {}", event().url)), "{}", data);
+        assert!(data.contains("receipt-abc.html"), "{}", data);
+    }
+
+    #[test]
+    fn find_recipient() {
+        let mut cfg: Config = toml::from_str(
+            r#"
+            state_path = "state.json"
+            templates = {}
+
+            [counterparties.acme]
+            name = "ООО Ромашка"
+            inn = "1234567890"
+            "#,
+        )
+        .unwrap();
+        let mut book = Book::default();
+
+        assert_eq!(recipient(&cfg, &book, None, &event()), None);
+        assert_eq!(
+            recipient(&cfg, &book, Some("tmpl@example.com"), &event()).as_deref(),
+            Some("tmpl@example.com")
+        );
+
+        book.clients.push(Client {
+            name: "ООО Ромашка".to_owned(),
+            inn: "1234567890".to_owned(),
+            kind: Kind::Organization,
+            phone: None,
+            email: Some("book@example.com".to_owned()),
+        });
+        assert_eq!(
+            recipient(&cfg, &book, None, &event()).as_deref(),
+            Some("book@example.com")
+        );
+
+        cfg.counterparties.get_mut("acme").unwrap().email = Some("acme@example.com".to_owned());
+        assert_eq!(
+            recipient(&cfg, &book, None, &event()).as_deref(),
+            Some("acme@example.com")
+        );
+    }
+}
@@ -1,39 +1,137 @@
 mod api;
+mod batch;
 mod cli;
+mod clients;
+mod clock;
+mod commands;
 mod config;
 mod functions;
+mod hooks;
+mod ledger;
 mod macros;
+mod mail;
 mod model;
+mod output;
+mod queue;
+mod rates;
+mod receipt;
+mod schedule;
 mod state;
+mod statement;
 mod template;
 
 use std::{error::Error, path::PathBuf};
 
-use anyhow::anyhow;
 use api::{AuthorizedClient, PhoneAuthenticator};
+use chrono::{DateTime, FixedOffset};
 use clap::Parser;
-use log::debug;
+use clock::Clock;
+use log::{debug, error, info};
 use model::{AccessToken, RefreshToken};
+use output::Output;
+use serde_json::json;
 use state::State;
 use template::compiled;
 
 #[derive(Parser)]
 #[command(name = env!("CARGO_BIN_NAME"))]
 #[command(bin_name = env!("CARGO_BIN_NAME"))]
-enum Cli {
+struct Cli {
+    #[arg(long, global = true, value_enum, default_value_t = Output::Text)]
+    #[arg(help = "Format of the command result printed to stdout")]
+    output: Output,
+
+    #[arg(long, global = true)]
+    #[arg(help = "Don't copy the check URL to the clipboard")]
+    no_clipboard: bool,
+
+    #[arg(long, global = true, value_name = "FILE", env = "LKNPD_TRACE_HTTP")]
+    #[arg(help = "Append sanitized HAR-like records of API requests to the file")]
+    trace_http: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
     #[command(about = "Prints tool version")]
     #[command(long_about = None)]
     Version,
 
+    #[command(about = "Interactively create a config with a first template")]
+    #[command(long_about = None)]
+    Init(commands::init::Args),
+
+    #[command(about = "Check the config and show where it is loaded from")]
+    #[command(long_about = None)]
+    Config(commands::config::Args),
+
     #[command(about = "Make a check from provided template")]
     #[command(long_about = None)]
     Check(CheckArgs),
+
+    #[command(about = "Manage templates")]
+    #[command(long_about = None)]
+    Templates(commands::templates::Args),
+
+    #[command(about = "Manage the address book of clients")]
+    #[command(long_about = None)]
+    Clients(commands::clients::Args),
+
+    #[command(about = "Issue every scheduled check which is due")]
+    #[command(long_about = None)]
+    RunDue(commands::schedule::Args),
+
+    #[command(about = "Serve a local HTTP JSON API for issuing checks")]
+    #[command(long_about = None)]
+    Serve(commands::serve::Args),
+
+    #[command(about = "Load checks issued in a period into the local ledger")]
+    #[command(long_about = None)]
+    Sync(commands::ledger::SyncArgs),
+
+    #[command(about = "Report on checks from the local ledger")]
+    #[command(long_about = None)]
+    Report(commands::ledger::ReportArgs),
+
+    #[command(about = "Manage checks which weren't issued because the service was unavailable")]
+    #[command(long_about = None)]
+    Queue(commands::queue::Args),
+
+    #[command(about = "Issue checks described in a batch file")]
+    #[command(long_about = None)]
+    Batch(commands::batch::Args),
+
+    #[command(about = "Propose checks for incoming payments from a bank statement")]
+    #[command(long_about = None)]
+    ImportStatement(commands::statement::ImportArgs),
+
+    #[command(about = "Reconcile issued checks against payments from a bank statement")]
+    #[command(long_about = None)]
+    Reconcile(commands::statement::ReconcileArgs),
 }
 
 #[derive(clap::Args)]
 struct CheckArgs {
-    #[arg(short='c', long, default_value=Some("./config.toml"))]
-    config_path: PathBuf,
+    #[arg(short = 'c', long, env = "LKNPD_CONFIG")]
+    config_path: Option<PathBuf>,
+
+    #[arg(long, env = "LKNPD_NOW")]
+    #[arg(help = "Time used as current by template functions, e.g. 2024-03-31T18:00:00+03:00")]
+    now: Option<DateTime<FixedOffset>>,
+
+    #[arg(long)]
+    #[arg(help = "Forget remembered values of the template variables and don't remember new ones")]
+    forget: bool,
+
+    #[arg(long, value_name = "FILE")]
+    #[arg(help = "Also render the receipt to an .html or .pdf file, can be repeated")]
+    receipt: Vec<PathBuf>,
+
+    #[arg(long)]
+    #[arg(help = "Email the receipt link to the client using [smtp] settings from config")]
+    send_email: bool,
 
     #[arg()]
     template: String,
@@ -45,53 +143,197 @@ fn main() -> Result<(), Box<dyn Error>> {
         .parse_default_env()
         .init();
 
-    match Cli::parse() {
-        Cli::Version => {
-            println!(env!("CARGO_PKG_VERSION"));
+    let cli = Cli::parse();
+    let output = cli.output;
+
+    if let Some(path) = &cli.trace_http {
+        api::enable_trace(path)?;
+    }
+
+    match cli.command {
+        Command::Version => {
+            let version = env!("CARGO_PKG_VERSION");
+            output.print(&json!({ "version": version }), || println!("{}", version))?;
         }
-        Cli::Check(args) => {
-            debug!("Подгружаем конфиг из {:?}", args.config_path);
+        Command::Check(args) => {
             let cfg = config::load(args.config_path)?;
 
             debug!("Подгружаем состояние из {:?}", cfg.state_path);
             let mut state = state::load(&cfg.state_path)?;
 
-            let raw_tmpl = cfg
-                .templates
-                .get(&args.template)
-                .ok_or(anyhow!("template {} not found", args.template))?
-                .clone();
+            if args.send_email && cfg.smtp.is_none() {
+                return Err(
+                    anyhow::anyhow!("--send-email requires [smtp] settings in config").into(),
+                );
+            }
 
-            let mut client = get_client(&state)?;
+            // Формат проверяем заранее, потому что после выписки чека ошибки
+            // сохранения только логируются.
+            for path in &args.receipt {
+                receipt::Format::from_path(path)?;
+            }
 
-            let tmpl = compiled::Template::new(raw_tmpl)?;
+            let raw_tmpl = cfg.template(&args.template)?;
 
-            let values = cli::ask(tmpl.get_fields())?;
+            let mut client = get_client(&state, &cfg.http)?;
 
-            let check = tmpl.build_check(&values)?;
+            let clock = Clock::from(args.now);
+            debug!("Используем часы {:?}", clock);
 
-            let url = client.register_income(check)?;
+            let email = raw_tmpl.email.clone();
 
-            println!("Чек доступен но URL: {}", url);
+            let tmpl = compiled::Template::new(raw_tmpl, clock)?.with_rates(cfg.rates());
+            let no_history = tmpl.no_history();
 
-            cli_clipboard::set_contents(url)?;
+            if args.forget {
+                debug!(
+                    "Забываем запомненные значения для шаблона {}",
+                    args.template
+                );
+                state.forget(&args.template);
+            }
 
-            println!("Так же чек скопирован в буфер обмена");
+            debug!("Подгружаем адресную книгу из {:?}", cfg.clients_path);
+            let book = clients::load(&cfg.clients_path)?;
+
+            let values = cli::ask(
+                tmpl.get_fields(),
+                &clock,
+                &state.last_values(&args.template),
+                &book.clients,
+            )?;
+
+            let check = tmpl.build_check(&values)?;
+
+            let issued = match client.register_income(check.clone()) {
+                Ok(receipt) => {
+                    let event =
+                        hooks::Event::new(&args.template, check, receipt, client.get_inn(), &clock);
+
+                    // Журнал и состояние сохраняем сразу после выписки, до
+                    // необязательных действий, каждое из которых может не сработать.
+                    ledger::record(&cfg.ledger_path, &event);
+
+                    Some(event)
+                }
+                // Сервис недоступен, поэтому сохраняем собранный чек в очередь,
+                // чтобы не заполнять его заново.
+                Err(e) if queue::is_temporary(&e) => {
+                    error!("Не удалось выписать чек: {:#}", e);
+
+                    let item = queue::Item::new(&args.template, check, &e);
+                    queue::push(&cfg.queue_path, item.clone())?;
+
+                    output.print(&json!({ "queued": item }), || {
+                        println!(
+                            "Чек сохранён в очередь под номером {}, отправьте его командой `queue flush` до {}",
+                            item.id,
+                            item.deadline().format("%d.%m.%Y %H:%M"),
+                        )
+                    })?;
+
+                    None
+                }
+                Err(e) => return Err(e.into()),
+            };
 
             debug!("Синхронизируем состояние с актуальными данными");
             state.access_token = Some(client.get_access_token());
             state.refresh_token = Some(client.get_refresh_token());
             state.taxpayer_identification_number = Some(client.get_inn());
 
+            if !args.forget {
+                let answers = values.into_values().flatten().collect();
+                state.remember(&args.template, answers, &no_history);
+            }
+
             debug!("Сохраняем состояние в {:?}", cfg.state_path);
             state::save(&state, &cfg.state_path)?;
+
+            if let Some(event) = issued {
+                output.print(&event, || println!("Чек доступен но URL: {}", event.url))?;
+
+                // Чек уже выписан, поэтому дальше ошибки только логируем.
+                if !cli.no_clipboard {
+                    match cli_clipboard::set_contents(event.url.clone()) {
+                        Ok(()) if output == Output::Text => {
+                            println!("Так же чек скопирован в буфер обмена")
+                        }
+                        Ok(()) => {}
+                        Err(e) => {
+                            error!("Не удалось скопировать ссылку на чек в буфер обмена: {}", e)
+                        }
+                    }
+                }
+
+                for path in &args.receipt {
+                    match receipt::render(
+                        &event,
+                        &client.get_name(),
+                        path,
+                        cfg.receipt_font.as_deref(),
+                    ) {
+                        Ok(()) => info!("Чек сохранён в {:?}", path),
+                        Err(e) => error!("Не удалось сохранить чек в {:?}: {:#}", path, e),
+                    }
+                }
+
+                hooks::run(&cfg.hooks, &cfg.http, &event, &clock);
+
+                if args.send_email {
+                    mail::notify(
+                        &cfg,
+                        &book,
+                        email.as_deref(),
+                        &client.get_name(),
+                        &event,
+                        &clock,
+                    );
+                }
+            }
+        }
+        Command::Init(args) => {
+            commands::init::run(args, output)?;
+        }
+        Command::Config(args) => {
+            commands::config::run(args, output)?;
+        }
+        Command::Templates(args) => {
+            commands::templates::run(args, output)?;
+        }
+        Command::Clients(args) => {
+            commands::clients::run(args, output)?;
+        }
+        Command::RunDue(args) => {
+            commands::schedule::run_due(args, output)?;
+        }
+        Command::Serve(args) => {
+            commands::serve::run(args, output)?;
+        }
+        Command::Sync(args) => {
+            commands::ledger::sync(args, output)?;
+        }
+        Command::Report(args) => {
+            commands::ledger::report(args, output)?;
+        }
+        Command::Queue(args) => {
+            commands::queue::run(args, output)?;
+        }
+        Command::Batch(args) => {
+            commands::batch::run(args, output)?;
+        }
+        Command::ImportStatement(args) => {
+            commands::statement::import(args, output)?;
+        }
+        Command::Reconcile(args) => {
+            commands::statement::reconcile(args, output)?;
         }
     };
 
     Ok(())
 }
 
-fn get_client(state: &State) -> anyhow::Result<api::AuthorizedClient> {
+fn get_client(state: &State, http: &api::HttpSettings) -> anyhow::Result<api::AuthorizedClient> {
     // У нас есть несколько позитивных сценариев которые мы должны тут обработать:
     // 1. State пустой, аутентификация прошла успешно
     // 2. State не пустой, токен там валиден
@@ -110,7 +352,7 @@ fn get_client(state: &State) -> anyhow::Result<api::AuthorizedClient> {
 
     if access_token.is_none() || refresh_token_expired {
         // Аутентифицируемся снова.
-        let (new_access_token, new_refresh_token) = authenticate(state)?;
+        let (new_access_token, new_refresh_token) = authenticate(state, http)?;
 
         access_token = Some(new_access_token);
         refresh_token = Some(new_refresh_token);
@@ -122,13 +364,17 @@ fn get_client(state: &State) -> anyhow::Result<api::AuthorizedClient> {
         state.device_id.clone(),
         access_token.unwrap(),
         refresh_token.unwrap(),
+        http,
     )
 }
 
-fn authenticate(state: &State) -> anyhow::Result<(AccessToken, RefreshToken)> {
+fn authenticate(
+    state: &State,
+    http: &api::HttpSettings,
+) -> anyhow::Result<(AccessToken, RefreshToken)> {
     let phone = inquire::Text::new("Номер телефона").prompt()?;
 
-    let auth = PhoneAuthenticator::new(phone.clone())?;
+    let auth = PhoneAuthenticator::new(phone.clone(), http)?;
 
     let challenge_token = auth.challenge()?;
 
@@ -1,12 +1,13 @@
-use std::fmt::Display;
+use std::fmt::{Debug, Display};
 
 use crate::newtype;
 use anyhow::anyhow;
-use chrono::{DateTime, FixedOffset, Utc};
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
 /// Чек.
-#[derive(Serialize, Deserialize, Default, Debug)]
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct Check {
     /// Название оказанной услуги.
     pub title: Title,
@@ -19,10 +20,30 @@ pub struct Check {
 
     /// Тот кому мы оказали услугу.
     pub counterparty: Counterparty,
+
+    /// Пересчёт цены из иностранной валюты, если она была указана в валюте.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conversion: Option<Conversion>,
+}
+
+/// Пересчёт суммы из иностранной валюты в рубли по курсу ЦБ.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Conversion {
+    /// Код валюты, например `USD`.
+    pub currency: String,
+
+    /// Сумма в валюте.
+    pub amount: Decimal,
+
+    /// Сколько рублей стоит единица валюты.
+    pub rate: Decimal,
+
+    /// Дата, с которой действует курс.
+    pub rate_date: NaiveDate,
 }
 
 /// Представление всех возможных вариантов заказчиков.
-#[derive(Serialize, Deserialize, Default, Debug)]
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub enum Counterparty {
     /// Физ. лицо.
     #[default]
@@ -82,7 +103,7 @@ pub type RefreshToken = Token;
 /// Токент.
 /// Может быть использован для того чтобы представляет как access, так и refresh
 /// токен.
-#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[derive(Serialize, Deserialize, Default, Clone)]
 pub struct Token {
     /// Значение токена.
     pub(super) value: String,
@@ -114,6 +135,16 @@ impl Token {
     }
 }
 
+/// Значение токена не выводится, чтобы оно не попало в логи.
+impl Debug for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Token")
+            .field("value", &"***")
+            .field("expire_at", &self.expire_at)
+            .finish()
+    }
+}
+
 impl Display for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.value)
@@ -0,0 +1,26 @@
+use serde::Serialize;
+
+/// Формат, в котором команды выводят результат в stdout.
+/// Логи и интерактивный ввод всегда идут в stderr.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Output {
+    /// Текст для человека.
+    #[default]
+    Text,
+
+    /// Один JSON объект на результат команды.
+    Json,
+}
+
+impl Output {
+    /// Печатает результат команды: в текстовом формате вызывает `text`, а в
+    /// JSON формате печатает `value` одной строкой.
+    pub fn print<T: Serialize>(self, value: &T, text: impl FnOnce()) -> anyhow::Result<()> {
+        match self {
+            Self::Text => text(),
+            Self::Json => println!("{}", serde_json::to_string(value)?),
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+use std::{fs, io, path::Path};
+
+use anyhow::anyhow;
+use chrono::{DateTime, Datelike, Days, FixedOffset, Local, NaiveDate, TimeZone};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::{api::RequestError, model::Check};
+
+/// Очередь чеков, которые не удалось выписать из-за недоступности сервиса.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Queue {
+    pub items: Vec<Item>,
+}
+
+/// Чек в очереди.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Item {
+    /// Идентификатор чека в очереди.
+    pub id: String,
+
+    /// Название шаблона, по которому собран чек.
+    pub template: String,
+
+    /// Собранный чек с исходным временем.
+    pub check: Check,
+
+    /// Момент, когда чек был поставлен в очередь.
+    pub queued_at: DateTime<FixedOffset>,
+
+    /// Ошибка, из-за которой чек не удалось выписать.
+    pub error: String,
+}
+
+impl Item {
+    pub fn new(template: &str, check: Check, error: &anyhow::Error) -> Self {
+        Self {
+            id: generate_id(),
+            template: template.to_owned(),
+            check,
+            queued_at: Local::now().fixed_offset(),
+            error: format!("{:#}", error),
+        }
+    }
+
+    /// Возвращает момент, до которого чек нужно выписать: конец 9 числа
+    /// месяца, следующего за месяцем расчёта.
+    pub fn deadline(&self) -> DateTime<FixedOffset> {
+        let date = self.check.date;
+
+        let (year, month) = match date.month() {
+            12 => (date.year() + 1, 1),
+            m => (date.year(), m + 1),
+        };
+
+        // Первое число месяца существует всегда, а смещение фиксированное,
+        // поэтому время однозначно.
+        let day = NaiveDate::from_ymd_opt(year, month, 1).unwrap() + Days::new(9);
+        date.offset()
+            .from_local_datetime(&day.and_hms_opt(0, 0, 0).unwrap())
+            .unwrap()
+    }
+}
+
+impl Queue {
+    /// Удаляет чек с указанным идентификатором из очереди и возвращает его.
+    pub fn remove(&mut self, id: &str) -> anyhow::Result<Item> {
+        let i = self
+            .items
+            .iter()
+            .position(|i| i.id == id)
+            .ok_or(anyhow!("check {} not found in queue", id))?;
+
+        Ok(self.items.remove(i))
+    }
+}
+
+/// Проверяет, что чек не удалось выписать из-за временной недоступности
+/// сервиса, и его стоит отправить позже.
+pub fn is_temporary(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<RequestError>() {
+        Some(RequestError::SendRequest(_)) => true,
+        Some(RequestError::Not200(status, _)) => status.is_server_error(),
+        _ => false,
+    }
+}
+
+/// Добавляет чек в очередь, хранящуюся в указанном файле.
+pub fn push(path: &Path, item: Item) -> anyhow::Result<()> {
+    let mut queue = load(path)?;
+
+    queue.items.push(item);
+
+    save(&queue, path)?;
+
+    Ok(())
+}
+
+fn generate_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(8)
+        .map(|c| char::from(c).to_ascii_lowercase())
+        .collect()
+}
+
+/// Загружает очередь из указанного файла.
+/// Если файла нет, то возвращается пустая очередь.
+pub fn load(path: &Path) -> LoadResult {
+    if !path.exists() {
+        return Ok(Queue::default());
+    }
+
+    let content = fs::read_to_string(path)?;
+
+    let queue: Queue = serde_json::from_str(&content)?;
+
+    Ok(queue)
+}
+
+pub type LoadResult = std::result::Result<Queue, LoadError>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum LoadError {
+    #[error("read queue file")]
+    ReadFile(#[from] io::Error),
+
+    #[error("deserialize")]
+    Deserialize(#[from] serde_json::Error),
+}
+
+/// Сохраняет очередь в указанный файл.
+pub fn save(queue: &Queue, path: &Path) -> SaveResult {
+    let content = serde_json::to_string_pretty(queue)?;
+
+    fs::create_dir_all(path.parent().unwrap_or(Path::new("")))?;
+
+    fs::write(path, content)?;
+
+    Ok(())
+}
+
+pub type SaveResult = std::result::Result<(), SaveError>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SaveError {
+    #[error("write queue file")]
+    WriteFile(#[from] io::Error),
+
+    #[error("serialize")]
+    Serialize(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+
+    use super::*;
+    use crate::model::{Price, Title};
+
+    fn item(date: &str) -> Item {
+        Item::new(
+            "monthly",
+            Check {
+                title: Title::new("Поддержка ПО").unwrap(),
+                price: Price::new(1000),
+                date: DateTime::parse_from_rfc3339(date).unwrap(),
+                counterparty: Default::default(),
+                conversion: None,
+            },
+            &anyhow!("service unavailable"),
+        )
+    }
+
+    #[test]
+    fn deadline() {
+        let cases = [
+            ("2024-01-31T23:30:00+03:00", "2024-02-10T00:00:00+03:00"),
+            ("2024-12-01T10:00:00+05:00", "2025-01-10T00:00:00+05:00"),
+        ];
+
+        for (date, expected) in cases {
+            assert_eq!(
+                item(date).deadline(),
+                DateTime::parse_from_rfc3339(expected).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn temporary_errors() {
+        let error = |e: RequestError| anyhow::Error::from(e);
+
+        assert!(is_temporary(&error(RequestError::Not200(
+            StatusCode::SERVICE_UNAVAILABLE,
+            String::new()
+        ))));
+        assert!(!is_temporary(&error(RequestError::Not200(
+            StatusCode::BAD_REQUEST,
+            String::new()
+        ))));
+        assert!(!is_temporary(&error(RequestError::Unauthorized(
+            String::new()
+        ))));
+        assert!(!is_temporary(&anyhow!("invalid check")));
+    }
+
+    #[test]
+    fn remove() {
+        let mut queue = Queue {
+            items: vec![item("2024-01-31T23:30:00+03:00")],
+        };
+        let id = queue.items[0].id.clone();
+
+        assert!(queue.remove("unknown").is_err());
+        assert_eq!(queue.remove(&id).unwrap().id, id);
+        assert!(queue.items.is_empty());
+    }
+}
@@ -0,0 +1,224 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+
+/// Сколько дней может пройти с даты последнего курса. ЦБ не устанавливает
+/// курсы на выходные и праздники, но более старый курс скорее означает, что
+/// файл за нужный день не скачан.
+const MAX_AGE_DAYS: i64 = 10;
+
+/// Курс валюты к рублю.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate {
+    /// Сколько рублей стоит единица валюты.
+    pub value: Decimal,
+
+    /// Дата, с которой действует курс.
+    pub date: NaiveDate,
+}
+
+/// Источник курсов валют к рублю.
+pub trait Provider: Debug + Send + Sync {
+    /// Возвращает курс валюты, действующий на указанную дату.
+    fn rate(&self, currency: &str, date: NaiveDate) -> anyhow::Result<Rate>;
+}
+
+/// Курсы ЦБ РФ из локальных файлов в формате ежедневных котировок, которые
+/// отдаёт `https://www.cbr.ru/scripts/XML_daily.asp?date_req=05/03/2024`.
+/// Путь может указывать на один файл или на каталог с `*.xml` файлами за
+/// разные дни.
+#[derive(Debug)]
+pub struct Cbr {
+    path: PathBuf,
+}
+
+impl Cbr {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_owned(),
+        }
+    }
+
+    fn files(&self) -> anyhow::Result<Vec<PathBuf>> {
+        if !self.path.is_dir() {
+            return Ok(vec![self.path.clone()]);
+        }
+
+        let mut paths = fs::read_dir(&self.path)
+            .with_context(|| format!("failed to read rates directory {:?}", self.path))?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        paths.retain(|p| p.extension().is_some_and(|e| e == "xml") && p.is_file());
+        paths.sort();
+
+        Ok(paths)
+    }
+}
+
+impl Provider for Cbr {
+    fn rate(&self, currency: &str, date: NaiveDate) -> anyhow::Result<Rate> {
+        let mut found: Option<Rate> = None;
+
+        // На дату действует последний курс, установленный не позже неё.
+        for path in self.files()? {
+            let content =
+                fs::read(&path).with_context(|| format!("failed to read rates file {:?}", path))?;
+
+            // Файлы в windows-1251, но нам нужны только латиница и цифры.
+            let daily = parse_daily(&String::from_utf8_lossy(&content))
+                .with_context(|| format!("failed to parse rates file {:?}", path))?;
+
+            if daily.date > date || found.is_some_and(|r| r.date >= daily.date) {
+                continue;
+            }
+
+            if let Some(value) = daily.rates.get(currency) {
+                found = Some(Rate {
+                    value: *value,
+                    date: daily.date,
+                });
+            }
+        }
+
+        match found {
+            Some(rate) if (date - rate.date).num_days() <= MAX_AGE_DAYS => Ok(rate),
+            _ => Err(anyhow!(
+                "no {} rate on {} in {:?}, download it from https://www.cbr.ru/scripts/XML_daily.asp?date_req={}",
+                currency,
+                date,
+                self.path,
+                date.format("%d/%m/%Y")
+            )),
+        }
+    }
+}
+
+/// Курсы за один день.
+#[derive(Debug, PartialEq)]
+struct Daily {
+    date: NaiveDate,
+
+    /// Код валюты -> рублей за единицу валюты.
+    rates: HashMap<String, Decimal>,
+}
+
+fn parse_daily(content: &str) -> anyhow::Result<Daily> {
+    let date = attribute(content, "ValCurs", "Date").ok_or(anyhow!("no ValCurs date"))?;
+    let date = NaiveDate::parse_from_str(date, "%d.%m.%Y")
+        .map_err(|_| anyhow!("invalid date {:?}", date))?;
+
+    let mut rates = HashMap::new();
+
+    for valute in content.split("<Valute").skip(1) {
+        let code = element(valute, "CharCode").ok_or(anyhow!("no CharCode"))?;
+        let number = |name: &str| {
+            element(valute, name)
+                .ok_or(anyhow!("no {} for {}", name, code))
+                .and_then(|v| {
+                    v.replace(',', ".")
+                        .parse::<Decimal>()
+                        .map_err(|_| anyhow!("invalid {} for {}: {:?}", name, code, v))
+                })
+        };
+
+        let rate = number("Value")?
+            .checked_div(number("Nominal")?)
+            .ok_or(anyhow!("invalid nominal for {}", code))?;
+
+        rates.insert(code.to_owned(), rate);
+    }
+
+    Ok(Daily { date, rates })
+}
+
+/// Возвращает содержимое первого элемента с указанным именем.
+fn element<'a>(content: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{}>", name);
+    let start = content.find(&open)? + open.len();
+    let end = start + content[start..].find(&format!("</{}>", name))?;
+
+    Some(content[start..end].trim())
+}
+
+/// Возвращает значение атрибута первого элемента с указанным именем.
+fn attribute<'a>(content: &'a str, element: &str, name: &str) -> Option<&'a str> {
+    let start = content.find(&format!("<{}", element))?;
+    let tag = &content[start..start + content[start..].find('>')?];
+
+    let prefix = format!("{}=\"", name);
+    let value_start = tag.find(&prefix)? + prefix.len();
+    let value_end = value_start + tag[value_start..].find('"')?;
+
+    Some(&tag[value_start..value_end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    fn daily(date: &str, usd: &str) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="windows-1251"?>
+<ValCurs Date="{}" name="Foreign Currency Market">
+<Valute ID="R01235"><NumCode>840</NumCode><CharCode>USD</CharCode><Nominal>1</Nominal><Name>Доллар США</Name><Value>{}</Value><VunitRate>{}</VunitRate></Valute>
+<Valute ID="R01820"><NumCode>392</NumCode><CharCode>JPY</CharCode><Nominal>100</Nominal><Name>Японских иен</Name><Value>60,8534</Value><VunitRate>0,608534</VunitRate></Valute>
+</ValCurs>"#,
+            date, usd, usd
+        )
+    }
+
+    #[test]
+    fn parse_cbr_daily() {
+        let daily = parse_daily(&daily("05.03.2024", "91,3336")).unwrap();
+
+        assert_eq!(daily.date, date("2024-03-05"));
+        assert_eq!(daily.rates["USD"], Decimal::new(913336, 4));
+        assert_eq!(daily.rates["JPY"], Decimal::new(608534, 6));
+
+        assert!(parse_daily("<ValCurs></ValCurs>").is_err());
+    }
+
+    #[test]
+    fn cbr_rate_on_date() {
+        let dir = std::env::temp_dir().join(format!("lknpd-rates-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        fs::write(dir.join("2024-03-01.xml"), daily("01.03.2024", "90,0000")).unwrap();
+        fs::write(dir.join("2024-03-02.xml"), daily("02.03.2024", "91,3336")).unwrap();
+        fs::write(dir.join("2024-03-06.xml"), daily("06.03.2024", "90,5000")).unwrap();
+
+        let cbr = Cbr::new(&dir);
+
+        // На воскресенье действует курс, установленный в субботу.
+        assert_eq!(
+            cbr.rate("USD", date("2024-03-03")).unwrap(),
+            Rate {
+                value: Decimal::new(913336, 4),
+                date: date("2024-03-02"),
+            }
+        );
+        assert_eq!(
+            cbr.rate("USD", date("2024-03-06")).unwrap().value,
+            Decimal::new(905, 1)
+        );
+
+        assert!(cbr.rate("EUR", date("2024-03-06")).is_err());
+        assert!(cbr.rate("USD", date("2024-02-29")).is_err());
+        assert!(cbr.rate("USD", date("2024-04-01")).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,400 @@
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+use printpdf::{Color, Mm, PdfDocument, PdfLayerReference, Rect, Rgb};
+use qrcode::{render::svg, QrCode};
+
+use crate::{hooks::Event, model::Counterparty};
+
+/// Шрифты с кириллицей, которые обычно есть в системе. Используются для PDF,
+/// если шрифт не указан в конфиге.
+const SYSTEM_FONTS: &[&str] = &[
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/System/Library/Fonts/Supplemental/Arial.ttf",
+    "/Library/Fonts/Arial.ttf",
+    "C:\\Windows\\Fonts\\arial.ttf",
+];
+
+/// Ширина чека в PDF, как у чековой ленты.
+const PDF_WIDTH: f32 = 80.0;
+
+/// Сколько символов помещается в строку чека в PDF.
+const PDF_LINE_CHARS: usize = 38;
+
+/// Чек в виде, в котором он печатается: строки с подписями и значениями.
+struct Document {
+    title: String,
+    lines: Vec<(String, String)>,
+    url: String,
+}
+
+impl Document {
+    fn new(event: &Event, seller: &str) -> Self {
+        let check = event.check.clone();
+
+        let title: String = check.title.into();
+        let price: u32 = check.price.into();
+        let price = format_price(price);
+
+        let mut lines = vec![
+            (
+                "Дата".to_owned(),
+                check.date.format("%d.%m.%Y %H:%M (%:z)").to_string(),
+            ),
+            ("Продавец".to_owned(), seller.to_owned()),
+            ("ИНН продавца".to_owned(), event.inn.clone()),
+            ("Режим НО".to_owned(), "НПД".to_owned()),
+            ("Наименование".to_owned(), format!("1. {}", title)),
+            ("Сумма".to_owned(), price.clone()),
+        ];
+
+        if let Some(c) = &check.conversion {
+            lines.push((
+                "В валюте".to_owned(),
+                format!(
+                    "{} {} по курсу ЦБ {} от {}",
+                    c.amount,
+                    c.currency,
+                    c.rate,
+                    c.rate_date.format("%d.%m.%Y")
+                ),
+            ));
+        }
+
+        lines.push(("Итого".to_owned(), price));
+        lines.push((
+            "Налог".to_owned(),
+            "Налог на профессиональный доход".to_owned(),
+        ));
+
+        match check.counterparty {
+            Counterparty::Person => {
+                lines.push(("Покупатель".to_owned(), "Физическое лицо".to_owned()))
+            }
+            Counterparty::Organization { name, inn } => {
+                lines.push(("Покупатель".to_owned(), name.into()));
+                lines.push(("ИНН покупателя".to_owned(), inn.into()));
+            }
+        }
+
+        Self {
+            title: format!("Чек №{}", event.uuid),
+            lines,
+            url: event.url.clone(),
+        }
+    }
+}
+
+/// Формат файла с чеком.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Html,
+    Pdf,
+}
+
+impl Format {
+    /// Определяет формат по расширению файла.
+    pub fn from_path(path: &Path) -> anyhow::Result<Self> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("html" | "htm") => Ok(Self::Html),
+            Some("pdf") => Ok(Self::Pdf),
+            _ => Err(anyhow!(
+                "unknown receipt format {:?}, use .html or .pdf",
+                path
+            )),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Html => "html",
+            Self::Pdf => "pdf",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Html => "text/html; charset=utf-8",
+            Self::Pdf => "application/pdf",
+        }
+    }
+}
+
+/// Рендерит чек в HTML или PDF, в зависимости от расширения файла. В чеке есть
+/// QR код со ссылкой на печатную форму чека в сервисе налоговой.
+pub fn render(event: &Event, seller: &str, path: &Path, font: Option<&Path>) -> anyhow::Result<()> {
+    let content = to_bytes(event, seller, Format::from_path(path)?, font)?;
+
+    fs::write(path, content).with_context(|| format!("failed to write receipt to {:?}", path))
+}
+
+/// Рендерит чек в указанном формате.
+pub fn to_bytes(
+    event: &Event,
+    seller: &str,
+    format: Format,
+    font: Option<&Path>,
+) -> anyhow::Result<Vec<u8>> {
+    match format {
+        Format::Html => Ok(html(event, seller)?.into_bytes()),
+        Format::Pdf => pdf(event, seller, &find_font(font)?),
+    }
+}
+
+/// Рендерит чек в HTML страницу.
+pub fn html(event: &Event, seller: &str) -> anyhow::Result<String> {
+    let doc = Document::new(event, seller);
+
+    let qr = QrCode::new(doc.url.as_bytes())?
+        .render::<svg::Color>()
+        .min_dimensions(200, 200)
+        .build();
+
+    let rows: String = doc
+        .lines
+        .iter()
+        .map(|(label, value)| {
+            format!(
+                "      <tr><th>{}</th><td>{}</td></tr>\n",
+                escape(label),
+                escape(value)
+            )
+        })
+        .collect();
+
+    Ok(format!(
+        r#"<!DOCTYPE html>
+<html lang="ru">
+<head>
+  <meta charset="utf-8">
+  <title>{title}</title>
+  <style>
+    body {{ font-family: sans-serif; max-width: 420px; margin: 2em auto; }}
+    th {{ text-align: left; font-weight: normal; color: #666; padding-right: 1em; vertical-align: top; }}
+    .qr {{ text-align: center; margin-top: 1em; }}
+    .url {{ font-size: 0.8em; word-break: break-all; }}
+  </style>
+</head>
+<body>
+  <h1>{title}</h1>
+  <table>
+{rows}  </table>
+  <div class="qr">
+    {qr}
+    <p class="url"><a href="{url}">{url}</a></p>
+  </div>
+</body>
+</html>
+"#,
+        title = escape(&doc.title),
+        rows = rows,
+        qr = qr,
+        url = escape(&doc.url),
+    ))
+}
+
+/// Рендерит чек в PDF шириной с чековую ленту. Для кириллицы нужен TrueType
+/// шрифт, который встраивается в документ.
+pub fn pdf(event: &Event, seller: &str, font: &Path) -> anyhow::Result<Vec<u8>> {
+    let doc = Document::new(event, seller);
+
+    // Раскладываем строки заранее, чтобы знать высоту страницы.
+    let mut text: Vec<(String, f32)> = vec![(doc.title.clone(), 11.0)];
+    for (label, value) in &doc.lines {
+        text.push((format!("{}:", label), 7.0));
+        for line in wrap(value, PDF_LINE_CHARS) {
+            text.push((line, 9.0));
+        }
+    }
+
+    let qr = QrCode::new(doc.url.as_bytes())?;
+    let qr_size = 40.0;
+    let line_height = 4.5;
+    let margin = 6.0;
+
+    let height = margin * 3.0 + text.len() as f32 * line_height + qr_size;
+
+    let (pdf, page, layer) = PdfDocument::new(&doc.title, Mm(PDF_WIDTH), Mm(height), "receipt");
+    let font_ref = pdf
+        .add_external_font(BufReader::new(
+            File::open(font).with_context(|| format!("failed to open font {:?}", font))?,
+        ))
+        .map_err(|e| anyhow!("failed to load font {:?}: {}", font, e))?;
+    let layer = pdf.get_page(page).get_layer(layer);
+
+    let mut y = height - margin;
+    for (line, size) in &text {
+        y -= line_height;
+        layer.use_text(line.clone(), *size, Mm(margin), Mm(y), &font_ref);
+    }
+
+    draw_qr(&layer, &qr, (PDF_WIDTH - qr_size) / 2.0, margin, qr_size);
+
+    pdf.save_to_bytes()
+        .map_err(|e| anyhow!("failed to render pdf: {}", e))
+}
+
+/// Рисует QR код квадратами в указанном месте страницы.
+fn draw_qr(layer: &PdfLayerReference, qr: &QrCode, x: f32, y: f32, size: f32) {
+    let width = qr.width();
+    let module = size / width as f32;
+
+    layer.set_fill_color(Color::Rgb(Rgb::new(0.0, 0.0, 0.0, None)));
+
+    for (i, color) in qr.to_colors().iter().enumerate() {
+        if *color != qrcode::Color::Dark {
+            continue;
+        }
+
+        // Строки QR кода идут сверху вниз, а координаты PDF снизу вверх.
+        let (col, row) = ((i % width) as f32, (i / width) as f32);
+        let left = x + col * module;
+        let bottom = y + size - (row + 1.0) * module;
+
+        layer.add_rect(Rect::new(
+            Mm(left),
+            Mm(bottom),
+            Mm(left + module),
+            Mm(bottom + module),
+        ));
+    }
+}
+
+/// Возвращает шрифт для PDF: указанный в конфиге или первый найденный
+/// системный.
+fn find_font(font: Option<&Path>) -> anyhow::Result<PathBuf> {
+    if let Some(font) = font {
+        return Ok(font.to_owned());
+    }
+
+    SYSTEM_FONTS
+        .iter()
+        .map(PathBuf::from)
+        .find(|p| p.is_file())
+        .ok_or(anyhow!(
+            "no TrueType font with cyrillic found, set receipt_font in config"
+        ))
+}
+
+/// Разбивает текст на строки не длиннее указанного числа символов по пробелам.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut current = String::new();
+
+    for word in text.split_whitespace() {
+        let chars: Vec<char> = word.chars().collect();
+
+        // Слишком длинные слова, например ссылки, режем как есть.
+        for part in chars.chunks(width) {
+            let part: String = part.iter().collect();
+            let len = current.chars().count();
+
+            if len > 0 && len + 1 + part.chars().count() > width {
+                lines.push(std::mem::take(&mut current));
+            }
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(&part);
+        }
+    }
+
+    if !current.is_empty() || lines.is_empty() {
+        lines.push(current);
+    }
+
+    lines
+}
+
+/// Форматирует цену с разделителями разрядов: `25 000,00 ₽`.
+fn format_price(price: u32) -> String {
+    let digits = price.to_string();
+    let mut grouped = String::new();
+
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(' ');
+        }
+        grouped.push(c);
+    }
+
+    format!("{},00 ₽", grouped)
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::{
+        api::{HttpSettings, Receipt},
+        model::{Check, OrganizationINN, OrganizationName, Price, Title},
+    };
+
+    fn event() -> Event {
+        Event::new(
+            "support",
+            Check {
+                title: Title::new("Поддержка ПО <март>").unwrap(),
+                price: Price::new(25000),
+                date: DateTime::parse_from_rfc3339("2024-03-31T18:00:00+03:00").unwrap(),
+                counterparty: Counterparty::Organization {
+                    name: OrganizationName::new("ООО \"Ромашка\"").unwrap(),
+                    inn: OrganizationINN::new("1234567890").unwrap(),
+                },
+                conversion: None,
+            },
+            Receipt {
+                uuid: "abc".to_owned(),
+                url: HttpSettings::default().receipt_url("123", "abc"),
+            },
+            "123456789012".to_owned(),
+            &crate::clock::Clock::System,
+        )
+    }
+
+    #[test]
+    fn render_html() {
+        let html = html(&event(), "Иванов Иван Иванович").unwrap();
+
+        assert!(html.contains("<title>Чек №abc</title>"));
+        assert!(
+            html.contains("<tr><th>Наименование</th><td>1. Поддержка ПО &lt;март&gt;</td></tr>")
+        );
+        assert!(html.contains("<tr><th>Итого</th><td>25 000,00 ₽</td></tr>"));
+        assert!(html.contains("<tr><th>Покупатель</th><td>ООО &quot;Ромашка&quot;</td></tr>"));
+        assert!(html.contains("<tr><th>ИНН продавца</th><td>123456789012</td></tr>"));
+        assert!(html.contains("<svg"));
+        assert!(html.contains(&format!(r#"href="{}""#, event().url)));
+    }
+
+    #[test]
+    fn wrap_lines() {
+        assert_eq!(
+            wrap("Поддержка ПО за март", 12),
+            ["Поддержка ПО", "за март"]
+        );
+        assert_eq!(wrap("abcdefgh", 3), ["abc", "def", "gh"]);
+        assert_eq!(wrap("", 3), [""]);
+    }
+
+    #[test]
+    fn price_format() {
+        assert_eq!(format_price(700), "700,00 ₽");
+        assert_eq!(format_price(25000), "25 000,00 ₽");
+        assert_eq!(format_price(1234567), "1 234 567,00 ₽");
+    }
+}
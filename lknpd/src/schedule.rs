@@ -0,0 +1,290 @@
+use std::{collections::HashMap, fmt::Display, str::FromStr};
+
+use anyhow::anyhow;
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+/// Регулярный чек, который выписывается по расписанию командой `run-due`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Schedule {
+    /// Название шаблона.
+    pub template: String,
+
+    /// Правило в формате cron, по которому выписывается чек.
+    pub rule: Rule,
+
+    /// Значения переменных шаблона.
+    #[serde(default)]
+    pub values: HashMap<String, String>,
+}
+
+/// Правило расписания в формате cron: минута, час, день месяца, месяц и день
+/// недели. Поддерживаются `*`, списки через `,`, диапазоны через `-`, шаг
+/// через `/`, а также сокращения `@yearly`, `@monthly`, `@weekly` и `@daily`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    source: String,
+    minutes: Vec<u32>,
+    hours: Vec<u32>,
+    days: Vec<u32>,
+    months: Vec<u32>,
+    weekdays: Vec<u32>,
+
+    /// Ограничены ли дни месяца и дни недели.
+    /// Как и в cron, если ограничены оба, то подходит день, удовлетворяющий
+    /// любому из них.
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl Rule {
+    /// Возвращает все моменты срабатывания правила в промежутке `(after, until]`
+    /// в хронологическом порядке.
+    pub fn occurrences(&self, after: NaiveDateTime, until: NaiveDateTime) -> Vec<NaiveDateTime> {
+        let mut result = Vec::new();
+        let mut date = after.date();
+
+        while date <= until.date() {
+            if self.matches_date(date) {
+                for &hour in &self.hours {
+                    for &minute in &self.minutes {
+                        let Some(at) = date.and_hms_opt(hour, minute, 0) else {
+                            continue;
+                        };
+                        if at > after && at <= until {
+                            result.push(at);
+                        }
+                    }
+                }
+            }
+
+            let Some(next) = date.succ_opt() else {
+                break;
+            };
+            date = next;
+        }
+
+        result
+    }
+
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        if !self.months.contains(&date.month()) {
+            return false;
+        }
+
+        let day = self.days.contains(&date.day());
+        let weekday = self
+            .weekdays
+            .contains(&date.weekday().num_days_from_sunday());
+
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            (true, false) => day,
+            (false, true) => weekday,
+            (false, false) => true,
+        }
+    }
+}
+
+impl FromStr for Rule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expanded = match s.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" => "0 0 * * *",
+            other => other,
+        };
+
+        let parts: Vec<&str> = expanded.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = parts.as_slice() else {
+            return Err(anyhow!(
+                "rule \"{}\" should have 5 fields: minute hour day month weekday",
+                s
+            ));
+        };
+
+        let field = |name: &str, value: &str, min: u32, max: u32| {
+            parse_field(value, min, max).map_err(|e| anyhow!("rule \"{}\": {}: {}", s, name, e))
+        };
+
+        let mut weekdays = field("weekday", weekdays, 0, 7)?;
+        // Воскресенье можно записать и как 0, и как 7.
+        if weekdays.contains(&7) {
+            weekdays.retain(|d| *d != 7);
+            if !weekdays.contains(&0) {
+                weekdays.insert(0, 0);
+            }
+        }
+
+        Ok(Self {
+            source: s.to_owned(),
+            minutes: field("minute", minutes, 0, 59)?,
+            hours: field("hour", hours, 0, 23)?,
+            days: field("day", days, 1, 31)?,
+            months: field("month", months, 1, 12)?,
+            weekdays,
+            days_restricted: !days.starts_with('*'),
+            weekdays_restricted: !parts[4].starts_with('*'),
+        })
+    }
+}
+
+/// Разбирает одно поле правила и возвращает отсортированный список значений.
+fn parse_field(value: &str, min: u32, max: u32) -> anyhow::Result<Vec<u32>> {
+    let number = |s: &str| -> anyhow::Result<u32> {
+        let n: u32 = s
+            .parse()
+            .map_err(|_| anyhow!("\"{}\" is not a number", s))?;
+        if n < min || n > max {
+            return Err(anyhow!("{} is out of range {}-{}", n, min, max));
+        }
+        Ok(n)
+    };
+
+    let mut values = Vec::new();
+
+    for part in value.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse()
+                    .map_err(|_| anyhow!("invalid step \"{}\"", step))?,
+            ),
+            None => (part, 1),
+        };
+
+        if step == 0 {
+            return Err(anyhow!("step should be greater than zero"));
+        }
+
+        let (from, to) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((from, to)) => (number(from)?, number(to)?),
+                None => {
+                    let n = number(range)?;
+                    // `5/15` означает с 5 и до конца с шагом 15.
+                    (n, if part.contains('/') { max } else { n })
+                }
+            },
+        };
+
+        if from > to {
+            return Err(anyhow!("invalid range {}-{}", from, to));
+        }
+
+        values.extend((from..=to).step_by(step));
+    }
+
+    values.sort_unstable();
+    values.dedup();
+
+    Ok(values)
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl Serialize for Rule {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rule {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn occurrences(rule: &str, after: &str, until: &str) -> Vec<String> {
+        rule.parse::<Rule>()
+            .unwrap()
+            .occurrences(at(after), at(until))
+            .iter()
+            .map(|o| o.format("%Y-%m-%d %H:%M").to_string())
+            .collect()
+    }
+
+    #[test]
+    fn parse_rules() {
+        let rule: Rule = "0 10 1,15 */3 *".parse().unwrap();
+        assert_eq!(rule.minutes, [0]);
+        assert_eq!(rule.hours, [10]);
+        assert_eq!(rule.days, [1, 15]);
+        assert_eq!(rule.months, [1, 4, 7, 10]);
+        assert!(rule.days_restricted);
+        assert!(!rule.weekdays_restricted);
+
+        let rule: Rule = "30 9 * * 1-5,7".parse().unwrap();
+        assert_eq!(rule.weekdays, [0, 1, 2, 3, 4, 5]);
+
+        let rule: Rule = "@monthly".parse().unwrap();
+        assert_eq!(rule.days, [1]);
+        assert_eq!(rule.to_string(), "@monthly");
+
+        for (rule, error) in [
+            ("0 10 1 *", "should have 5 fields"),
+            ("60 10 1 * *", "minute: 60 is out of range 0-59"),
+            ("0 10 0 * *", "day: 0 is out of range 1-31"),
+            ("0 10 5-1 * *", "day: invalid range 5-1"),
+            ("0 10 */0 * *", "day: step should be greater than zero"),
+            ("0 x * * *", "hour: \"x\" is not a number"),
+        ] {
+            let err = rule.parse::<Rule>().unwrap_err().to_string();
+            assert!(err.contains(error), "{}: {}", rule, err);
+        }
+    }
+
+    #[test]
+    fn monthly_occurrences_catch_up() {
+        assert_eq!(
+            occurrences("0 10 1 * *", "2024-01-01 10:00", "2024-04-01 09:59"),
+            ["2024-02-01 10:00", "2024-03-01 10:00"]
+        );
+        assert_eq!(
+            occurrences("0 10 1 * *", "2024-01-01 10:00", "2024-04-01 10:00"),
+            ["2024-02-01 10:00", "2024-03-01 10:00", "2024-04-01 10:00"]
+        );
+        assert!(occurrences("0 10 1 * *", "2024-04-01 10:00", "2024-04-20 10:00").is_empty());
+    }
+
+    #[test]
+    fn days_and_weekdays() {
+        // 31 число есть не в каждом месяце.
+        assert_eq!(
+            occurrences("0 0 31 * *", "2024-01-01 00:00", "2024-06-01 00:00"),
+            ["2024-01-31 00:00", "2024-03-31 00:00", "2024-05-31 00:00"]
+        );
+
+        // Каждый понедельник и 1 число.
+        assert_eq!(
+            occurrences("0 12 1 * 1", "2024-03-25 13:00", "2024-04-09 00:00"),
+            ["2024-04-01 12:00", "2024-04-08 12:00"]
+        );
+        assert_eq!(
+            occurrences("0 12 1 * 1", "2024-04-20 00:00", "2024-05-07 00:00"),
+            [
+                "2024-04-22 12:00",
+                "2024-04-29 12:00",
+                "2024-05-01 12:00",
+                "2024-05-06 12:00"
+            ]
+        );
+    }
+}
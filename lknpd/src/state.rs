@@ -1,4 +1,6 @@
-use std::{fs, io, path::Path};
+use std::{collections::HashMap, fs, io, path::Path};
+
+use chrono::{DateTime, FixedOffset};
 
 use rand::{distributions::Alphanumeric, Rng};
 use serde::{Deserialize, Serialize};
@@ -20,6 +22,16 @@ pub struct State {
 
     /// ИНН самозанятого.
     pub taxpayer_identification_number: Option<String>,
+
+    /// Последние введённые значения переменных: название шаблона -> переменная -> значение.
+    /// Предлагаются по-умолчанию при следующем использовании шаблона.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub history: HashMap<String, HashMap<String, String>>,
+
+    /// Момент срабатывания расписания, за который был выписан последний чек:
+    /// название расписания -> момент.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub schedule: HashMap<String, DateTime<FixedOffset>>,
 }
 
 impl Default for State {
@@ -31,8 +43,35 @@ impl Default for State {
             refresh_token: None,
             access_token: None,
             taxpayer_identification_number: None,
+            history: HashMap::new(),
+            schedule: HashMap::new(),
+        }
+    }
+}
+
+impl State {
+    /// Возвращает последние введённые значения переменных для шаблона.
+    pub fn last_values(&self, template: &str) -> HashMap<String, String> {
+        self.history.get(template).cloned().unwrap_or_default()
+    }
+
+    /// Запоминает введённые значения переменных для шаблона.
+    /// Значения переменных из `except` не запоминаются, а ранее запомненные удаляются.
+    pub fn remember(&mut self, template: &str, values: HashMap<String, String>, except: &[String]) {
+        let history = self.history.entry(template.to_owned()).or_default();
+
+        history.extend(values);
+        history.retain(|name, _| !except.contains(name));
+
+        if history.is_empty() {
+            self.history.remove(template);
         }
     }
+
+    /// Забывает все запомненные значения переменных для шаблона.
+    pub fn forget(&mut self, template: &str) {
+        self.history.remove(template);
+    }
 }
 
 fn generate_device_id() -> String {
@@ -90,3 +129,50 @@ pub enum SaveError {
     #[error("serialize")]
     Serialize(#[from] serde_json::Error),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_state_without_history() {
+        let state: State = serde_json::from_str(
+            r#"{"device_id":"abc","access_token":null,"refresh_token":null,"taxpayer_identification_number":null}"#,
+        )
+        .unwrap();
+
+        assert!(state.history.is_empty());
+        assert!(state.last_values("monthly").is_empty());
+    }
+
+    #[test]
+    fn remember_and_forget() {
+        let mut state = State::default();
+
+        state.remember(
+            "monthly",
+            HashMap::from([
+                ("hours".to_owned(), "10".to_owned()),
+                ("invoice".to_owned(), "42".to_owned()),
+            ]),
+            &["invoice".to_owned()],
+        );
+        state.remember(
+            "monthly",
+            HashMap::from([("extra".to_owned(), "500".to_owned())]),
+            &[],
+        );
+
+        assert_eq!(
+            state.last_values("monthly"),
+            HashMap::from([
+                ("hours".to_owned(), "10".to_owned()),
+                ("extra".to_owned(), "500".to_owned()),
+            ])
+        );
+        assert!(state.last_values("other").is_empty());
+
+        state.forget("monthly");
+        assert!(state.history.is_empty());
+    }
+}
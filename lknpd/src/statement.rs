@@ -0,0 +1,624 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::{anyhow, Context};
+use chrono::NaiveDate;
+use encoding_rs::WINDOWS_1251;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::ledger::Entry;
+
+/// Сколько дней по-умолчанию может пройти между платежом и чеком за него.
+pub const MATCH_DAYS: u64 = 7;
+
+/// Допуск по сумме по-умолчанию. Цена в чеке округляется до целых рублей,
+/// поэтому платёж с копейками отличается от суммы своего чека до полурубля.
+pub const MATCH_AMOUNT: Decimal = Decimal::from_parts(5, 0, 0, false, 1);
+
+/// Платёж из банковской выписки.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Payment {
+    /// Номер платёжного документа.
+    pub number: String,
+
+    /// Дата платежа.
+    pub date: NaiveDate,
+
+    /// Сумма платежа.
+    pub amount: Decimal,
+
+    /// Название плательщика.
+    pub payer: String,
+
+    /// ИНН плательщика, если указан.
+    pub payer_inn: Option<String>,
+
+    /// Назначение платежа.
+    pub purpose: String,
+
+    /// Поступление на наш счёт.
+    pub incoming: bool,
+}
+
+/// Загружает платежи из файла выписки в формате 1С (`1CClientBankExchange`) или
+/// CSV. Формат определяется по содержимому файла. ИНН самозанятого нужен, чтобы
+/// отличить поступления от списаний в выписке 1С.
+pub fn load(path: &Path, own_inn: Option<&str>) -> anyhow::Result<Vec<Payment>> {
+    let content = decode(&fs::read(path)?);
+
+    let payments = if content.trim_start().starts_with("1CClientBankExchange") {
+        parse_1c(&content, own_inn)
+    } else {
+        parse_csv(&content)
+    };
+
+    payments.with_context(|| format!("failed to parse statement {:?}", path))
+}
+
+/// Выписки из банков часто в кодировке Windows-1251, а не в UTF-8.
+fn decode(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(s) => s.trim_start_matches('\u{feff}').to_owned(),
+        Err(_) => WINDOWS_1251.decode(bytes).0.into_owned(),
+    }
+}
+
+/// Разбирает выписку в формате обмена 1С с клиентом банка.
+fn parse_1c(content: &str, own_inn: Option<&str>) -> anyhow::Result<Vec<Payment>> {
+    let mut accounts = Vec::new();
+    let mut document: Option<HashMap<&str, &str>> = None;
+    let mut payments = Vec::new();
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        let (key, value) = line.split_once('=').unwrap_or((line, ""));
+
+        match document.as_mut() {
+            None if key == "СекцияДокумент" => document = Some(HashMap::new()),
+            None if key == "РасчСчет" => accounts.push(value),
+            None => {}
+            Some(fields) if key == "КонецДокумента" => {
+                let payment = payment_from_1c(fields, &accounts, own_inn)
+                    .with_context(|| format!("document ending at line {}", i + 1))?;
+                payments.push(payment);
+                document = None;
+            }
+            Some(fields) => {
+                fields.insert(key, value);
+            }
+        }
+    }
+
+    if document.is_some() {
+        return Err(anyhow!("unterminated document section"));
+    }
+
+    Ok(payments)
+}
+
+fn payment_from_1c(
+    fields: &HashMap<&str, &str>,
+    accounts: &[&str],
+    own_inn: Option<&str>,
+) -> anyhow::Result<Payment> {
+    let get = |key: &str| fields.get(key).copied().unwrap_or_default().trim();
+    let non_empty = |key: &str| Some(get(key)).filter(|v| !v.is_empty());
+
+    // Выписка может содержать и поступления, и списания. Поступления
+    // определяем по получателю, а если его реквизиты не совпали, то по дате
+    // поступления, которая есть только у входящих платежей.
+    let incoming = match (non_empty("ПолучательИНН"), own_inn) {
+        (Some(inn), Some(own)) if inn == own => true,
+        _ if accounts.contains(&get("ПолучательСчет")) => true,
+        _ if accounts.contains(&get("ПлательщикСчет")) => false,
+        _ => non_empty("ДатаПоступило").is_some(),
+    };
+
+    let date = non_empty("ДатаПоступило")
+        .or(non_empty("Дата"))
+        .ok_or(anyhow!("no date"))?;
+
+    Ok(Payment {
+        number: get("Номер").to_owned(),
+        date: parse_date(date)?,
+        amount: parse_amount(get("Сумма"))?,
+        payer: non_empty("Плательщик1")
+            .or(non_empty("Плательщик"))
+            .unwrap_or_default()
+            .to_owned(),
+        payer_inn: non_empty("ПлательщикИНН").map(str::to_owned),
+        purpose: get("НазначениеПлатежа").to_owned(),
+        incoming,
+    })
+}
+
+/// Разбирает выписку в CSV с заголовком. Колонки определяются по названиям,
+/// поступления отличаются от списаний по знаку суммы.
+fn parse_csv(content: &str) -> anyhow::Result<Vec<Payment>> {
+    let header = content.lines().next().unwrap_or_default();
+    let delimiter = if header.contains(';') { b';' } else { b',' };
+
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(content.as_bytes());
+
+    let headers: Vec<String> = reader
+        .headers()?
+        .iter()
+        .map(|h| h.trim().to_lowercase())
+        .collect();
+
+    let column = |names: &[&str]| headers.iter().position(|h| names.contains(&h.as_str()));
+
+    let date = column(&["date", "дата"]).ok_or(anyhow!("no date column"))?;
+    let amount = column(&["amount", "сумма"]).ok_or(anyhow!("no amount column"))?;
+    let number = column(&["number", "номер"]);
+    let payer = column(&["payer", "плательщик"]);
+    let payer_inn = column(&["payer_inn", "инн плательщика", "инн"]);
+    let purpose = column(&["purpose", "назначение платежа", "назначение"]);
+
+    let mut payments = Vec::new();
+
+    for (i, record) in reader.records().enumerate() {
+        let record = record?;
+        let get = |c: Option<usize>| c.and_then(|c| record.get(c)).unwrap_or_default().trim();
+
+        let row = || format!("row {}", i + 2);
+        let value = parse_amount(get(Some(amount))).with_context(row)?;
+
+        payments.push(Payment {
+            number: get(number).to_owned(),
+            date: parse_date(get(Some(date))).with_context(row)?,
+            amount: value.abs(),
+            payer: get(payer).to_owned(),
+            payer_inn: Some(get(payer_inn))
+                .filter(|v| !v.is_empty())
+                .map(str::to_owned),
+            purpose: get(purpose).to_owned(),
+            incoming: value.is_sign_positive(),
+        });
+    }
+
+    Ok(payments)
+}
+
+fn parse_date(value: &str) -> anyhow::Result<NaiveDate> {
+    NaiveDate::parse_from_str(value, "%d.%m.%Y")
+        .or_else(|_| NaiveDate::parse_from_str(value, "%Y-%m-%d"))
+        .map_err(|_| anyhow!("invalid date {:?}", value))
+}
+
+/// Разбирает сумму, в которой могут быть пробелы между разрядами и запятая
+/// вместо точки.
+fn parse_amount(value: &str) -> anyhow::Result<Decimal> {
+    value
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| if c == ',' { '.' } else { c })
+        .collect::<String>()
+        .parse()
+        .map_err(|_| anyhow!("invalid amount {:?}", value))
+}
+
+/// Допуски при сопоставлении платежей с чеками.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerance {
+    /// Допустимая разница между суммой платежа и суммой чека.
+    pub amount: Decimal,
+
+    /// Сколько дней может пройти между платежом и чеком за него.
+    pub days: u64,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            amount: MATCH_AMOUNT,
+            days: MATCH_DAYS,
+        }
+    }
+}
+
+impl Tolerance {
+    /// Проверяет, что чек выписан в пределах допуска от даты платежа, а ИНН
+    /// заказчика совпадает с ИНН плательщика, когда известны оба.
+    fn close(&self, payment: &Payment, entry: &Entry) -> bool {
+        let same_client = match (&payment.payer_inn, &entry.client_inn) {
+            (Some(payer), Some(client)) => payer == client,
+            _ => true,
+        };
+
+        days_between(payment, entry) <= self.days && same_client
+    }
+}
+
+fn days_between(payment: &Payment, entry: &Entry) -> u64 {
+    (entry.operation_time.date_naive() - payment.date)
+        .num_days()
+        .unsigned_abs()
+}
+
+/// Сопоставляет платежи с чеками из журнала. Чек подходит платежу, если суммы
+/// и даты отличаются не больше, чем на допуск, а ИНН заказчика совпадает с ИНН
+/// плательщика, когда известны оба. Из подходящих выбирается чек с самой
+/// близкой суммой, а затем датой. Аннулированные чеки не учитываются, а каждый
+/// чек сопоставляется только одному платежу.
+///
+/// Возвращает для каждого платежа индекс подходящего чека в `entries`.
+pub fn match_receipts(
+    payments: &[Payment],
+    entries: &[Entry],
+    tolerance: &Tolerance,
+) -> Vec<Option<usize>> {
+    let mut used = vec![false; entries.len()];
+
+    payments
+        .iter()
+        .map(|p| {
+            let found = entries
+                .iter()
+                .enumerate()
+                .filter(|(i, e)| {
+                    !used[*i]
+                        && e.cancelled_at.is_none()
+                        && (e.amount - p.amount).abs() <= tolerance.amount
+                        && tolerance.close(p, e)
+                })
+                .min_by_key(|(_, e)| ((e.amount - p.amount).abs(), days_between(p, e)))
+                .map(|(i, _)| i);
+
+            if let Some(i) = found {
+                used[i] = true;
+            }
+
+            found
+        })
+        .collect()
+}
+
+/// Расхождение между выпиской и чеками.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, derive_more::Display)]
+#[serde(rename_all = "snake_case")]
+pub enum Discrepancy {
+    /// На платёж не выписан чек.
+    #[display(fmt = "платёж без чека")]
+    UnmatchedPayment,
+
+    /// Чек выписан, но платежа за него в выписке нет.
+    #[display(fmt = "чек без платежа")]
+    OrphanReceipt,
+
+    /// Чек выписан тому же заказчику в те же дни, но на другую сумму.
+    #[display(fmt = "не совпадает сумма")]
+    AmountMismatch,
+}
+
+/// Строка отчёта о сверке.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Row {
+    pub discrepancy: Discrepancy,
+    pub date: NaiveDate,
+    pub payment_number: Option<String>,
+    pub payer: Option<String>,
+    pub payer_inn: Option<String>,
+    pub payment_amount: Option<Decimal>,
+    pub receipt_uuid: Option<String>,
+    pub receipt_title: Option<String>,
+    pub receipt_amount: Option<Decimal>,
+}
+
+impl Row {
+    fn new(discrepancy: Discrepancy, payment: Option<&Payment>, entry: Option<&Entry>) -> Self {
+        let date = payment
+            .map(|p| p.date)
+            .or(entry.map(|e| e.operation_time.date_naive()))
+            .unwrap_or_default();
+
+        Self {
+            discrepancy,
+            date,
+            payment_number: payment.map(|p| p.number.clone()),
+            payer: payment.map(|p| p.payer.clone()),
+            payer_inn: payment
+                .and_then(|p| p.payer_inn.clone())
+                .or(entry.and_then(|e| e.client_inn.clone())),
+            payment_amount: payment.map(|p| p.amount),
+            receipt_uuid: entry.map(|e| e.uuid.clone()),
+            receipt_title: entry.map(|e| e.title.clone()),
+            receipt_amount: entry.map(|e| e.amount),
+        }
+    }
+}
+
+/// Результат сверки чеков с поступлениями из выписки.
+#[derive(Serialize, Debug, Default)]
+pub struct Reconciliation {
+    /// Сколько платежей сопоставлено с чеками.
+    pub matched: usize,
+
+    /// Расхождения в порядке дат.
+    pub rows: Vec<Row>,
+}
+
+/// Сверяет чеки с поступлениями. Сначала платежи сопоставляются с чеками
+/// с учётом допуска, затем оставшиеся платежи сопоставляются с оставшимися
+/// чеками того же заказчика в пределах допуска по датам, такие пары считаются
+/// расхождением в сумме. Всё, что осталось, это платежи без чеков и чеки без
+/// платежей.
+pub fn reconcile(payments: &[Payment], entries: &[Entry], tolerance: &Tolerance) -> Reconciliation {
+    let matches = match_receipts(payments, entries, tolerance);
+
+    let mut used: Vec<bool> = entries.iter().map(|e| e.cancelled_at.is_some()).collect();
+    for i in matches.iter().flatten() {
+        used[*i] = true;
+    }
+
+    let mut result = Reconciliation {
+        matched: matches.iter().flatten().count(),
+        rows: Vec::new(),
+    };
+
+    for (p, _) in payments.iter().zip(&matches).filter(|(_, m)| m.is_none()) {
+        let mismatch = entries
+            .iter()
+            .enumerate()
+            .filter(|(i, e)| {
+                !used[*i]
+                    && p.payer_inn.is_some()
+                    && e.client_inn == p.payer_inn
+                    && tolerance.close(p, e)
+            })
+            .min_by_key(|(_, e)| days_between(p, e))
+            .map(|(i, _)| i);
+
+        let row = match mismatch {
+            Some(i) => {
+                used[i] = true;
+                Row::new(Discrepancy::AmountMismatch, Some(p), Some(&entries[i]))
+            }
+            None => Row::new(Discrepancy::UnmatchedPayment, Some(p), None),
+        };
+
+        result.rows.push(row);
+    }
+
+    for (e, _) in entries.iter().zip(&used).filter(|(_, used)| !**used) {
+        result
+            .rows
+            .push(Row::new(Discrepancy::OrphanReceipt, None, Some(e)));
+    }
+
+    result.rows.sort_by_key(|r| r.date);
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::clients::Kind;
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn parse_1c_statement() {
+        let content = "1CClientBankExchange
+ВерсияФормата=1.03
+Кодировка=Windows
+РасчСчет=40802810000000000001
+СекцияДокумент=Платежное поручение
+Номер=15
+Дата=04.03.2024
+Сумма=25000.00
+ПлательщикСчет=40702810000000000002
+Плательщик=ИНН 1234567890 ООО \"Ромашка\"
+ПлательщикИНН=1234567890
+Плательщик1=ООО \"Ромашка\"
+ПолучательСчет=40802810000000000001
+ПолучательИНН=123456789012
+ДатаПоступило=05.03.2024
+НазначениеПлатежа=Оплата по счёту 7 за поддержку ПО
+КонецДокумента
+СекцияДокумент=Платежное поручение
+Номер=16
+Дата=06.03.2024
+Сумма=1 200,50
+ПлательщикСчет=40802810000000000001
+ПлательщикИНН=123456789012
+ПолучательСчет=40702810000000000003
+ПолучательИНН=7700000000
+ДатаСписано=06.03.2024
+НазначениеПлатежа=Оплата хостинга
+КонецДокумента
+КонецФайла
+";
+
+        let payments = parse_1c(content, Some("123456789012")).unwrap();
+
+        assert_eq!(
+            payments,
+            [
+                Payment {
+                    number: "15".to_owned(),
+                    date: date("2024-03-05"),
+                    amount: Decimal::from(25000),
+                    payer: "ООО \"Ромашка\"".to_owned(),
+                    payer_inn: Some("1234567890".to_owned()),
+                    purpose: "Оплата по счёту 7 за поддержку ПО".to_owned(),
+                    incoming: true,
+                },
+                Payment {
+                    number: "16".to_owned(),
+                    date: date("2024-03-06"),
+                    amount: Decimal::new(120050, 2),
+                    payer: String::new(),
+                    payer_inn: Some("123456789012".to_owned()),
+                    purpose: "Оплата хостинга".to_owned(),
+                    incoming: false,
+                },
+            ]
+        );
+
+        assert!(parse_1c(
+            "1CClientBankExchange\nСекцияДокумент=Платежное поручение\n",
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parse_csv_statement() {
+        let content = "Дата;Сумма;Плательщик;ИНН;Назначение
+05.03.2024;25 000,00;ООО \"Ромашка\";1234567890;Оплата по счёту 7
+06.03.2024;-1200,50;ООО Хостинг;7700000000;Оплата хостинга
+";
+
+        let payments = parse_csv(content).unwrap();
+
+        assert_eq!(payments.len(), 2);
+        assert_eq!(payments[0].date, date("2024-03-05"));
+        assert_eq!(payments[0].amount, Decimal::from(25000));
+        assert_eq!(payments[0].payer, "ООО \"Ромашка\"");
+        assert_eq!(payments[0].payer_inn.as_deref(), Some("1234567890"));
+        assert!(payments[0].incoming);
+        assert_eq!(payments[1].amount, Decimal::new(120050, 2));
+        assert!(!payments[1].incoming);
+
+        let err = parse_csv("date,amount\n2024-03-05,много\n").unwrap_err();
+        assert_eq!(format!("{:#}", err), "row 2: invalid amount \"много\"");
+    }
+
+    #[test]
+    fn decode_windows_1251() {
+        let (bytes, _, _) = WINDOWS_1251.encode("Дата=05.03.2024");
+        assert_eq!(decode(&bytes), "Дата=05.03.2024");
+        assert_eq!(decode("Дата".as_bytes()), "Дата");
+    }
+
+    fn payment(d: &str, amount: i64, inn: Option<&str>) -> Payment {
+        Payment {
+            number: String::new(),
+            date: date(d),
+            amount: Decimal::from(amount),
+            payer: String::new(),
+            payer_inn: inn.map(str::to_owned),
+            purpose: String::new(),
+            incoming: true,
+        }
+    }
+
+    fn entry(uuid: &str, time: &str, amount: i64, inn: Option<&str>) -> Entry {
+        Entry {
+            uuid: uuid.to_owned(),
+            title: "Поддержка ПО".to_owned(),
+            amount: Decimal::from(amount),
+            operation_time: DateTime::parse_from_rfc3339(time).unwrap(),
+            kind: Kind::Organization,
+            client_name: None,
+            client_inn: inn.map(str::to_owned),
+            cancelled_at: None,
+            cancel_reason: None,
+        }
+    }
+
+    #[test]
+    fn match_payments_with_receipts() {
+        let mut cancelled = entry("c", "2024-03-05T12:00:00+03:00", 500, None);
+        cancelled.cancelled_at = Some(cancelled.operation_time);
+
+        let entries = [
+            entry("a", "2024-03-06T12:00:00+03:00", 25000, Some("1234567890")),
+            entry("b", "2024-03-05T12:00:00+03:00", 700, None),
+            cancelled,
+        ];
+
+        let payments = [
+            payment("2024-03-05", 25000, Some("1234567890")),
+            // Этот чек уже сопоставлен предыдущему платежу.
+            payment("2024-03-05", 25000, Some("1234567890")),
+            payment("2024-03-05", 700, Some("7700000000")),
+            payment("2024-03-20", 700, None),
+            payment("2024-03-05", 500, None),
+        ];
+
+        assert_eq!(
+            match_receipts(&payments, &entries, &Tolerance::default()),
+            [Some(0), None, Some(1), None, None]
+        );
+
+        // Чек за платёж с копейками выписан на округлённую сумму.
+        let mut fractional = payment("2024-03-05", 700, None);
+        fractional.amount = Decimal::new(70050, 2);
+        let mut other = payment("2024-03-05", 700, None);
+        other.amount = Decimal::new(70150, 2);
+
+        assert_eq!(
+            match_receipts(&[fractional, other], &entries, &Tolerance::default()),
+            [Some(1), None]
+        );
+
+        let tolerance = Tolerance {
+            amount: Decimal::ONE,
+            days: 1,
+        };
+        let payments = [
+            payment("2024-03-07", 25001, None),
+            payment("2024-03-03", 700, None),
+        ];
+
+        assert_eq!(
+            match_receipts(&payments, &entries, &tolerance),
+            [Some(0), None]
+        );
+    }
+
+    #[test]
+    fn reconcile_payments() {
+        let entries = [
+            entry("a", "2024-03-06T12:00:00+03:00", 25000, Some("1234567890")),
+            entry("b", "2024-03-10T12:00:00+03:00", 20000, Some("7700000000")),
+            entry("c", "2024-03-01T12:00:00+03:00", 700, None),
+            entry("d", "2024-03-15T12:00:00+03:00", 1200, None),
+        ];
+
+        // Чек за платёж с копейками выписан на округлённую сумму.
+        let mut fractional = payment("2024-03-15", 1200, None);
+        fractional.amount = Decimal::new(120050, 2);
+
+        let payments = [
+            payment("2024-03-05", 25000, Some("1234567890")),
+            payment("2024-03-11", 24000, Some("7700000000")),
+            payment("2024-03-12", 1500, None),
+            fractional,
+        ];
+
+        let result = reconcile(&payments, &entries, &Tolerance::default());
+
+        assert_eq!(result.matched, 2);
+        assert_eq!(
+            result
+                .rows
+                .iter()
+                .map(|r| (r.discrepancy, r.receipt_uuid.as_deref(), r.payment_amount))
+                .collect::<Vec<_>>(),
+            [
+                (Discrepancy::OrphanReceipt, Some("c"), None),
+                (
+                    Discrepancy::AmountMismatch,
+                    Some("b"),
+                    Some(Decimal::from(24000))
+                ),
+                (
+                    Discrepancy::UnmatchedPayment,
+                    None,
+                    Some(Decimal::from(1500))
+                ),
+            ]
+        );
+    }
+}
@@ -1,9 +1,9 @@
-use chrono::DateTime;
+use chrono::{DateTime, NaiveDate};
 use log::debug;
-use regex::Regex;
-use std::{collections::HashMap, str::FromStr};
+use rust_decimal::{prelude::ToPrimitive, Decimal, RoundingStrategy};
+use std::{collections::HashMap, fmt::Display, sync::Arc};
 
-use crate::{functions, model};
+use crate::{clock::Clock, functions, model, rates};
 use anyhow::anyhow;
 
 use super::raw;
@@ -17,21 +17,227 @@ pub type FieldValues = HashMap<String, String>;
 pub struct Template {
     fields: Fields,
     raw: raw::Template,
+    clock: Clock,
+    rates: Option<Arc<dyn rates::Provider>>,
 }
 
 /// Представление одно поля шаблона.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Field {
-    /// Список всех найденных плейсхолдеров в данном поле.
-    pub placeholders: Vec<Placeholder>,
+    /// Исходный текст поля.
+    pub source: String,
+
+    /// Синтаксическое дерево поля.
+    pub nodes: Vec<Node>,
 }
 
 impl Field {
-    fn new(str: &str) -> anyhow::Result<Self> {
+    /// Разбирает текст поля шаблона.
+    pub fn new(str: &str) -> Result<Self, Error> {
+        let nodes = template::template(str)
+            .map_err(|e| Error::new(str, e.location.offset, format!("expected {}", e.expected)))?;
+
         Ok(Self {
-            placeholders: template::template(str)?,
+            source: str.to_owned(),
+            nodes,
         })
     }
+
+    /// Возвращает список переменных, значения для которых нужно запросить у
+    /// пользователя, без повторов и в порядке их появления в поле.
+    pub fn variables(&self) -> Vec<Variable> {
+        let mut vars = Vec::new();
+        Self::collect_variables(&self.nodes, &mut vars);
+        vars
+    }
+
+    /// Подставляет значения переменных в поле. Функции вычисляются
+    /// относительно переданных часов.
+    pub fn render(&self, values: &FieldValues, clock: &Clock) -> Result<String, Error> {
+        let mut result = String::with_capacity(self.source.len());
+        Self::render_nodes(&self.source, &self.nodes, values, clock, &mut result)?;
+        Ok(result)
+    }
+
+    /// Возвращает переменные с суммами в иностранной валюте и валюты этих сумм.
+    pub fn money(&self) -> Vec<(String, String)> {
+        let mut money = Vec::new();
+        Self::collect_money(&self.nodes, &mut money);
+        money
+    }
+
+    fn collect_money(nodes: &[Node], money: &mut Vec<(String, String)>) {
+        for node in nodes {
+            match node {
+                Node::Text(_) => {}
+                Node::Placeholder {
+                    placeholder: Placeholder::Money { name, currency },
+                    span: _,
+                } => {
+                    let pair = (name.clone(), currency.clone());
+                    if !money.contains(&pair) {
+                        money.push(pair);
+                    }
+                }
+                Node::Placeholder { .. } => {}
+                Node::If {
+                    condition: _,
+                    then,
+                    otherwise,
+                    span: _,
+                } => {
+                    Self::collect_money(then, money);
+                    Self::collect_money(otherwise, money);
+                }
+            }
+        }
+    }
+
+    fn collect_variables(nodes: &[Node], vars: &mut Vec<Variable>) {
+        for node in nodes {
+            match node {
+                Node::Text(_) => {}
+                Node::Placeholder {
+                    placeholder,
+                    span: _,
+                } => {
+                    for var in placeholder.variables() {
+                        Self::add_variable(vars, var);
+                    }
+                }
+                Node::If {
+                    condition,
+                    then,
+                    otherwise,
+                    span: _,
+                } => {
+                    Self::add_variable(
+                        vars,
+                        Variable {
+                            name: condition.clone(),
+                            default: None,
+                            required: false,
+                            kind: VariableKind::Text,
+                        },
+                    );
+                    Self::collect_variables(then, vars);
+                    Self::collect_variables(otherwise, vars);
+                }
+            }
+        }
+    }
+
+    fn add_variable(vars: &mut Vec<Variable>, var: Variable) {
+        match vars.iter_mut().find(|v| v.name == var.name) {
+            Some(v) => {
+                v.required |= var.required;
+                if var.kind != VariableKind::Text {
+                    v.kind = var.kind;
+                }
+                if v.default.is_none() {
+                    v.default = var.default;
+                }
+            }
+            None => vars.push(var),
+        }
+    }
+
+    fn render_nodes(
+        source: &str,
+        nodes: &[Node],
+        values: &FieldValues,
+        clock: &Clock,
+        result: &mut String,
+    ) -> Result<(), Error> {
+        for node in nodes {
+            match node {
+                Node::Text(span) => result.push_str(span.slice(source)),
+                Node::Placeholder { placeholder, span } => {
+                    let value = Self::get_value(placeholder, values, clock)
+                        .map_err(|e| Error::new(source, span.start, format!("{:#}", e)))?;
+                    result.push_str(&value);
+                }
+                Node::If {
+                    condition,
+                    then,
+                    otherwise,
+                    span,
+                } => {
+                    let value = Self::get_variable(condition, values)
+                        .map_err(|e| Error::new(source, span.start, format!("{:#}", e)))?;
+                    let branch = if is_truthy(&value) { then } else { otherwise };
+                    Self::render_nodes(source, branch, values, clock, result)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn get_value(ph: &Placeholder, values: &FieldValues, clock: &Clock) -> anyhow::Result<String> {
+        match ph {
+            Placeholder::Variable {
+                name,
+                default: _,
+                fallbacks,
+            } => {
+                let value = Self::get_variable(name, values)?;
+                if !value.trim().is_empty() {
+                    return Ok(value);
+                }
+
+                for fallback in fallbacks {
+                    let value = match fallback {
+                        Fallback::String(s) => s.clone(),
+                        Fallback::Function(name) => functions::execute(name, clock)?,
+                        Fallback::Variable(name) => Self::get_variable(name, values)?,
+                    };
+                    if !value.trim().is_empty() {
+                        return Ok(value);
+                    }
+                }
+
+                Ok(String::new())
+            }
+            Placeholder::Client { name, property } => {
+                Self::get_variable(&property.key(name), values)
+            }
+            Placeholder::Money { name, currency: _ } => {
+                Self::get_variable(&rubles_key(name), values)
+            }
+            Placeholder::Function { name } => {
+                functions::execute(name, clock).map_err(|e| anyhow!(e))
+            }
+            Placeholder::Expression { expression } => {
+                let value = expression.evaluate(values, clock)?;
+                Ok(value.round_dp(2).normalize().to_string())
+            }
+        }
+    }
+
+    fn get_variable(name: &str, values: &FieldValues) -> anyhow::Result<String> {
+        values
+            .get(name)
+            .ok_or(anyhow!("field {} not found", name))
+            .cloned()
+    }
+}
+
+/// Разбирает цену из поля шаблона. Цена в чеке целая, поэтому дробный
+/// результат, например выражения `{{ hours * 2500 }}`, округляется до рублей
+/// так же, как суммы в валюте.
+fn parse_price(price: &str) -> anyhow::Result<model::Price> {
+    let value = Expression::parse_number(price).map_err(|e| e.context("price"))?;
+    let rubles = value.round_dp_with_strategy(0, RoundingStrategy::MidpointAwayFromZero);
+
+    if rubles != value {
+        debug!("Округлили цену {} до {} руб.", value, rubles);
+    }
+
+    let rubles = rubles
+        .to_u32()
+        .ok_or(anyhow!("price {} is out of range", value))?;
+
+    Ok(model::Price::new(rubles))
 }
 
 /// Все поля шаблона.
@@ -53,35 +259,44 @@ pub enum FieldName {
 
 impl Template {
     /// Создаёт новый инстанс скомпилированного шаблона.
-    pub fn new(raw: raw::Template) -> anyhow::Result<Self> {
+    /// Функции в шаблоне будут вычисляться относительно переданных часов.
+    pub fn new(raw: raw::Template, clock: Clock) -> anyhow::Result<Self> {
         Ok(Self {
             fields: Self::parse(&raw)?,
             raw,
+            clock,
+            rates: None,
         })
     }
 
+    /// Задаёт источник курсов для пересчёта сумм в иностранной валюте.
+    pub fn with_rates(mut self, rates: Arc<dyn rates::Provider>) -> Self {
+        self.rates = Some(rates);
+        self
+    }
+
     fn parse(raw: &raw::Template) -> anyhow::Result<Fields> {
         let mut fields = Fields::with_capacity(4);
 
         debug!("Parse field {}", FieldName::Title);
-        let f = Field::new(&raw.title).map_err(|e| e.context("title"))?;
+        let f = Field::new(&raw.title).map_err(|e| anyhow!(e).context("title"))?;
         fields.insert(FieldName::Title, f);
 
         debug!("Parse field {}", FieldName::Price);
-        let f = Field::new(&raw.price).map_err(|e| e.context("price"))?;
+        let f = Field::new(&raw.price).map_err(|e| anyhow!(e).context("price"))?;
         fields.insert(FieldName::Price, f);
 
         debug!("Parse field {}", FieldName::Date);
-        let f = Field::new(&raw.date).map_err(|e| e.context("date"))?;
+        let f = Field::new(&raw.date).map_err(|e| anyhow!(e).context("date"))?;
         fields.insert(FieldName::Date, f);
 
         if let raw::Counterparty::Organization { name, inn } = &raw.counterparty {
             debug!("Parse field {}", FieldName::CounterpartyOrganizationName);
-            let f = Field::new(name).map_err(|e| e.context("organization name"))?;
+            let f = Field::new(name).map_err(|e| anyhow!(e).context("organization name"))?;
             fields.insert(FieldName::CounterpartyOrganizationName, f);
 
             debug!("Parse field {}", FieldName::CounterpartyOrganizationINN);
-            let f = Field::new(inn).map_err(|e| e.context("organization inn"))?;
+            let f = Field::new(inn).map_err(|e| anyhow!(e).context("organization inn"))?;
             fields.insert(FieldName::CounterpartyOrganizationINN, f);
         }
 
@@ -93,82 +308,292 @@ impl Template {
         &self.fields
     }
 
+    /// Возвращает список переменных из всех полей шаблона, без повторов.
+    pub fn variables(&self) -> Vec<Variable> {
+        let mut vars = Vec::new();
+        for name in enum_iterator::all::<FieldName>() {
+            if let Some(f) = self.fields.get(&name) {
+                for var in f.variables() {
+                    Field::add_variable(&mut vars, var);
+                }
+            }
+        }
+        vars
+    }
+
+    /// Переменные, значения которых не запоминаются: указанные в `no_history`
+    /// и переменные со значением по-умолчанию из функции, например
+    /// `date:now()`, чтобы в следующий раз предлагалось свежее значение.
+    pub fn no_history(&self) -> Vec<String> {
+        let mut names = self.raw.no_history.clone();
+
+        for var in self.variables() {
+            if matches!(var.default, Some(PlaceholderDefault::Function(_)))
+                && !names.contains(&var.name)
+            {
+                names.push(var.name);
+            }
+        }
+
+        names
+    }
+
     /// Собираем чек на основании данных в шаблоне и значений для переменных,
     /// которые задал пользователь.
     pub fn build_check(&self, values: &Values) -> anyhow::Result<model::Check> {
-        let title = self.replace_values(FieldName::Title, &self.raw.title, values)?;
-        let price = self.replace_values(FieldName::Price, &self.raw.price, values)?;
-        let date = self.replace_values(FieldName::Date, &self.raw.date, values)?;
+        // Суммы в валюте пересчитываются по курсу на дату чека, поэтому дату
+        // вычисляем первой.
+        let date = DateTime::parse_from_rfc3339(&self.render(FieldName::Date, values)?)?;
+        let (values, conversion) = self.convert(values, date.date_naive())?;
+        let values = &values;
+
+        let title = self.render(FieldName::Title, values)?;
+        let price = self.render(FieldName::Price, values)?;
         let counterparty = match &self.raw.counterparty {
             raw::Counterparty::Person => model::Counterparty::Person,
-            raw::Counterparty::Organization { name, inn } => model::Counterparty::Organization {
-                name: (self.replace_values(
-                    FieldName::CounterpartyOrganizationName,
-                    name,
-                    values,
-                )?)
-                .try_into()?,
-                inn: (self.replace_values(FieldName::CounterpartyOrganizationINN, inn, values)?)
-                    .try_into()?,
-            },
+            raw::Counterparty::Organization { name: _, inn: _ } => {
+                model::Counterparty::Organization {
+                    name: (self.render(FieldName::CounterpartyOrganizationName, values)?)
+                        .try_into()?,
+                    inn: (self.render(FieldName::CounterpartyOrganizationINN, values)?)
+                        .try_into()?,
+                }
+            }
         };
 
         Ok(model::Check {
             title: title.try_into()?,
-            price: price.parse()?,
-            date: DateTime::parse_from_rfc3339(&date)?,
+            price: parse_price(&price)?,
+            date,
             counterparty,
+            conversion,
         })
     }
 
-    fn replace_values(
+    /// Пересчитывает суммы из плейсхолдеров `money` в рубли по курсу на
+    /// указанную дату. Сумма в рублях округляется до целых, так как цена в
+    /// чеке целая. Возвращает значения переменных, дополненные суммами в
+    /// рублях, и пересчёт, который попадёт в чек: пересчёт цены, если она
+    /// указана в валюте, иначе первый из пересчётов.
+    fn convert(
         &self,
-        name: FieldName,
-        input: &str,
         values: &Values,
-    ) -> anyhow::Result<String> {
-        debug!("Replace values for {}", name);
-        match self.fields.get(&name) {
-            Some(f) => {
-                let mut result = String::from_str(input)?;
-
-                if f.placeholders.is_empty() {
-                    return Ok(result);
-                }
+        date: NaiveDate,
+    ) -> anyhow::Result<(Values, Option<model::Conversion>)> {
+        let mut values = values.clone();
+        let mut conversion = None;
+
+        for name in enum_iterator::all::<FieldName>() {
+            let Some(field) = self.fields.get(&name) else {
+                continue;
+            };
+
+            for (variable, currency) in field.money() {
+                let rates = self
+                    .rates
+                    .as_ref()
+                    .ok_or(anyhow!("no exchange rates to convert {}", currency))
+                    .map_err(|e| e.context(name.to_string()))?;
+
+                let field_values = values.entry(name.clone()).or_default();
+
+                let amount = Field::get_variable(&variable, field_values)
+                    .and_then(|v| Expression::parse_number(&v))
+                    .map_err(|e| e.context(format!("variable {}", variable)))?;
 
-                let values = values.get(&name).ok_or(anyhow!("there are no values"))?;
+                let rate = rates.rate(&currency, date)?;
 
-                for ph in f.placeholders.iter() {
-                    let pattern = Self::create_pattern(ph);
-                    let r = Regex::new(&pattern)?;
-                    let val = Self::get_value(ph, values)?;
-                    result = r.replace_all(&result, &val).to_string();
+                let rubles = amount
+                    .checked_mul(rate.value)
+                    .ok_or(anyhow!("overflow in {} * {}", amount, rate.value))?
+                    .round_dp_with_strategy(0, RoundingStrategy::MidpointAwayFromZero);
+
+                debug!(
+                    "Пересчитали {} {} в {} руб. по курсу {} от {}",
+                    amount, currency, rubles, rate.value, rate.date
+                );
+
+                field_values.insert(rubles_key(&variable), rubles.normalize().to_string());
+
+                if conversion.is_none() || name == FieldName::Price {
+                    conversion = Some(model::Conversion {
+                        currency,
+                        amount,
+                        rate: rate.value,
+                        rate_date: rate.date,
+                    });
                 }
-                Ok(result)
             }
-            None => Err(anyhow!("unhandled field {}", name)),
         }
+
+        Ok((values, conversion))
     }
 
-    fn create_pattern(ph: &Placeholder) -> String {
-        let name = ph.name();
-        format!(r"\{{\{{\s*{}.*?\}}\}}", name)
+    fn render(&self, name: FieldName, values: &Values) -> anyhow::Result<String> {
+        debug!("Render field {}", name);
+        let field = self
+            .fields
+            .get(&name)
+            .ok_or(anyhow!("unhandled field {}", name))?;
+
+        let empty = FieldValues::new();
+        let values = values.get(&name).unwrap_or(&empty);
+
+        field
+            .render(values, &self.clock)
+            .map_err(|e| anyhow!(e).context(name.to_string()))
     }
+}
 
-    fn get_value(ph: &Placeholder, values: &FieldValues) -> anyhow::Result<String> {
-        match ph {
-            Placeholder::Variable { name, default: _ } => values
-                .get(name)
-                .ok_or(anyhow!("field {} not found", name))
-                .cloned(),
-            Placeholder::Function { name } => functions::execute(name).map_err(|e| anyhow!(e)),
+/// Возвращает ключ, под которым сумма в рублях для переменной с суммой в
+/// валюте лежит среди значений переменных поля.
+fn rubles_key(variable: &str) -> String {
+    format!("{}.rub", variable)
+}
+
+/// Проверяет, что значение переменной из условия считается истинным: оно не
+/// пустое и не является явным отрицанием.
+fn is_truthy(value: &str) -> bool {
+    let value = value.trim().to_lowercase();
+    !matches!(value.as_str(), "" | "0" | "false" | "no" | "нет")
+}
+
+/// Узел синтаксического дерева шаблона.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    /// Текст, который попадёт в чек как есть.
+    Text(Span),
+
+    /// Плейсхолдер.
+    Placeholder {
+        placeholder: Placeholder,
+
+        /// Положение плейсхолдера вместе с `{{ }}`.
+        span: Span,
+    },
+
+    /// Условный блок `{{#if var}}…{{else}}…{{/if}}`.
+    /// Если значение переменной истинно, то будет подставлена ветка `then`,
+    /// иначе ветка `otherwise`.
+    If {
+        condition: String,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+
+        /// Положение открывающего тега `{{#if var}}`.
+        span: Span,
+    },
+}
+
+/// Положение фрагмента в исходном тексте поля, в байтах.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// Возвращает фрагмент исходного текста.
+    pub fn slice<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.start..self.end]
+    }
+
+    /// Возвращает номер строки и символа в строке, с которых начинается
+    /// фрагмент. Нумерация начинается с 1.
+    pub fn location(&self, source: &str) -> (usize, usize) {
+        let before = &source[..self.start];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let column = before[line_start..].chars().count() + 1;
+        (line, column)
+    }
+}
+
+/// Ошибка в поле шаблона с указанием места, где она произошла.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("line {line}, column {column}: {message}\n{excerpt}")]
+pub struct Error {
+    /// Номер строки, начиная с 1.
+    pub line: usize,
+
+    /// Номер символа в строке, начиная с 1.
+    pub column: usize,
+
+    /// Описание ошибки.
+    pub message: String,
+
+    /// Строка шаблона с указателем на место ошибки.
+    excerpt: String,
+}
+
+impl Error {
+    fn new(source: &str, offset: usize, message: String) -> Self {
+        let (line, column) = Span::new(offset, offset).location(source);
+
+        let text = source.lines().nth(line - 1).unwrap_or_default();
+        let excerpt = format!("  | {}\n  | {}^", text, " ".repeat(column - 1));
+
+        Self {
+            line,
+            column,
+            message,
+            excerpt,
         }
     }
 }
 
-enum Token {
-    Skip,
-    Placeholder(Placeholder),
+/// Переменная, значение для которой нужно запросить у пользователя.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variable {
+    /// Имя переменной.
+    pub name: String,
+
+    /// Значение по-умолчанию.
+    pub default: Option<PlaceholderDefault>,
+
+    /// Нужно ли обязательно ввести значение.
+    /// Переменные из условий и переменные с запасными значениями можно
+    /// оставить пустыми.
+    pub required: bool,
+
+    /// Тип переменной.
+    pub kind: VariableKind,
+}
+
+/// Тип переменной, от которого зависит как её значение будет запрошено у
+/// пользователя.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VariableKind {
+    /// Произвольный текст.
+    Text,
+
+    /// Заказчик из адресной книги.
+    /// Пользователь выбирает заказчика из списка, а в шаблон подставляются
+    /// его свойства.
+    Client,
+}
+
+/// Свойство заказчика из адресной книги, которое можно подставить в шаблон.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, derive_more::Display)]
+pub enum ClientProperty {
+    #[display(fmt = "name")]
+    Name,
+    #[display(fmt = "inn")]
+    Inn,
+    #[display(fmt = "phone")]
+    Phone,
+}
+
+impl ClientProperty {
+    /// Возвращает ключ, под которым значение свойства лежит среди значений
+    /// переменных поля.
+    pub fn key(&self, variable: &str) -> String {
+        format!("{}.{}", variable, self)
+    }
 }
 
 /// Представление плейсхолдера в шаблоне.
@@ -182,27 +607,275 @@ pub enum Placeholder {
 
         /// Значение по-умолчанию.
         default: Option<PlaceholderDefault>,
+
+        /// Запасные значения, которые будут опробованы по порядку, если
+        /// пользователь оставил переменную пустой.
+        fallbacks: Vec<Fallback>,
+    },
+
+    /// Заказчик из адресной книги: `{{ buyer:client(inn) }}`.
+    /// Заказчик будет выбран пользователем один раз для всех плейсхолдеров
+    /// с этой переменной, а в чек попадёт указанное свойство.
+    Client {
+        name: String,
+        property: ClientProperty,
     },
 
+    /// Сумма в иностранной валюте: `{{ amount:money(USD) }}`.
+    /// Сумма запрашивается у пользователя в валюте, а в чек попадает сумма в
+    /// рублях по курсу ЦБ на дату чека.
+    Money { name: String, currency: String },
+
     /// Функция.
     /// Результат будет вычислен и подставлен при формировании чека.
     Function { name: String },
+
+    /// Арифметическое выражение.
+    /// Значения для входящих в него переменных будут затребованы у пользователя,
+    /// а результат будет вычислен при формировании чека.
+    Expression { expression: Expression },
 }
 
 impl Placeholder {
-    /// Возвращает название имя плейсхолдера.
-    pub fn name(&self) -> String {
+    /// Возвращает список переменных, значения для которых нужно запросить у
+    /// пользователя.
+    pub fn variables(&self) -> Vec<Variable> {
+        match self {
+            Self::Variable {
+                name,
+                default,
+                fallbacks,
+            } => {
+                let mut vars = vec![Variable {
+                    name: name.clone(),
+                    default: default.clone(),
+                    required: fallbacks.is_empty(),
+                    kind: VariableKind::Text,
+                }];
+
+                vars.extend(fallbacks.iter().filter_map(|f| match f {
+                    Fallback::Variable(name) => Some(Variable {
+                        name: name.clone(),
+                        default: None,
+                        required: false,
+                        kind: VariableKind::Text,
+                    }),
+                    _ => None,
+                }));
+
+                vars
+            }
+            Self::Client { name, property: _ } => vec![Variable {
+                name: name.clone(),
+                default: None,
+                required: true,
+                kind: VariableKind::Client,
+            }],
+            Self::Money { name, currency: _ } => vec![Variable {
+                name: name.clone(),
+                default: None,
+                required: true,
+                kind: VariableKind::Text,
+            }],
+            Self::Function { name: _ } => Vec::new(),
+            Self::Expression { expression } => expression
+                .variables()
+                .into_iter()
+                .map(|name| Variable {
+                    name,
+                    default: None,
+                    required: true,
+                    kind: VariableKind::Text,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Display for Placeholder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Variable {
+                name,
+                default,
+                fallbacks,
+            } => {
+                write!(f, "{}", name)?;
+                if let Some(d) = default {
+                    write!(f, ":{}", d)?;
+                }
+                for fallback in fallbacks {
+                    write!(f, " ?? {}", fallback)?;
+                }
+                Ok(())
+            }
+            Self::Client { name, property } => write!(f, "{}:client({})", name, property),
+            Self::Money { name, currency } => write!(f, "{}:money({})", name, currency),
+            Self::Function { name } => write!(f, "{}()", name),
+            Self::Expression { expression } => write!(f, "{}", expression),
+        }
+    }
+}
+
+/// Запасное значение для переменной, которую пользователь оставил пустой.
+/// Записывается через `??`: `{{ name ?? "Физ лицо" }}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fallback {
+    /// Строка.
+    String(String),
+
+    /// Функция.
+    Function(String),
+
+    /// Другая переменная.
+    Variable(String),
+}
+
+impl Display for Fallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Variable { name, default: _ } => name.clone(),
-            Self::Function { name } => name.clone(),
+            Self::String(s) => write!(f, "\"{}\"", s),
+            Self::Function(name) => write!(f, "{}()", name),
+            Self::Variable(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Арифметическое выражение.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expression {
+    /// Число.
+    Number(Decimal),
+
+    /// Переменная.
+    Variable(String),
+
+    /// Функция.
+    Function(String),
+
+    /// Бинарная операция.
+    Binary {
+        operator: Operator,
+        left: Box<Expression>,
+        right: Box<Expression>,
+    },
+}
+
+/// Арифметический оператор.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, derive_more::Display)]
+pub enum Operator {
+    #[display(fmt = "+")]
+    Add,
+    #[display(fmt = "-")]
+    Sub,
+    #[display(fmt = "*")]
+    Mul,
+    #[display(fmt = "/")]
+    Div,
+}
+
+impl Expression {
+    fn binary(operator: Operator, left: Expression, right: Expression) -> Self {
+        Self::Binary {
+            operator,
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
+    /// Возвращает имена всех переменных в выражении, без повторов.
+    pub fn variables(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        self.collect_variables(&mut names);
+        names
+    }
+
+    fn collect_variables(&self, names: &mut Vec<String>) {
+        match self {
+            Self::Variable(name) => {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+            Self::Binary {
+                operator: _,
+                left,
+                right,
+            } => {
+                left.collect_variables(names);
+                right.collect_variables(names);
+            }
+            Self::Number(_) | Self::Function(_) => {}
+        }
+    }
+
+    /// Вычисляет значение выражения.
+    pub fn evaluate(&self, values: &FieldValues, clock: &Clock) -> anyhow::Result<Decimal> {
+        match self {
+            Self::Number(n) => Ok(*n),
+            Self::Variable(name) => {
+                let value = values
+                    .get(name)
+                    .ok_or(anyhow!("field {} not found", name))?;
+                Self::parse_number(value).map_err(|e| e.context(format!("variable {}", name)))
+            }
+            Self::Function(name) => {
+                let value = functions::execute(name, clock)?;
+                Self::parse_number(&value).map_err(|e| e.context(format!("function {}", name)))
+            }
+            Self::Binary {
+                operator,
+                left,
+                right,
+            } => {
+                let l = left.evaluate(values, clock)?;
+                let r = right.evaluate(values, clock)?;
+
+                let res = match operator {
+                    Operator::Add => l.checked_add(r),
+                    Operator::Sub => l.checked_sub(r),
+                    Operator::Mul => l.checked_mul(r),
+                    Operator::Div => {
+                        if r.is_zero() {
+                            return Err(anyhow!("division by zero"));
+                        }
+                        l.checked_div(r)
+                    }
+                };
+
+                res.ok_or(anyhow!("overflow in {} {} {}", l, operator, r))
+            }
         }
     }
 
-    /// Возвращает значение по умолчанию.
-    pub fn default(&self) -> Option<PlaceholderDefault> {
+    fn parse_number(value: &str) -> anyhow::Result<Decimal> {
+        // Пользователи часто вводят дробную часть через запятую.
+        let value = value.trim().replace(',', ".");
+        value
+            .parse()
+            .map_err(|_| anyhow!("\"{}\" is not a number", value))
+    }
+}
+
+impl Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Variable { name: _, default } => default.clone(),
-            Self::Function { name: _ } => None,
+            Self::Number(n) => write!(f, "{}", n),
+            Self::Variable(name) => write!(f, "{}", name),
+            Self::Function(name) => write!(f, "{}()", name),
+            Self::Binary {
+                operator,
+                left,
+                right,
+            } => {
+                // Вложенные операции всегда берём в скобки, чтобы не думать о
+                // приоритетах.
+                let operand = |e: &Expression| match e {
+                    Self::Binary { .. } => format!("({})", e),
+                    _ => e.to_string(),
+                };
+                write!(f, "{} {} {}", operand(left), operator, operand(right))
+            }
         }
     }
 }
@@ -219,74 +892,149 @@ pub enum PlaceholderDefault {
     Function(String),
 }
 
-// todo(dshemin): попробовать переписать парсер и убрать отсюда Token::Skip.
+impl Display for PlaceholderDefault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::String(s) => write!(f, "\"{}\"", s),
+            Self::Function(name) => write!(f, "{}()", name),
+        }
+    }
+}
 
 peg::parser! {
     grammar template() for str {
-        pub rule template() -> Vec<Placeholder>
-            = t:(placeholder() / skip())* {
-                t.iter().filter_map(|x| {
-                    match x {
-                        Token::Placeholder(p) => Some((*p).clone()),
-                        _ => None,
-                    }
-                }).collect()
+        pub rule template() -> Vec<Node>
+            = nodes()
+
+        rule nodes() -> Vec<Node>
+            = node()*
+
+        rule node() -> Node
+            = !(else_tag() / end_if_tag()) n:(if_block() / placeholder() / text()) { n }
+
+        rule if_block() -> Node
+            = s:position!() r#"{{"# space()* "#if" space()+ c:ident() space()* r#"}}"# e:position!()
+              t:nodes()
+              o:(else_tag() o:nodes() { o })?
+              end_if_tag() {
+                Node::If {
+                    condition: c,
+                    then: t,
+                    otherwise: o.unwrap_or_default(),
+                    span: Span::new(s, e),
+                }
             }
 
-        rule placeholder() -> Token
-            = r#"{{"# space()* p:(function() / variable()) space()* r#"}}"# {
-                Token::Placeholder(p)
+        rule else_tag()
+            = r#"{{"# space()* "else" space()* r#"}}"#
+
+        rule end_if_tag()
+            = r#"{{"# space()* "/if" space()* r#"}}"#
+
+        rule placeholder() -> Node
+            = s:position!() r#"{{"# space()* p:(expression() / client() / money() / function() / variable()) space()* r#"}}"# e:position!() {
+                Node::Placeholder { placeholder: p, span: Span::new(s, e) }
+            }
+
+        // Текстом считается всё, что не является началом тега.
+        rule text() -> Node
+            = s:position!() (!r#"{{"# [_])+ e:position!() {
+                Node::Text(Span::new(s, e))
+            }
+
+        rule client() -> Placeholder
+            = v:ident() space()* ":" space()* "client" space()* "(" space()* p:client_property() space()* ")" {
+                Placeholder::Client { name: v, property: p }
             }
 
-        rule skip() -> Token
-            = w:$((letter() / digit() / punctuation() / whitespace())+) {
-                Token::Skip
+        rule client_property() -> ClientProperty
+            = "name" { ClientProperty::Name }
+            / "inn" { ClientProperty::Inn }
+            / "phone" { ClientProperty::Phone }
+
+        rule money() -> Placeholder
+            = v:ident() space()* ":" space()* "money" space()* "(" space()* c:currency() space()* ")" {
+                Placeholder::Money { name: v, currency: c }
             }
 
+        rule currency() -> String
+            = c:$(['A'..='Z'] ['A'..='Z'] ['A'..='Z']) { c.to_owned() }
+
         rule variable() -> Placeholder
-            = v:$(ident()) ":"? d:(variable_default())? {
-                Placeholder::Variable{ name: v.to_owned(), default: d }
+            = v:$(ident()) ":"? d:(variable_default())? f:(fallback()*) {
+                Placeholder::Variable{ name: v.to_owned(), default: d, fallbacks: f }
+            }
+
+        rule fallback() -> Fallback
+            = space()* "??" space()* f:(fallback_string() / fallback_function() / fallback_variable()) {
+                f
             }
 
+        rule fallback_string() -> Fallback
+            = "\"" s:(string()) "\"" { Fallback::String(s) }
+
+        rule fallback_function() -> Fallback
+            = f:ident() function_arguments() { Fallback::Function(f) }
+
+        rule fallback_variable() -> Fallback
+            = v:ident() { Fallback::Variable(v) }
+
         rule variable_default() -> PlaceholderDefault
             = variable_default_string() / variable_default_function()
 
         rule variable_default_string() -> PlaceholderDefault
             =  "\"" s:(string()) "\"" {
-                PlaceholderDefault::String(s.to_owned())
+                PlaceholderDefault::String(s)
             }
 
         rule variable_default_function() -> PlaceholderDefault
-            = f:$(ident() space()*) function_arguments() {
-                PlaceholderDefault::Function(f.to_owned())
+            = f:ident() space()* function_arguments() {
+                PlaceholderDefault::Function(f)
+            }
+
+        rule expression() -> Placeholder
+            = e:arithmetic() {?
+                // Одиночные переменные и функции разбираются своими правилами.
+                match e {
+                    Expression::Binary { .. } => Ok(Placeholder::Expression { expression: e }),
+                    _ => Err("expression"),
+                }
             }
 
+        rule arithmetic() -> Expression = precedence!{
+            l:(@) space()* "+" space()* r:@ { Expression::binary(Operator::Add, l, r) }
+            l:(@) space()* "-" space()* r:@ { Expression::binary(Operator::Sub, l, r) }
+            --
+            l:(@) space()* "*" space()* r:@ { Expression::binary(Operator::Mul, l, r) }
+            l:(@) space()* "/" space()* r:@ { Expression::binary(Operator::Div, l, r) }
+            --
+            n:number() { Expression::Number(n) }
+            f:ident() function_arguments() { Expression::Function(f) }
+            v:ident() { Expression::Variable(v) }
+            "(" space()* e:arithmetic() space()* ")" { e }
+        }
+
+        rule number() -> Decimal
+            = n:$(digit()+ ("." digit()+)?) {? n.parse().or(Err("number")) }
+
         rule function() -> Placeholder
-            = f:$(ident() space()*) function_arguments() {
-                Placeholder::Function{ name: f.to_owned() }
+            = f:ident() space()* function_arguments() {
+                Placeholder::Function{ name: f }
             }
 
         rule ident() -> String
             = i:$(en_letter() (en_letter() / digit())*) {? i.parse().or(Err("ident"))}
 
         rule string() -> String
-            = s:$((letter() / digit() / space())+) {? s.parse().or(Err("string")) }
+            = s:$((!"\"" [_])+) { s.to_owned() }
 
         rule function_arguments() = "()"
 
         rule digit() = ['0'..='9']
 
-        rule letter() = en_letter() / ru_letter()
-
         rule en_letter() = ['a'..='z'] / ['A'..='Z']
 
-        rule ru_letter() = ['а'..='я'] / ['А'..='Я'] / "ё" / "Ё"
-
-        rule whitespace() = space() / "\n" / "\r" / "\t"
-
         rule space() = " "
-
-        rule punctuation() = "," / "." / "?" / "!" / "-" / "'" / "\""
     }
 }
 
@@ -294,55 +1042,585 @@ peg::parser! {
 mod tests {
     use super::*;
 
-    #[test]
-    fn create_pattern_for_variable() {
-        let ph = Placeholder::Variable {
-            name: "foo".to_owned(),
-            default: Some(PlaceholderDefault::Function("bar".to_owned())),
-        };
+    fn span(source: &str, fragment: &str) -> Span {
+        let start = source.find(fragment).expect("fragment not found");
+        Span::new(start, start + fragment.len())
+    }
 
-        let pattern = Template::create_pattern(&ph);
-        assert_eq!(pattern, format!(r"\{{\{{\s*{}.*?\}}\}}", ph.name()))
+    fn placeholders(nodes: Vec<Node>) -> Vec<Placeholder> {
+        nodes
+            .into_iter()
+            .filter_map(|n| match n {
+                Node::Placeholder {
+                    placeholder,
+                    span: _,
+                } => Some(placeholder),
+                _ => None,
+            })
+            .collect()
     }
 
-    #[test]
-    fn create_pattern_for_function() {
-        let ph = Placeholder::Function {
-            name: "foo".to_owned(),
-        };
+    fn variable(name: &str, span: Span) -> Node {
+        Node::Placeholder {
+            placeholder: Placeholder::Variable {
+                name: name.to_owned(),
+                default: None,
+                fallbacks: Vec::new(),
+            },
+            span,
+        }
+    }
+
+    fn render(source: &str, values: &[(&str, &str)]) -> anyhow::Result<String> {
+        let tmpl = Template::new(
+            raw::Template {
+                title: source.to_owned(),
+                price: "1".to_owned(),
+                date: "{{ now() }}".to_owned(),
+                counterparty: raw::Counterparty::Person,
+                no_history: Vec::new(),
+                email: None,
+            },
+            Clock::Fixed(DateTime::parse_from_rfc3339("2024-03-31T18:00:00+03:00").unwrap()),
+        )?;
 
-        let pattern = Template::create_pattern(&ph);
-        assert_eq!(pattern, format!(r"\{{\{{\s*{}.*?\}}\}}", ph.name()))
+        let values = Values::from([(
+            FieldName::Title,
+            values
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        )]);
+
+        tmpl.render(FieldName::Title, &values)
     }
 
     #[test]
     fn template() {
-        assert_eq!(
-            template::template(
-                r#"
+        let src = r#"
             some text with variable {{ var1 }},
             variable with default value {{ var2:"123" }},
             variable with default function {{ var3:foo() }}
             and function {{bar()}} end
-            "#
-            ),
+            "#;
+
+        assert_eq!(
+            template::template(src),
             Ok(vec![
-                Placeholder::Variable {
-                    name: "var1".to_owned(),
-                    default: None
+                Node::Text(span(src, "\n            some text with variable ")),
+                variable("var1", span(src, "{{ var1 }}")),
+                Node::Text(span(src, ",\n            variable with default value ")),
+                Node::Placeholder {
+                    placeholder: Placeholder::Variable {
+                        name: "var2".to_owned(),
+                        default: Some(PlaceholderDefault::String("123".to_owned())),
+                        fallbacks: Vec::new(),
+                    },
+                    span: span(src, r#"{{ var2:"123" }}"#),
+                },
+                Node::Text(span(src, ",\n            variable with default function ")),
+                Node::Placeholder {
+                    placeholder: Placeholder::Variable {
+                        name: "var3".to_owned(),
+                        default: Some(PlaceholderDefault::Function("foo".to_owned())),
+                        fallbacks: Vec::new(),
+                    },
+                    span: span(src, "{{ var3:foo() }}"),
+                },
+                Node::Text(span(src, "\n            and function ")),
+                Node::Placeholder {
+                    placeholder: Placeholder::Function {
+                        name: "bar".to_owned()
+                    },
+                    span: span(src, "{{bar()}}"),
+                },
+                Node::Text(span(src, " end\n            ")),
+            ])
+        );
+    }
+
+    #[test]
+    fn template_with_conditions() {
+        let src = "Услуги{{#if advance}} (аванс){{/if}}";
+        assert_eq!(
+            template::template(src),
+            Ok(vec![
+                Node::Text(span(src, "Услуги")),
+                Node::If {
+                    condition: "advance".to_owned(),
+                    then: vec![Node::Text(span(src, " (аванс)"))],
+                    otherwise: Vec::new(),
+                    span: span(src, "{{#if advance}}"),
                 },
+            ])
+        );
+
+        let src = "{{ #if a }}{{#if b}}{{ b }}{{/if}}{{ else }}нет{{ /if }}";
+        assert_eq!(
+            template::template(src),
+            Ok(vec![Node::If {
+                condition: "a".to_owned(),
+                then: vec![Node::If {
+                    condition: "b".to_owned(),
+                    then: vec![variable("b", span(src, "{{ b }}"))],
+                    otherwise: Vec::new(),
+                    span: span(src, "{{#if b}}"),
+                }],
+                otherwise: vec![Node::Text(span(src, "нет"))],
+                span: span(src, "{{ #if a }}"),
+            }])
+        );
+
+        assert!(template::template("{{#if a}}не закрыт").is_err());
+        assert!(template::template("{{else}}").is_err());
+        assert!(template::template("{{/if}}").is_err());
+    }
+
+    #[test]
+    fn template_with_fallbacks() {
+        assert_eq!(
+            template::template(r#"{{ name ?? other ?? "Физ. лицо" }}{{ date ?? now() }}"#)
+                .map(placeholders),
+            Ok(vec![
                 Placeholder::Variable {
-                    name: "var2".to_owned(),
-                    default: Some(PlaceholderDefault::String("123".to_owned())),
+                    name: "name".to_owned(),
+                    default: None,
+                    fallbacks: vec![
+                        Fallback::Variable("other".to_owned()),
+                        Fallback::String("Физ. лицо".to_owned()),
+                    ],
                 },
                 Placeholder::Variable {
-                    name: "var3".to_owned(),
-                    default: Some(PlaceholderDefault::Function("foo".to_owned())),
+                    name: "date".to_owned(),
+                    default: None,
+                    fallbacks: vec![Fallback::Function("now".to_owned())],
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn field_variables() {
+        let field = Field::new(
+            r#"{{#if advance}}{{ name ?? other }}{{else}}{{ name:"x" }} {{ hours * rate }}{{/if}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            field.variables(),
+            vec![
+                Variable {
+                    name: "advance".to_owned(),
+                    default: None,
+                    required: false,
+                    kind: VariableKind::Text,
+                },
+                Variable {
+                    name: "name".to_owned(),
+                    default: Some(PlaceholderDefault::String("x".to_owned())),
+                    required: true,
+                    kind: VariableKind::Text,
+                },
+                Variable {
+                    name: "other".to_owned(),
+                    default: None,
+                    required: false,
+                    kind: VariableKind::Text,
+                },
+                Variable {
+                    name: "hours".to_owned(),
+                    default: None,
+                    required: true,
+                    kind: VariableKind::Text,
+                },
+                Variable {
+                    name: "rate".to_owned(),
+                    default: None,
+                    required: true,
+                    kind: VariableKind::Text,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn template_with_expressions() {
+        assert_eq!(
+            template::template(r#"{{ hours * 2500 }}, {{ (base + extra) / 2 }}, {{ year() - 1 }}"#)
+                .map(placeholders),
+            Ok(vec![
+                Placeholder::Expression {
+                    expression: Expression::binary(
+                        Operator::Mul,
+                        Expression::Variable("hours".to_owned()),
+                        Expression::Number(Decimal::new(2500, 0)),
+                    ),
+                },
+                Placeholder::Expression {
+                    expression: Expression::binary(
+                        Operator::Div,
+                        Expression::binary(
+                            Operator::Add,
+                            Expression::Variable("base".to_owned()),
+                            Expression::Variable("extra".to_owned()),
+                        ),
+                        Expression::Number(Decimal::new(2, 0)),
+                    ),
+                },
+                Placeholder::Expression {
+                    expression: Expression::binary(
+                        Operator::Sub,
+                        Expression::Function("year".to_owned()),
+                        Expression::Number(Decimal::new(1, 0)),
+                    ),
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn render_by_concatenation() {
+        // Имя одной переменной является префиксом имени другой.
+        assert_eq!(
+            render("{{ a }} и {{ ab }}", &[("a", "1"), ("ab", "2")]).unwrap(),
+            "1 и 2"
+        );
+
+        // Подставленное значение не интерпретируется как шаблон.
+        assert_eq!(
+            render("{{ a }}{{ b }}", &[("a", "{{ b }}"), ("b", "x")]).unwrap(),
+            "{{ b }}x"
+        );
+
+        // В тексте можно использовать любые символы.
+        assert_eq!(
+            render("{{ now() }} — 100% + {} : ; / {{ a }}", &[("a", "ok")]).unwrap(),
+            "2024-03-31T18:00:00+03:00 — 100% + {} : ; / ok"
+        );
+    }
+
+    #[test]
+    fn errors_point_to_position() {
+        let err = Field::new("Услуги\nза {{ hours * }}").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 15));
+        assert_eq!(
+            err.to_string().lines().skip(1).collect::<Vec<_>>(),
+            vec!["  | за {{ hours * }}", "  |               ^"]
+        );
+
+        let err = render("Услуги\n  {{ hours * 2 }}", &[("hours", "abc")]).unwrap_err();
+        let err = err.downcast_ref::<Error>().unwrap();
+        assert_eq!((err.line, err.column), (2, 3));
+        assert_eq!(err.message, "variable hours: \"abc\" is not a number");
+
+        let err = render("{{#if flag}}да{{/if}}", &[]).unwrap_err();
+        let err = err.downcast_ref::<Error>().unwrap();
+        assert_eq!((err.line, err.column), (1, 1));
+        assert_eq!(err.message, "field flag not found");
+    }
+
+    #[test]
+    fn expression_evaluate() {
+        let expression = Expression::binary(
+            Operator::Sub,
+            Expression::binary(
+                Operator::Add,
+                Expression::Variable("a".to_owned()),
+                Expression::binary(
+                    Operator::Mul,
+                    Expression::Variable("b".to_owned()),
+                    Expression::Variable("a".to_owned()),
+                ),
+            ),
+            Expression::Number(Decimal::new(15, 1)),
+        );
+
+        assert_eq!(
+            template::template("{{ a + b * a - 1.5 }}").map(placeholders),
+            Ok(vec![Placeholder::Expression {
+                expression: expression.clone(),
+            }])
+        );
+
+        assert_eq!(expression.variables(), vec!["a".to_owned(), "b".to_owned()]);
+
+        let values = FieldValues::from([
+            ("a".to_owned(), "2".to_owned()),
+            ("b".to_owned(), "0,5".to_owned()),
+        ]);
+        assert_eq!(
+            expression.evaluate(&values, &Clock::System).unwrap(),
+            Decimal::new(15, 1)
+        );
+
+        let values = FieldValues::from([
+            ("a".to_owned(), "abc".to_owned()),
+            ("b".to_owned(), "1".to_owned()),
+        ]);
+        assert!(expression.evaluate(&values, &Clock::System).is_err());
+
+        let division_by_zero = Expression::binary(
+            Operator::Div,
+            Expression::Number(Decimal::ONE),
+            Expression::Number(Decimal::ZERO),
+        );
+        assert!(division_by_zero.evaluate(&values, &Clock::System).is_err());
+    }
+
+    #[test]
+    fn build_check_with_computed_price() {
+        let tmpl = Template::new(
+            raw::Template {
+                title: "Консультация, {{ hours }} ч.".to_owned(),
+                price: "{{ hours * 2500 + extra }}".to_owned(),
+                date: "{{ now() }}".to_owned(),
+                counterparty: raw::Counterparty::Person,
+                no_history: Vec::new(),
+                email: None,
+            },
+            Clock::Fixed(DateTime::parse_from_rfc3339("2024-03-31T18:00:00+03:00").unwrap()),
+        )
+        .unwrap();
+
+        let field_values = FieldValues::from([
+            ("hours".to_owned(), "1.5".to_owned()),
+            ("extra".to_owned(), "100".to_owned()),
+        ]);
+        let values = Values::from([
+            (FieldName::Title, field_values.clone()),
+            (FieldName::Price, field_values),
+            (FieldName::Date, FieldValues::new()),
+        ]);
+
+        let check = tmpl.build_check(&values).unwrap();
+        assert_eq!(
+            check.title,
+            model::Title::new("Консультация, 1.5 ч.").unwrap()
+        );
+        assert_eq!(check.price, model::Price::new(3850));
+        assert_eq!(check.date.to_rfc3339(), "2024-03-31T18:00:00+03:00");
+    }
+
+    #[test]
+    fn build_check_rounds_fractional_price() {
+        let tmpl = Template::new(
+            raw::Template {
+                title: "Консультация".to_owned(),
+                price: "{{ hours * 1001 }}".to_owned(),
+                date: "{{ now() }}".to_owned(),
+                counterparty: raw::Counterparty::Person,
+                no_history: Vec::new(),
+                email: None,
+            },
+            Clock::Fixed(DateTime::parse_from_rfc3339("2024-03-31T18:00:00+03:00").unwrap()),
+        )
+        .unwrap();
+
+        let check = |hours: &str| {
+            tmpl.build_check(&Values::from([
+                (FieldName::Title, FieldValues::new()),
+                (
+                    FieldName::Price,
+                    FieldValues::from([("hours".to_owned(), hours.to_owned())]),
+                ),
+                (FieldName::Date, FieldValues::new()),
+            ]))
+        };
+
+        assert_eq!(check("1.5").unwrap().price, model::Price::new(1502));
+        assert_eq!(check("0.33").unwrap().price, model::Price::new(330));
+        assert_eq!(
+            format!("{:#}", check("-1").unwrap_err()),
+            "price -1001 is out of range"
+        );
+    }
+
+    #[test]
+    fn build_check_with_conditions_and_fallbacks() {
+        let tmpl = Template::new(
+            raw::Template {
+                title: "Услуги{{#if advance}} (аванс){{/if}}".to_owned(),
+                price: "1000".to_owned(),
+                date: "{{ now() }}".to_owned(),
+                counterparty: raw::Counterparty::Organization {
+                    name: r#"{{ name ?? "Ромашка" }}"#.to_owned(),
+                    inn: "1234567890".to_owned(),
                 },
-                Placeholder::Function {
-                    name: "bar".to_owned()
+                no_history: Vec::new(),
+                email: None,
+            },
+            Clock::Fixed(DateTime::parse_from_rfc3339("2024-03-31T18:00:00+03:00").unwrap()),
+        )
+        .unwrap();
+
+        let build = |advance: &str, name: &str| {
+            let values = Values::from([
+                (
+                    FieldName::Title,
+                    FieldValues::from([("advance".to_owned(), advance.to_owned())]),
+                ),
+                (
+                    FieldName::CounterpartyOrganizationName,
+                    FieldValues::from([("name".to_owned(), name.to_owned())]),
+                ),
+            ]);
+            tmpl.build_check(&values).unwrap()
+        };
+
+        let check = build("да", "");
+        assert_eq!(check.title, model::Title::new("Услуги (аванс)").unwrap());
+        assert!(matches!(
+            check.counterparty,
+            model::Counterparty::Organization { name, inn: _ }
+                if name == model::OrganizationName::new("Ромашка").unwrap()
+        ));
+
+        let check = build("нет", "Лютик");
+        assert_eq!(check.title, model::Title::new("Услуги").unwrap());
+        assert!(matches!(
+            check.counterparty,
+            model::Counterparty::Organization { name, inn: _ }
+                if name == model::OrganizationName::new("Лютик").unwrap()
+        ));
+    }
+
+    #[test]
+    fn placeholder_display() {
+        let src = r#"{{ a:"x" ?? b ?? now() }}{{ a + b * (c - 1) }}{{ year() }}"#;
+        assert_eq!(
+            template::template(src)
+                .map(placeholders)
+                .unwrap()
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>(),
+            vec![r#"a:"x" ?? b ?? now()"#, "a + (b * (c - 1))", "year()"]
+        );
+    }
+
+    #[test]
+    fn build_check_with_client() {
+        let tmpl = Template::new(
+            raw::Template {
+                title: "Услуги для {{ buyer }}".to_owned(),
+                price: "1000".to_owned(),
+                date: "{{ now() }}".to_owned(),
+                counterparty: raw::Counterparty::Organization {
+                    name: "{{ buyer:client(name) }}".to_owned(),
+                    inn: "{{buyer : client( inn )}}".to_owned(),
+                },
+                no_history: Vec::new(),
+                email: None,
+            },
+            Clock::Fixed(DateTime::parse_from_rfc3339("2024-03-31T18:00:00+03:00").unwrap()),
+        )
+        .unwrap();
+
+        let vars = tmpl.variables();
+        assert_eq!(vars.len(), 1);
+        assert_eq!(vars[0].name, "buyer");
+        assert_eq!(vars[0].kind, VariableKind::Client);
+
+        let client = FieldValues::from([
+            ("buyer".to_owned(), "Ромашка".to_owned()),
+            (ClientProperty::Name.key("buyer"), "Ромашка".to_owned()),
+            (ClientProperty::Inn.key("buyer"), "1234567890".to_owned()),
+        ]);
+        let values = enum_iterator::all::<FieldName>()
+            .map(|name| (name, client.clone()))
+            .collect();
+
+        let check = tmpl.build_check(&values).unwrap();
+        assert_eq!(
+            check.title,
+            model::Title::new("Услуги для Ромашка").unwrap()
+        );
+        assert!(matches!(
+            check.counterparty,
+            model::Counterparty::Organization { name, inn }
+                if name == model::OrganizationName::new("Ромашка").unwrap()
+                    && inn == model::OrganizationINN::new("1234567890").unwrap()
+        ));
+        assert_eq!(
+            tmpl.get_fields()[&FieldName::CounterpartyOrganizationINN].nodes[0],
+            Node::Placeholder {
+                placeholder: Placeholder::Client {
+                    name: "buyer".to_owned(),
+                    property: ClientProperty::Inn,
+                },
+                span: Span::new(0, 25),
+            }
+        );
+    }
+
+    #[derive(Debug)]
+    struct FixedRates;
+
+    impl rates::Provider for FixedRates {
+        fn rate(&self, currency: &str, date: NaiveDate) -> anyhow::Result<rates::Rate> {
+            match currency {
+                "USD" => Ok(rates::Rate {
+                    value: Decimal::new(913336, 4),
+                    date: date.pred_opt().unwrap(),
+                }),
+                _ => Err(anyhow!("no {} rate", currency)),
+            }
+        }
+    }
+
+    #[test]
+    fn build_check_with_money() {
+        assert_eq!(
+            template::template("{{ amount:money(USD) }}{{ x : money( EUR ) }}").map(placeholders),
+            Ok(vec![
+                Placeholder::Money {
+                    name: "amount".to_owned(),
+                    currency: "USD".to_owned(),
+                },
+                Placeholder::Money {
+                    name: "x".to_owned(),
+                    currency: "EUR".to_owned(),
                 },
             ])
         );
+        assert!(template::template("{{ amount:money(usd) }}").is_err());
+
+        let raw = raw::Template {
+            title: "Разработка ПО, {{ amount }} USD".to_owned(),
+            price: "{{ amount:money(USD) }}".to_owned(),
+            date: "{{ now() }}".to_owned(),
+            counterparty: raw::Counterparty::Person,
+            no_history: Vec::new(),
+            email: None,
+        };
+        let clock =
+            Clock::Fixed(DateTime::parse_from_rfc3339("2024-03-05T18:00:00+03:00").unwrap());
+
+        let amount = FieldValues::from([("amount".to_owned(), "1000,5".to_owned())]);
+        let values = Values::from([
+            (FieldName::Title, amount.clone()),
+            (FieldName::Price, amount),
+        ]);
+
+        let tmpl = Template::new(raw.clone(), clock).unwrap();
+        assert!(tmpl.build_check(&values).is_err());
+
+        let tmpl = tmpl.with_rates(Arc::new(FixedRates));
+        let check = tmpl.build_check(&values).unwrap();
+
+        assert_eq!(check.price, model::Price::new(91379));
+        assert_eq!(
+            check.title,
+            model::Title::new("Разработка ПО, 1000,5 USD").unwrap()
+        );
+        assert_eq!(
+            check.conversion,
+            Some(model::Conversion {
+                currency: "USD".to_owned(),
+                amount: Decimal::new(10005, 1),
+                rate: Decimal::new(913336, 4),
+                rate_date: NaiveDate::from_ymd_opt(2024, 3, 4).unwrap(),
+            })
+        );
     }
 }
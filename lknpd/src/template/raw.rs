@@ -9,6 +9,15 @@ pub struct Template {
     pub price: String,
     pub date: String,
     pub counterparty: Counterparty,
+
+    /// Переменные, значения которых не нужно запоминать и предлагать
+    /// по-умолчанию при следующем использовании шаблона.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub no_history: Vec<String>,
+
+    /// Email заказчика, на который отправляется ссылка на чек.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
 }
 
 /// Представление "сырого" заказчика.
@@ -22,3 +31,51 @@ pub enum Counterparty {
         inn: String,
     },
 }
+
+/// Шаблон в том виде, в котором он описан в конфиге.
+/// Может наследовать незаполненные поля от другого шаблона через `extends` и
+/// ссылаться на заказчика из конфига по названию. Перед компиляцией
+/// превращается в [`Template`].
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct Definition {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counterparty: Option<CounterpartyRef>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_history: Option<Vec<String>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+}
+
+/// Заказчик в шаблоне: либо описан прямо в шаблоне, либо задан названием
+/// заказчика из секции `counterparties` конфига.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum CounterpartyRef {
+    Inline(Counterparty),
+    Named(String),
+}
+
+/// Организация-заказчик, описанная в конфиге один раз и используемая в
+/// нескольких шаблонах.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Organization {
+    pub name: String,
+    pub inn: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+}